@@ -1,48 +1,309 @@
+use std::collections::{HashMap};
 use game_state::{GameState, GameStateMut};
 use partial_state::{PartialState};
-use map::{distance};
+use map::{Terrain, distance};
 use pathfinder::{self, Pathfinder, path_cost, truncate_path};
-use dir::{Dir};
-use unit::{Unit, UnitTypeId};
+use dir::{Dir, dirs};
+use unit::{Unit, UnitTypeId, UnitClass};
 use db::{Db};
 use misc::{get_shuffled_indices};
+use internal_state::{REINFORCEMENT_POINTS_PER_TURN};
 use check::{check_command};
+use hit_chance::{hit_chance, tile_cover_bonus};
+use threat_map::{ThreatMap};
 use ::{
     CoreEvent,
     Command,
     MoveMode,
+    MovePoints,
     PlayerId,
+    UnitId,
     ExactPos,
+    MapPos,
     ObjectClass,
+    ReactionFireMode,
+    Sector,
+    AiProfile,
+    AiPersonality,
+    AiDifficulty,
+    Bot,
     get_free_exact_pos,
+    move_cost_modifier,
 };
 
+/// Weapons with at least this armor penetration count as anti-tank
+const ANTI_TANK_AP: i32 = 5;
+
+/// Extra value of anti-tank units when all known enemies are vehicles
+const ANTI_TANK_WEIGHT: f32 = 2.0;
+
+/// Extra value of infantry when all sector tiles are cities
+const CITY_INFANTRY_WEIGHT: f32 = 2.0;
+
+/// Transporters are only useful when there's someone to carry
+const TRANSPORTER_WEIGHT: f32 = 0.5;
+
+/// The AI doesn't save points for longer than this many turns
+const MAX_SAVING_TURNS: i32 = 1;
+
+/// Own units within this distance make a reinforcement sector crowded
+const SPAWN_CROWD_DISTANCE: i32 = 2;
+
+/// How many move points one point of cover is worth
+const COVER_WEIGHT: i32 = 2;
+
+/// Suppressing a squad completely is worth this part of killing it
+const SUPPRESSION_WEIGHT: f32 = 0.5;
+
+/// Targets that all our units can kill during this turn get this bonus
+const FOCUS_FIRE_MULTIPLIER: f32 = 2.0;
+
+const SECTOR_THREAT_DISTANCE: i32 = 2;
+const SECTOR_THREAT_MULTIPLIER: f32 = 1.5;
+
+/// Infantry that is this far away from its objective waits for a transporter
+const MIN_TRANSPORT_DISTANCE: i32 = 4;
+
+/// Infantry waits for an empty transporter that is this close
+const PICKUP_DISTANCE: i32 = 3;
+
+/// Passengers get off this close to their objective
+const UNLOAD_DISTANCE: i32 = 2;
+
+/// Infantry this much farther than the enemy's weapon range is already in danger
+const SMOKE_DISTANCE: i32 = 2;
+
+/// How many move points an unseen sector is worth
+const UNSEEN_SECTOR_BONUS: i32 = 8;
+
+/// How many move points are added for every tile between an unseen tile
+/// and the nearest enemy reinforcement sector
+const APPROACH_WEIGHT: i32 = 2;
+
 #[derive(Clone, Debug)]
 pub struct Ai {
     id: PlayerId,
     state: PartialState,
     pathfinder: Pathfinder,
+    known_enemies: HashMap<UnitId, UnitTypeId>,
+    threat_map: ThreatMap,
+    profile: AiProfile,
 }
 
 impl Ai {
-    pub fn new(id: PlayerId, map_name: &str) -> Ai {
-        let state = PartialState::new(map_name, id);
+    pub fn new(id: PlayerId, map_name: &str, profile: AiProfile) -> Ai {
+        let state = if profile.is_cheating {
+            PartialState::new_full(map_name, id)
+        } else {
+            PartialState::new(map_name, id)
+        };
         let map_size = state.map().size();
         Ai {
             id: id,
             state: state,
             pathfinder: Pathfinder::new(map_size),
+            known_enemies: HashMap::new(),
+            threat_map: ThreatMap::new(map_size),
+            profile: profile,
         }
     }
 
-    pub fn apply_event(&mut self, db: &Db, event: &CoreEvent) {
-        self.state.apply_event(db, event);
+    /// How many move points the AI pays to avoid one enemy's reaction fire
+    fn exposure_weight(&self) -> i32 {
+        match self.profile.personality {
+            AiPersonality::Passive => 8,
+            AiPersonality::Defensive => 4,
+            AiPersonality::Aggressive => 0,
+        }
+    }
+
+    /// Lower is better, infantry prefers tiles with cover
+    fn position_score(&self, db: &Db, unit: &Unit, cost: MovePoints, pos: MapPos) -> i32 {
+        let unit_type = db.unit_type(unit.type_id);
+        let exposure = self.threat_map.threat(pos);
+        let mut score = cost.n + self.exposure_weight() * exposure;
+        if unit_type.class == UnitClass::Infantry {
+            score -= COVER_WEIGHT * tile_cover_bonus(&self.state, pos);
+        }
+        score
+    }
+
+    fn is_winning(&self) -> bool {
+        let score = self.state.score();
+        let own_score = score[&self.id].n;
+        score.iter().all(|(&id, enemy_score)| id == self.id || own_score > enemy_score.n)
+    }
+
+    /// The unit holds a sector if it's the first of the player's units there
+    fn is_garrison(&self, db: &Db, unit: &Unit) -> bool {
+        let unit_type = db.unit_type(unit.type_id);
+        if unit_type.is_air {
+            return false;
+        }
+        self.state.sectors().values()
+            .filter(|sector| sector.positions.contains(&unit.pos.map_pos))
+            .any(|sector| {
+                let garrison_id = sector.positions.iter()
+                    .flat_map(|&pos| self.state.units_at(pos))
+                    .filter(|other| other.player_id == self.id)
+                    .filter(|other| !db.unit_type(other.type_id).is_air)
+                    .filter(|other| !self.is_passenger(other))
+                    .map(|other| other.id)
+                    .min();
+                garrison_id == Some(unit.id)
+            })
+    }
+
+    fn is_held(&self, db: &Db, sector: &Sector) -> bool {
+        sector.owner_id == Some(self.id) || sector.positions.iter()
+            .flat_map(|&pos| self.state.units_at(pos))
+            .any(|unit| unit.player_id == self.id && !db.unit_type(unit.type_id).is_air)
+    }
+
+    /// Best position in a sector that is not ours yet and that no other
+    /// free unit of ours is closer to
+    fn get_sector_pos(&self, db: &Db, unit: &Unit) -> Option<ExactPos> {
+        if db.unit_type(unit.type_id).is_air {
+            return None;
+        }
+        let mut best_pos = None;
+        let mut best_score = pathfinder::max_cost().n;
+        for sector in self.state.sectors().values() {
+            if self.is_held(db, sector) {
+                continue;
+            }
+            let center = sector.center();
+            let unit_distance = (distance(unit.pos.map_pos, center), unit.id);
+            let is_taken = self.state.units().values().any(|other| {
+                other.player_id == self.id
+                    && other.id != unit.id
+                    && !db.unit_type(other.type_id).is_air
+                    && !self.is_passenger(other)
+                    && !self.is_garrison(db, other)
+                    && (distance(other.pos.map_pos, center), other.id) < unit_distance
+            });
+            if is_taken {
+                continue;
+            }
+            for &pos in &sector.positions {
+                let exact_pos = match get_free_exact_pos(
+                    db, &self.state, unit.type_id, pos
+                ) {
+                    Some(pos) => pos,
+                    None => continue,
+                };
+                let path = match self.pathfinder.get_path(exact_pos) {
+                    Some(path) => path,
+                    None => continue,
+                };
+                let cost = path_cost(db, &self.state, unit, &path);
+                let score = self.position_score(db, unit, cost, pos);
+                if best_score > score {
+                    best_score = score;
+                    best_pos = Some(exact_pos);
+                }
+            }
+        }
+        best_pos
+    }
+
+    /// The free unit with the best view goes exploring first
+    fn is_scout(&self, db: &Db, unit: &Unit) -> bool {
+        let scout_id = self.state.units().values()
+            .filter(|other| other.player_id == self.id)
+            .filter(|other| !self.is_passenger(other))
+            .filter(|other| !self.is_garrison(db, other))
+            .max_by_key(|other| {
+                (db.unit_type(other.type_id).los_range, -other.id.id)
+            })
+            .map(|other| other.id);
+        scout_id == Some(unit.id)
+    }
+
+    /// Unseen tile that is worth a look: unseen sectors and likely
+    /// approach routes from the enemy's reinforcement sectors come first
+    fn get_exploration_pos(&self, db: &Db, unit: &Unit) -> Option<ExactPos> {
+        let enemy_reinforcement_sectors: Vec<_> = self.state.objects().values()
+            .filter(|object| object.class == ObjectClass::ReinforcementSector)
+            .filter(|object| object.owner_id.is_some())
+            .filter(|object| object.owner_id != Some(self.id))
+            .map(|object| object.pos.map_pos)
+            .collect();
+        let mut best_pos = None;
+        let mut best_score = pathfinder::max_cost().n;
+        for pos in self.state.map().get_iter() {
+            if self.state.is_tile_visible(pos) {
+                continue;
+            }
+            let exact_pos = match get_free_exact_pos(
+                db, &self.state, unit.type_id, pos
+            ) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let cost = self.pathfinder.get_map().tile(exact_pos).cost();
+            if cost.n == pathfinder::max_cost().n {
+                continue;
+            }
+            let mut score = cost.n;
+            let is_sector = self.state.sectors().values()
+                .any(|sector| sector.positions.contains(&pos));
+            if is_sector {
+                score -= UNSEEN_SECTOR_BONUS;
+            }
+            let approach_distance = enemy_reinforcement_sectors.iter()
+                .map(|&sector_pos| distance(pos, sector_pos))
+                .min();
+            if let Some(approach_distance) = approach_distance {
+                score += APPROACH_WEIGHT * approach_distance;
+            }
+            if best_score > score {
+                best_score = score;
+                best_pos = Some(exact_pos);
+            }
+        }
+        best_pos
+    }
+
+    /// Best covered tile that can be reached during this turn,
+    /// `None` if the unit should stay where it is
+    fn get_cover_pos(&self, db: &Db, unit: &Unit) -> Option<ExactPos> {
+        let unit_type = db.unit_type(unit.type_id);
+        if unit_type.class != UnitClass::Infantry {
+            return None;
+        }
+        if tile_cover_bonus(&self.state, unit.pos.map_pos) > 0 {
+            return None;
+        }
+        let move_points = unit.move_points.unwrap();
+        let mut best_pos = None;
+        let mut best_score = pathfinder::max_cost().n;
+        for pos in self.state.map().get_iter() {
+            if tile_cover_bonus(&self.state, pos) == 0 {
+                continue;
+            }
+            let exact_pos = match get_free_exact_pos(
+                db, &self.state, unit.type_id, pos
+            ) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let cost = self.pathfinder.get_map().tile(exact_pos).cost();
+            if cost.n > move_points.n {
+                continue;
+            }
+            let score = self.position_score(db, unit, cost, pos);
+            if best_score > score {
+                best_score = score;
+                best_pos = Some(exact_pos);
+            }
+        }
+        best_pos
     }
 
-    // TODO: move fill_map here
     fn get_best_pos(&self, db: &Db, unit: &Unit) -> Option<ExactPos> {
         let mut best_pos = None;
-        let mut best_cost = pathfinder::max_cost();
+        let mut best_score = pathfinder::max_cost().n;
         for enemy in self.state.units().values() {
             if enemy.player_id == self.id {
                 continue;
@@ -64,8 +325,9 @@ impl Ai {
                     None => continue,
                 };
                 let cost = path_cost(db, &self.state, unit, &path);
-                if best_cost.n > cost.n {
-                    best_cost.n = cost.n;
+                let score = self.position_score(db, unit, cost, destination);
+                if best_score > score {
+                    best_score = score;
                     best_pos = Some(exact_destination);
                 }
             }
@@ -97,9 +359,10 @@ impl Ai {
         false
     }
 
-    pub fn try_get_attack_command(&self, db: &Db) -> Option<Command> {
+    fn legal_attacks(&self, db: &Db) -> Vec<(&Unit, &Unit)> {
+        let mut attacks = Vec::new();
         for unit in self.state.units().values() {
-            if unit.player_id != self.id {
+            if unit.player_id != self.id || self.is_passenger(unit) {
                 continue;
             }
             if unit.attack_points.unwrap().n <= 0 {
@@ -114,97 +377,557 @@ impl Ai {
                     defender_id: target.id,
                 };
                 if check_command(db, self.id, &self.state, &command).is_ok() {
-                    return Some(command);
+                    attacks.push((unit, target));
                 }
             }
         }
-        None
+        attacks
+    }
+
+    /// Dangerous units and units close to our sectors come first
+    fn threat(&self, db: &Db, target: &Unit) -> f32 {
+        let target_type = db.unit_type(target.type_id);
+        let weapon_type = db.weapon_type(target_type.weapon_type_id);
+        let mut threat = 1.0 + weapon_type.damage as f32;
+        let is_close_to_our_sector = self.state.sectors().values()
+            .filter(|sector| self.is_held(db, sector))
+            .flat_map(|sector| sector.positions.iter())
+            .any(|&pos| distance(pos, target.pos.map_pos) <= SECTOR_THREAT_DISTANCE);
+        if is_close_to_our_sector {
+            threat *= SECTOR_THREAT_MULTIPLIER;
+        }
+        threat
+    }
+
+    /// Ranks all legal attacks by what they are expected to achieve
+    pub fn try_get_attack_command(&self, db: &Db) -> Option<Command> {
+        let attacks = self.legal_attacks(db);
+        // kills that all our units together are expected to make this turn
+        let mut expected_kills = HashMap::new();
+        for &(attacker, target) in &attacks {
+            let chance = hit_chance(db, &self.state, attacker, target);
+            let attack_points = attacker.attack_points.unwrap().n as f32;
+            *expected_kills.entry(target.id).or_insert(0.0)
+                += chance.expected_kills * attack_points;
+        }
+        let mut best_command = None;
+        let mut best_value = 0.0;
+        for &(attacker, target) in &attacks {
+            let chance = hit_chance(db, &self.state, attacker, target);
+            if chance.total == 0 {
+                continue;
+            }
+            // finishing a squad is better than hurting a fresh one
+            let mut value = chance.expected_kills / target.count as f32
+                + SUPPRESSION_WEIGHT * chance.expected_suppression / 100.0;
+            if self.profile.difficulty != AiDifficulty::Easy {
+                if expected_kills[&target.id] >= target.count as f32 {
+                    value *= FOCUS_FIRE_MULTIPLIER;
+                }
+                value *= self.threat(db, target);
+            }
+            if value > best_value {
+                best_value = value;
+                best_command = Some(Command::AttackUnit {
+                    attacker_id: attacker.id,
+                    defender_id: target.id,
+                });
+            }
+        }
+        best_command
+    }
+
+    fn get_destination(&self, db: &Db, unit: &Unit) -> Option<ExactPos> {
+        let sector_pos = self.get_sector_pos(db, unit);
+        let enemy_pos = match self.profile.personality {
+            AiPersonality::Passive => None,
+            _ => self.get_best_pos(db, unit),
+        };
+        // when behind on score every free unit races for sectors,
+        // otherwise it goes for whatever is closer
+        match (sector_pos, enemy_pos) {
+            (Some(sector_pos), Some(enemy_pos)) => {
+                let map = self.pathfinder.get_map();
+                let sector_cost = map.tile(sector_pos).cost().n;
+                let enemy_cost = map.tile(enemy_pos).cost().n;
+                let is_hunting = self.is_winning()
+                    || self.profile.personality == AiPersonality::Aggressive;
+                if is_hunting && enemy_cost < sector_cost {
+                    Some(enemy_pos)
+                } else {
+                    Some(sector_pos)
+                }
+            },
+            (None, None) => if self.is_scout(db, unit) {
+                self.get_exploration_pos(db, unit)
+            } else {
+                self.get_cover_pos(db, unit)
+            },
+            (sector_pos, enemy_pos) => sector_pos.or(enemy_pos),
+        }
+    }
+
+    fn is_under_fire(&self, path: &[ExactPos]) -> bool {
+        path.iter().skip(1).any(|pos| self.threat_map.threat(pos.map_pos) > 0)
     }
 
     pub fn try_get_move_command(&mut self, db: &Db) -> Option<Command> {
+        self.threat_map.update(db, &self.state, self.id);
         for unit in self.state.units().values() {
-            if unit.player_id != self.id {
+            if unit.player_id != self.id || self.is_passenger(unit) {
+                continue;
+            }
+            if unit.move_points.unwrap().n <= 0 {
+                continue;
+            }
+            if self.is_garrison(db, unit) || self.is_close_to_enemies(db, unit) {
+                continue;
+            }
+            // the ride is coming
+            if self.needs_ride(db, unit) && self.is_transporter_coming(db, unit) {
                 continue;
             }
-            if self.is_close_to_enemies(db, unit) {
+            let unit_type = db.unit_type(unit.type_id);
+            let is_empty_transporter = unit_type.is_transporter
+                && unit.passenger_id.is_none();
+            if is_empty_transporter && self.is_next_to_rider(db, unit) {
                 continue;
             }
             self.pathfinder.fill_map(db, &self.state, unit);
-            // TODO: if no enemy is visible then move to random invisible tile
-            let destination = match self.get_best_pos(db, unit) {
+            let pickup_pos = if is_empty_transporter {
+                self.get_pickup_pos(db, unit)
+            } else {
+                None
+            };
+            let destination = match pickup_pos.or_else(|| self.get_destination(db, unit)) {
                 Some(destination) => destination,
                 None => continue,
             };
+            // avoid reaction fire as much as the personality wants,
+            // but any path that is still under fire is hunted through
+            let exposure_weight = self.exposure_weight();
+            if exposure_weight > 0 {
+                let threat_map = &self.threat_map;
+                self.pathfinder.fill_map_with_extra_cost(
+                    db,
+                    &self.state,
+                    unit,
+                    &|pos| exposure_weight * threat_map.threat(pos),
+                );
+            }
             let path = match self.pathfinder.get_path(destination) {
                 Some(path) => path,
                 None => continue,
             };
-            let path = match truncate_path(db, &self.state, &path, unit) {
+            let fast_path = match truncate_path(db, &self.state, &path, unit, MoveMode::Fast) {
                 Some(path) => path,
                 None => continue,
             };
-            let cost = path_cost(db, &self.state, unit, &path);
+            let hunt_path = if self.is_under_fire(&fast_path) {
+                truncate_path(db, &self.state, &path, unit, MoveMode::Hunt)
+            } else {
+                None
+            };
+            let (path, mode) = match hunt_path {
+                Some(path) => (path, MoveMode::Hunt),
+                None => (fast_path, MoveMode::Fast),
+            };
+            let cost = path_cost(db, &self.state, unit, &path).n * move_cost_modifier(mode);
             let move_points = unit.move_points.unwrap();
-            if move_points.n < cost.n {
+            if move_points.n < cost {
                 continue;
             }
             let command = Command::Move {
                 unit_id: unit.id,
                 path: path,
-                mode: MoveMode::Fast,
+                mode: mode,
             };
             if check_command(db, self.id, &self.state, &command).is_err() {
                 continue;
             }
             return Some(command);
         }
-        // TODO: if there are no visible enemies then try to capture some sector
         None
     }
 
-    pub fn try_get_create_unit_command(&self, db: &Db) -> Option<Command> {
-        let reinforcement_points = self.state.reinforcement_points()[&self.id];
-        for type_index in get_shuffled_indices(db.unit_types()) {
-            let unit_type_id = UnitTypeId{id: type_index as i32};
-            let unit_type = db.unit_type(unit_type_id);
-            if unit_type.cost > reinforcement_points {
+    fn is_passenger(&self, unit: &Unit) -> bool {
+        self.state.units().values()
+            .any(|other| other.passenger_id == Some(unit.id))
+    }
+
+    /// Nearest sector that we don't hold or nearest visible enemy
+    fn get_objective(&self, db: &Db, unit: &Unit) -> Option<MapPos> {
+        let sectors = self.state.sectors().values()
+            .filter(|sector| !self.is_held(db, sector))
+            .map(|sector| sector.center());
+        let enemies = self.state.units().values()
+            .filter(|enemy| enemy.player_id != self.id)
+            .map(|enemy| enemy.pos.map_pos);
+        sectors.chain(enemies)
+            .min_by_key(|&pos| distance(unit.pos.map_pos, pos))
+    }
+
+    /// Infantry that is far away from its objective
+    fn needs_ride(&self, db: &Db, unit: &Unit) -> bool {
+        let unit_type = db.unit_type(unit.type_id);
+        if unit_type.class != UnitClass::Infantry
+            || unit.move_points.unwrap().n <= 0
+            || self.is_passenger(unit)
+            || self.is_garrison(db, unit)
+        {
+            return false;
+        }
+        match self.get_objective(db, unit) {
+            Some(pos) => distance(unit.pos.map_pos, pos) >= MIN_TRANSPORT_DISTANCE,
+            None => false,
+        }
+    }
+
+    fn is_transporter_coming(&self, db: &Db, rider: &Unit) -> bool {
+        self.state.units().values().any(|unit| {
+            unit.player_id == self.id
+                && db.unit_type(unit.type_id).is_transporter
+                && unit.passenger_id.is_none()
+                && unit.move_points.unwrap().n > 0
+                && distance(unit.pos.map_pos, rider.pos.map_pos) <= PICKUP_DISTANCE
+        })
+    }
+
+    fn is_next_to_rider(&self, db: &Db, transporter: &Unit) -> bool {
+        self.state.units().values().any(|unit| {
+            unit.player_id == self.id
+                && distance(unit.pos.map_pos, transporter.pos.map_pos) <= 1
+                && self.needs_ride(db, unit)
+        })
+    }
+
+    /// Free tile next to the nearest infantry that needs a ride
+    fn get_pickup_pos(&self, db: &Db, transporter: &Unit) -> Option<ExactPos> {
+        let rider = self.state.units().values()
+            .filter(|unit| unit.player_id == self.id && self.needs_ride(db, unit))
+            .min_by_key(|unit| distance(unit.pos.map_pos, transporter.pos.map_pos))?;
+        dirs()
+            .map(|dir| Dir::get_neighbour_pos(rider.pos.map_pos, dir))
+            .filter(|&pos| self.state.map().is_inboard(pos))
+            .filter_map(|pos| get_free_exact_pos(db, &self.state, transporter.type_id, pos))
+            .filter(|&pos| self.pathfinder.get_path(pos).is_some())
+            .min_by_key(|&pos| self.pathfinder.get_map().tile(pos).cost().n)
+    }
+
+    pub fn try_get_load_command(&self, db: &Db) -> Option<Command> {
+        for transporter in self.state.units().values() {
+            if transporter.player_id != self.id
+                || !db.unit_type(transporter.type_id).is_transporter
+                || transporter.passenger_id.is_some()
+                || transporter.move_points.unwrap().n <= 0
+            {
                 continue;
             }
-            for object in self.state.objects().values() {
-                let owner_id = match object.owner_id {
-                    Some(id) => id,
-                    None => continue,
-                };
-                if owner_id != self.id {
-                    continue;
-                }
-                if object.class != ObjectClass::ReinforcementSector {
+            for unit in self.state.units().values() {
+                if unit.player_id != self.id || !self.needs_ride(db, unit) {
                     continue;
                 }
-                let exact_pos = match get_free_exact_pos(
-                    db,
-                    &self.state,
-                    unit_type_id,
-                    object.pos.map_pos,
-                ) {
-                    Some(pos) => pos,
-                    None => continue,
-                };
-                let command = Command::CreateUnit {
-                    type_id: unit_type_id,
-                    pos: exact_pos,
+                let command = Command::LoadUnit {
+                    transporter_id: transporter.id,
+                    passenger_id: unit.id,
                 };
-                if check_command(db, self.id, &self.state, &command).is_err() {
+                if check_command(db, self.id, &self.state, &command).is_ok() {
+                    return Some(command);
+                }
+            }
+        }
+        None
+    }
+
+    /// Passengers get off close to their objective
+    pub fn try_get_unload_command(&self, db: &Db) -> Option<Command> {
+        for transporter in self.state.units().values() {
+            if transporter.player_id != self.id {
+                continue;
+            }
+            let passenger = match transporter.passenger_id {
+                Some(id) => self.state.unit(id),
+                None => continue,
+            };
+            let objective = self.get_objective(db, transporter);
+            let is_close = match objective {
+                Some(pos) => distance(transporter.pos.map_pos, pos) <= UNLOAD_DISTANCE,
+                None => true,
+            };
+            if !is_close && !self.is_garrison(db, transporter) {
+                continue;
+            }
+            let command = dirs()
+                .map(|dir| Dir::get_neighbour_pos(transporter.pos.map_pos, dir))
+                .filter(|&pos| self.state.map().is_inboard(pos))
+                .filter_map(|pos| get_free_exact_pos(db, &self.state, passenger.type_id, pos))
+                .map(|pos| Command::UnloadUnit {
+                    transporter_id: transporter.id,
+                    passenger_id: passenger.id,
+                    pos: pos,
+                })
+                .filter(|command| check_command(db, self.id, &self.state, command).is_ok())
+                .min_by_key(|command| match (command, objective) {
+                    (&Command::UnloadUnit{pos, ..}, Some(objective)) => {
+                        distance(pos.map_pos, objective)
+                    },
+                    _ => 0,
+                });
+            if command.is_some() {
+                return command;
+            }
+        }
+        None
+    }
+
+    /// Mortars cover infantry that crosses open ground
+    /// in front of visible enemies
+    pub fn try_get_smoke_command(&self, db: &Db) -> Option<Command> {
+        if self.profile.difficulty == AiDifficulty::Easy {
+            return None;
+        }
+        for mortar in self.state.units().values() {
+            if mortar.player_id != self.id || self.is_passenger(mortar) {
+                continue;
+            }
+            let mortar_type = db.unit_type(mortar.type_id);
+            let weapon_type = db.weapon_type(mortar_type.weapon_type_id);
+            if weapon_type.smoke.is_none() {
+                continue;
+            }
+            for unit in self.state.units().values() {
+                if unit.player_id != self.id
+                    || unit.id == mortar.id
+                    || db.unit_type(unit.type_id).class != UnitClass::Infantry
+                    || self.is_passenger(unit)
+                    || *self.state.map().tile(unit.pos) != Terrain::Plain
+                {
                     continue;
                 }
+                for enemy in self.state.units().values() {
+                    if enemy.player_id == self.id {
+                        continue;
+                    }
+                    let enemy_type = db.unit_type(enemy.type_id);
+                    let enemy_weapon_type = db.weapon_type(enemy_type.weapon_type_id);
+                    let distance_to_enemy = distance(unit.pos.map_pos, enemy.pos.map_pos);
+                    if distance_to_enemy > enemy_weapon_type.max_distance + SMOKE_DISTANCE {
+                        continue;
+                    }
+                    let pos = dirs()
+                        .map(|dir| Dir::get_neighbour_pos(unit.pos.map_pos, dir))
+                        .filter(|&pos| self.state.map().is_inboard(pos))
+                        .min_by_key(|&pos| distance(pos, enemy.pos.map_pos))
+                        .unwrap();
+                    let is_smoked = self.state.objects_at(pos).iter()
+                        .any(|object| object.class == ObjectClass::Smoke);
+                    if is_smoked {
+                        continue;
+                    }
+                    let command = Command::Smoke{unit_id: mortar.id, pos: pos};
+                    if check_command(db, self.id, &self.state, &command).is_ok() {
+                        return Some(command);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Shooting that can't hurt anybody only gives the unit away
+    fn should_hold_fire(&self, db: &Db, unit: &Unit) -> bool {
+        let enemies: Vec<_> = self.state.units().values()
+            .filter(|enemy| enemy.player_id != self.id)
+            .collect();
+        if enemies.is_empty() {
+            return self.is_scout(db, unit);
+        }
+        enemies.iter()
+            .all(|enemy| hit_chance(db, &self.state, unit, enemy).total == 0)
+    }
+
+    pub fn try_get_reaction_fire_mode_command(&self, db: &Db) -> Option<Command> {
+        if self.profile.difficulty == AiDifficulty::Easy {
+            return None;
+        }
+        for unit in self.state.units().values() {
+            if unit.player_id != self.id || self.is_passenger(unit) {
+                continue;
+            }
+            if db.unit_type(unit.type_id).reactive_attack_points.n == 0 {
+                continue;
+            }
+            let mode = match (self.should_hold_fire(db, unit), unit.reaction_fire_mode) {
+                (true, ReactionFireMode::Normal) => ReactionFireMode::HoldFire,
+                (false, ReactionFireMode::HoldFire) => ReactionFireMode::Normal,
+                _ => continue,
+            };
+            let command = Command::SetReactionFireMode {
+                unit_id: unit.id,
+                mode: mode,
+            };
+            if check_command(db, self.id, &self.state, &command).is_ok() {
                 return Some(command);
             }
         }
         None
     }
 
-    pub fn get_command(&mut self, db: &Db) -> Command {
-        if let Some(cmd) = self.try_get_attack_command(db) {
+    /// Part of the known enemy units that are vehicles
+    fn enemy_armor_share(&self, db: &Db) -> f32 {
+        if self.known_enemies.is_empty() {
+            return 0.0;
+        }
+        let vehicles_count = self.known_enemies.values()
+            .filter(|&&type_id| db.unit_type(type_id).class == UnitClass::Vehicle)
+            .count();
+        vehicles_count as f32 / self.known_enemies.len() as f32
+    }
+
+    /// Part of the sector tiles that are cities
+    fn city_share(&self) -> f32 {
+        let positions: Vec<_> = self.state.sectors().values()
+            .flat_map(|sector| sector.positions.iter())
+            .collect();
+        if positions.is_empty() {
+            return 0.0;
+        }
+        let cities_count = positions.iter()
+            .filter(|&&&pos| *self.state.map().tile(pos) == Terrain::City)
+            .count();
+        cities_count as f32 / positions.len() as f32
+    }
+
+    /// How much we need another unit of this type, higher is better
+    fn unit_type_value(
+        &self,
+        db: &Db,
+        type_id: UnitTypeId,
+        armor_share: f32,
+        city_share: f32,
+    ) -> f32 {
+        let unit_type = db.unit_type(type_id);
+        let mut value = if unit_type.is_transporter {
+            TRANSPORTER_WEIGHT
+        } else {
+            1.0
+        };
+        if db.weapon_type(unit_type.weapon_type_id).ap >= ANTI_TANK_AP {
+            value += ANTI_TANK_WEIGHT * armor_share;
+        }
+        if unit_type.class == UnitClass::Infantry {
+            value += CITY_INFANTRY_WEIGHT * city_share;
+        }
+        // keep the army mixed
+        let owned_count = self.state.units().values()
+            .filter(|unit| unit.player_id == self.id && unit.type_id == type_id)
+            .count();
+        value / (1 + owned_count) as f32
+    }
+
+    /// The most needed unit type that we can afford now or soon enough
+    fn get_wanted_unit_type(&self, db: &Db) -> Option<UnitTypeId> {
+        let reinforcement_points = self.state.reinforcement_points()[&self.id];
+        let has_units = self.state.units().values()
+            .any(|unit| unit.player_id == self.id);
+        let is_saving = has_units && self.profile.difficulty != AiDifficulty::Easy;
+        // don't save points when there's nobody to fight meanwhile
+        let max_cost = if is_saving {
+            reinforcement_points + REINFORCEMENT_POINTS_PER_TURN * MAX_SAVING_TURNS
+        } else {
+            reinforcement_points
+        };
+        let armor_share = self.enemy_armor_share(db);
+        let city_share = self.city_share();
+        let mut best_type_id = None;
+        let mut best_value = 0.0;
+        // shuffled, so equally useful types are picked at random
+        for type_index in get_shuffled_indices(db.unit_types()) {
+            let type_id = UnitTypeId{id: type_index as i32};
+            if db.unit_type(type_id).cost > max_cost {
+                continue;
+            }
+            let value = self.unit_type_value(db, type_id, armor_share, city_share);
+            if value > best_value {
+                best_value = value;
+                best_type_id = Some(type_id);
+            }
+        }
+        best_type_id
+    }
+
+    /// Own reinforcement sectors, the least crowded ones first
+    fn get_spawn_positions(&self) -> Vec<MapPos> {
+        let mut positions: Vec<_> = self.state.objects().values()
+            .filter(|object| object.class == ObjectClass::ReinforcementSector)
+            .filter(|object| object.owner_id == Some(self.id))
+            .map(|object| object.pos.map_pos)
+            .collect();
+        positions.sort_by_key(|&pos| {
+            self.state.units().values()
+                .filter(|unit| unit.player_id == self.id)
+                .filter(|unit| distance(unit.pos.map_pos, pos) <= SPAWN_CROWD_DISTANCE)
+                .count()
+        });
+        positions
+    }
+
+    pub fn try_get_create_unit_command(&self, db: &Db) -> Option<Command> {
+        let type_id = self.get_wanted_unit_type(db)?;
+        // saving points for this unit
+        if db.unit_type(type_id).cost > self.state.reinforcement_points()[&self.id] {
+            return None;
+        }
+        for pos in self.get_spawn_positions() {
+            let exact_pos = match get_free_exact_pos(db, &self.state, type_id, pos) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let command = Command::CreateUnit {
+                type_id: type_id,
+                pos: exact_pos,
+            };
+            if check_command(db, self.id, &self.state, &command).is_err() {
+                continue;
+            }
+            return Some(command);
+        }
+        None
+    }
+}
+
+impl Bot for Ai {
+    fn apply_event(&mut self, db: &Db, event: &CoreEvent) {
+        let units_count = self.state.units().len();
+        self.state.apply_event(db, event);
+        // remember enemies even after they are hidden again
+        match *event {
+            CoreEvent::CreateUnit{ref unit_info}
+            | CoreEvent::ShowUnit{ref unit_info}
+            | CoreEvent::UnloadUnit{ref unit_info, ..}
+                if unit_info.player_id != self.id =>
+            {
+                self.known_enemies.insert(unit_info.unit_id, unit_info.type_id);
+            },
+            CoreEvent::AttackUnit{ref attack_info}
+                if self.state.units().len() < units_count =>
+            {
+                self.known_enemies.remove(&attack_info.defender_id);
+            },
+            _ => {},
+        }
+    }
+
+    fn get_command(&mut self, db: &Db) -> Command {
+        if let Some(cmd) = self.try_get_reaction_fire_mode_command(db) {
+            cmd
+        } else if let Some(cmd) = self.try_get_smoke_command(db) {
+            cmd
+        } else if let Some(cmd) = self.try_get_attack_command(db) {
+            cmd
+        } else if let Some(cmd) = self.try_get_unload_command(db) {
+            cmd
+        } else if let Some(cmd) = self.try_get_load_command(db) {
             cmd
         } else if let Some(cmd) = self.try_get_move_command(db) {
             cmd
@@ -1,15 +1,22 @@
 use std::{fmt, error};
 use game_state::{GameState};
-use map::{distance};
+use map::{Terrain, distance};
 use pathfinder::{path_cost, tile_cost};
-use unit::{Unit, UnitClass};
+use unit::{Unit, UnitClass, UnitTypeId};
 use db::{Db};
 use fov::{fov, simple_fov};
 use ::{
     Command,
     FireMode,
+    ReactionFireMode,
     PlayerId,
+    UnitId,
+    MapPos,
+    ExactPos,
+    SlotId,
     ObjectClass,
+    find_object_at,
+    get_slots_count,
     is_exact_pos_free,
     move_cost_modifier,
 };
@@ -42,6 +49,14 @@ pub enum CommandError {
     BadDefenderId,
     BadPath,
     BadUnitType,
+    BadTerrain,
+    NoMinefield,
+    AlreadyConstructed,
+    AlreadyEntrenched,
+    NotEnoughSupportPoints,
+    NoSpotter,
+    UnseenObstacle,
+    NothingToUndo,
 }
 
 impl CommandError {
@@ -73,6 +88,14 @@ impl CommandError {
             CommandError::BadDefenderId => "Bad defender id",
             CommandError::BadPath => "Bad path",
             CommandError::BadUnitType => "Bad unit type",
+            CommandError::BadTerrain => "Bad terrain",
+            CommandError::NoMinefield => "No minefield",
+            CommandError::AlreadyConstructed => "Already constructed",
+            CommandError::AlreadyEntrenched => "Already entrenched",
+            CommandError::NotEnoughSupportPoints => "No support points",
+            CommandError::NoSpotter => "No unit can see the target",
+            CommandError::UnseenObstacle => "Something unseen is in the way",
+            CommandError::NothingToUndo => "Nothing to undo",
         }
     }
 }
@@ -89,6 +112,28 @@ impl error::Error for CommandError {
     }
 }
 
+/// The position is on the map and its slot fits the unit type
+fn is_exact_pos_valid<S: GameState>(
+    db: &Db,
+    state: &S,
+    type_id: UnitTypeId,
+    pos: ExactPos,
+) -> bool {
+    if !state.map().is_inboard(pos.map_pos) {
+        return false;
+    }
+    let unit_type = db.unit_type(type_id);
+    match pos.slot_id {
+        SlotId::Air => unit_type.is_air,
+        SlotId::WholeTile => unit_type.is_big && !unit_type.is_air,
+        SlotId::Id(id) => {
+            !unit_type.is_big && !unit_type.is_air
+                && (id as i32) < get_slots_count(state.map(), pos.map_pos)
+        },
+        SlotId::TwoTiles(_) => false,
+    }
+}
+
 pub fn check_command<S: GameState>(
     db: &Db,
     player_id: PlayerId,
@@ -97,7 +142,15 @@ pub fn check_command<S: GameState>(
 ) -> Result<(), CommandError> {
     match *command {
         Command::EndTurn => Ok(()),
+        // only `Core` knows the history
+        Command::Undo => Ok(()),
         Command::CreateUnit{pos, type_id} => {
+            if type_id.id < 0 || type_id.id as usize >= db.unit_types().len() {
+                return Err(CommandError::BadUnitType);
+            }
+            if !is_exact_pos_valid(db, state, type_id, pos) {
+                return Err(CommandError::BadPath);
+            }
             let mut is_sector = false;
             for object in state.objects_at(pos.map_pos) {
                 if object.class == ObjectClass::ReinforcementSector {
@@ -119,19 +172,23 @@ pub fn check_command<S: GameState>(
             Ok(())
         },
         Command::Move{unit_id, ref path, mode} => {
-            let unit = state.unit(unit_id);
+            let unit = match state.units().get(&unit_id) {
+                Some(unit) => unit,
+                None => return Err(CommandError::BadUnitId),
+            };
             if unit.player_id != player_id {
                 return Err(CommandError::CanNotCommandEnemyUnits);
             }
-            if path.len() < 2 {
+            if path.len() < 2 || path[0] != unit.pos {
                 return Err(CommandError::BadPath);
             }
-            if state.units().get(&unit_id).is_none() {
-                return Err(CommandError::BadUnitId);
-            }
-            let unit = state.unit(unit_id);
             for window in path.windows(2) {
                 let pos = window[1];
+                if !is_exact_pos_valid(db, state, unit.type_id, pos)
+                    || distance(window[0].map_pos, pos.map_pos) != 1
+                {
+                    return Err(CommandError::BadPath);
+                }
                 if !is_exact_pos_free(db, state, unit.type_id, pos) {
                     return Err(CommandError::BadPath);
                 }
@@ -144,6 +201,27 @@ pub fn check_command<S: GameState>(
             }
             Ok(())
         },
+        Command::GroupMove{ref unit_ids, pos, ..} => {
+            if unit_ids.is_empty() {
+                return Err(CommandError::BadUnitId);
+            }
+            for (i, unit_id) in unit_ids.iter().enumerate() {
+                let unit = match state.units().get(unit_id) {
+                    Some(unit) => unit,
+                    None => return Err(CommandError::BadUnitId),
+                };
+                if unit.player_id != player_id {
+                    return Err(CommandError::CanNotCommandEnemyUnits);
+                }
+                if unit_ids[..i].contains(unit_id) {
+                    return Err(CommandError::BadUnitId);
+                }
+            }
+            if !state.map().is_inboard(pos) {
+                return Err(CommandError::BadPath);
+            }
+            Ok(())
+        },
         Command::AttackUnit{attacker_id, defender_id} => {
             if state.units().get(&attacker_id).is_none() {
                 return Err(CommandError::BadAttackerId);
@@ -158,6 +236,38 @@ pub fn check_command<S: GameState>(
             let defender = state.unit(defender_id);
             check_attack(db, state, attacker, defender, FireMode::Active)
         },
+        Command::AreaFire{unit_id, pos} => {
+            let unit = match state.units().get(&unit_id) {
+                Some(unit) => unit,
+                None => return Err(CommandError::BadUnitId),
+            };
+            if unit.player_id != player_id {
+                return Err(CommandError::CanNotCommandEnemyUnits);
+            }
+            let unit_type = db.unit_type(unit.type_id);
+            let weapon_type = db.weapon_type(unit_type.weapon_type_id);
+            if !weapon_type.is_inderect {
+                return Err(CommandError::BadUnitType);
+            }
+            if unit.attack_points.unwrap().n <= 0 {
+                return Err(CommandError::NotEnoughAttackPoints);
+            }
+            let minimal_ok_morale = 50;
+            if unit.morale < minimal_ok_morale {
+                return Err(CommandError::BadMorale);
+            }
+            if !state.map().is_inboard(pos) {
+                return Err(CommandError::OutOfRange);
+            }
+            let distance = distance(unit.pos.map_pos, pos);
+            if distance > weapon_type.max_distance {
+                return Err(CommandError::OutOfRange);
+            }
+            if distance < weapon_type.min_distance {
+                return Err(CommandError::TooClose);
+            }
+            Ok(())
+        },
         Command::LoadUnit{transporter_id, passenger_id} => {
             if state.units().get(&transporter_id).is_none() {
                 return Err(CommandError::BadTransporterId);
@@ -213,6 +323,9 @@ pub fn check_command<S: GameState>(
             if !db.unit_type(transporter.type_id).is_transporter {
                 return Err(CommandError::BadTransporterClass);
             }
+            if !is_exact_pos_valid(db, state, passenger.type_id, pos) {
+                return Err(CommandError::BadPath);
+            }
             if distance(transporter.pos.map_pos, pos.map_pos) > 1 {
                 return Err(CommandError::UnloadDistanceIsTooBig);
             }
@@ -229,7 +342,7 @@ pub fn check_command<S: GameState>(
             }
             Ok(())
         },
-        Command::SetReactionFireMode{unit_id, ..} => {
+        Command::SetReactionFireMode{unit_id, mode} => {
             let unit = match state.units().get(&unit_id) {
                 Some(unit) => unit,
                 None => return Err(CommandError::BadUnitId),
@@ -237,6 +350,26 @@ pub fn check_command<S: GameState>(
             if unit.player_id != player_id {
                 return Err(CommandError::CanNotCommandEnemyUnits);
             }
+            let unit_type = db.unit_type(unit.type_id);
+            let weapon_type = db.weapon_type(unit_type.weapon_type_id);
+            match mode {
+                ReactionFireMode::Normal |
+                ReactionFireMode::HoldFire => {},
+                ReactionFireMode::Overwatch{pos} => {
+                    if !state.map().is_inboard(pos)
+                        || distance(unit.pos.map_pos, pos) > weapon_type.max_distance
+                    {
+                        return Err(CommandError::OutOfRange);
+                    }
+                },
+                ReactionFireMode::Ambush{distance: ambush_distance} => {
+                    if ambush_distance < 1
+                        || ambush_distance > weapon_type.max_distance
+                    {
+                        return Err(CommandError::OutOfRange);
+                    }
+                },
+            }
             Ok(())
         },
         Command::Smoke{unit_id, pos} => {
@@ -252,7 +385,9 @@ pub fn check_command<S: GameState>(
             if !weapon_type.smoke.is_some() {
                 return Err(CommandError::BadUnitType);
             }
-            if distance(unit.pos.map_pos, pos) > weapon_type.max_distance {
+            if !state.map().is_inboard(pos)
+                || distance(unit.pos.map_pos, pos) > weapon_type.max_distance
+            {
                 return Err(CommandError::OutOfRange);
             }
             let attack_points = unit.attack_points.unwrap();
@@ -261,7 +396,120 @@ pub fn check_command<S: GameState>(
             }
             Ok(())
         },
+        Command::DigIn{unit_id} => {
+            let unit = match state.units().get(&unit_id) {
+                Some(unit) => unit,
+                None => return Err(CommandError::BadUnitId),
+            };
+            if unit.player_id != player_id {
+                return Err(CommandError::CanNotCommandEnemyUnits);
+            }
+            let unit_type = db.unit_type(unit.type_id);
+            if unit_type.class != UnitClass::Infantry {
+                return Err(CommandError::BadUnitType);
+            }
+            if unit.is_entrenched || unit.is_digging_in {
+                return Err(CommandError::AlreadyEntrenched);
+            }
+            // digging in takes the whole turn
+            if unit.move_points.unwrap().n != unit_type.move_points.n {
+                return Err(CommandError::NotEnoughMovePoints);
+            }
+            Ok(())
+        },
+        Command::CallSupport{pos, support_type} => {
+            if !state.map().is_inboard(pos) {
+                return Err(CommandError::OutOfRange);
+            }
+            let support_points = state.support_points()[&player_id];
+            if support_type.cost() > support_points {
+                return Err(CommandError::NotEnoughSupportPoints);
+            }
+            // `Core` checks that the player sees the tile
+            Ok(())
+        },
+        Command::BuildBridge{unit_id, pos} => {
+            check_engineer_work(db, player_id, state, unit_id, pos)?;
+            if *state.map().tile(pos) != Terrain::Water {
+                return Err(CommandError::BadTerrain);
+            }
+            check_construction(state, pos, ObjectClass::Bridge)
+        },
+        Command::BuildEntrenchment{unit_id, pos} => {
+            check_engineer_work(db, player_id, state, unit_id, pos)?;
+            if *state.map().tile(pos) == Terrain::Water {
+                return Err(CommandError::BadTerrain);
+            }
+            check_construction(state, pos, ObjectClass::Entrenchment)
+        },
+        Command::PlaceMinefield{unit_id, pos} => {
+            check_engineer_work(db, player_id, state, unit_id, pos)?;
+            if *state.map().tile(pos) == Terrain::Water {
+                return Err(CommandError::BadTerrain);
+            }
+            // enemy minefields are not taken into account
+            // so that this check tells nothing about them
+            let is_mined = state.objects_at(pos).iter().any(|object| {
+                object.class == ObjectClass::Minefield
+                    && object.owner_id == Some(player_id)
+            });
+            if is_mined {
+                return Err(CommandError::AlreadyConstructed);
+            }
+            Ok(())
+        },
+        Command::ClearMinefield{unit_id, pos} => {
+            check_engineer_work(db, player_id, state, unit_id, pos)?;
+            if find_object_at(state, pos, ObjectClass::Minefield).is_none() {
+                return Err(CommandError::NoMinefield);
+            }
+            Ok(())
+        },
+    }
+}
+
+/// Engineers work on their own or neighbour tile and it takes the whole turn
+fn check_engineer_work<S: GameState>(
+    db: &Db,
+    player_id: PlayerId,
+    state: &S,
+    unit_id: UnitId,
+    pos: MapPos,
+) -> Result<(), CommandError> {
+    let unit = match state.units().get(&unit_id) {
+        Some(unit) => unit,
+        None => return Err(CommandError::BadUnitId),
+    };
+    if unit.player_id != player_id {
+        return Err(CommandError::CanNotCommandEnemyUnits);
     }
+    let unit_type = db.unit_type(unit.type_id);
+    if !unit_type.is_engineer {
+        return Err(CommandError::BadUnitType);
+    }
+    if !state.map().is_inboard(pos) || distance(unit.pos.map_pos, pos) > 1 {
+        return Err(CommandError::OutOfRange);
+    }
+    if unit.move_points.unwrap().n != unit_type.move_points.n {
+        return Err(CommandError::NotEnoughMovePoints);
+    }
+    if unit.attack_points.unwrap().n != unit_type.attack_points.n {
+        return Err(CommandError::NotEnoughAttackPoints);
+    }
+    Ok(())
+}
+
+fn check_construction<S: GameState>(
+    state: &S,
+    pos: MapPos,
+    class: ObjectClass,
+) -> Result<(), CommandError> {
+    if let Some(id) = find_object_at(state, pos, class) {
+        if state.objects()[&id].is_complete() {
+            return Err(CommandError::AlreadyConstructed);
+        }
+    }
+    Ok(())
 }
 
 pub fn check_attack<S: GameState>(
@@ -305,8 +553,12 @@ pub fn check_attack<S: GameState>(
             return Err(CommandError::TooClose);
         }
     }
-    let is_los_ok = los(db, state, attacker, defender);
-    if !weapon_type.is_inderect && !is_los_ok {
+    if weapon_type.is_inderect {
+        // some unit of the player must see the target
+        if !state.is_unit_visible(db, defender) {
+            return Err(CommandError::NoSpotter);
+        }
+    } else if !los(db, state, attacker, defender) {
         return Err(CommandError::NoLos);
     }
     Ok(())
@@ -111,6 +111,42 @@ fn get_weapon_types() -> Vec<WeaponType> {
             reaction_fire: true,
             smoke: None,
         },
+        WeaponType {
+            name: "off_map_artillery".to_owned(),
+            damage: 7,
+            ap: 3,
+            accuracy: 8,
+            max_distance: 0,
+            max_air_distance: None,
+            min_distance: 0,
+            is_inderect: true,
+            reaction_fire: false,
+            smoke: None,
+        },
+        WeaponType {
+            name: "air_strike".to_owned(),
+            damage: 9,
+            ap: 8,
+            accuracy: 11,
+            max_distance: 0,
+            max_air_distance: None,
+            min_distance: 0,
+            is_inderect: true,
+            reaction_fire: false,
+            smoke: None,
+        },
+        WeaponType {
+            name: "mine".to_owned(),
+            damage: 7,
+            ap: 6,
+            accuracy: 0,
+            max_distance: 0,
+            max_air_distance: None,
+            min_distance: 0,
+            is_inderect: false,
+            reaction_fire: false,
+            smoke: None,
+        },
     ]
 }
 
@@ -134,6 +170,7 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: false,
             is_big: true,
             is_air: false,
+            is_engineer: false,
             cost: 16,
         },
         UnitType {
@@ -153,6 +190,7 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: false,
             is_big: false,
             is_air: false,
+            is_engineer: false,
             cost: 10,
         },
         UnitType {
@@ -172,6 +210,7 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: false,
             is_big: false,
             is_air: false,
+            is_engineer: false,
             cost: 8,
         },
         UnitType {
@@ -191,6 +230,7 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: false,
             is_big: false,
             is_air: false,
+            is_engineer: false,
             cost: 6,
         },
         UnitType {
@@ -210,6 +250,7 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: false,
             is_big: false,
             is_air: false,
+            is_engineer: false,
             cost: 6,
         },
         UnitType {
@@ -230,6 +271,7 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: false,
             is_big: false,
             is_air: false,
+            is_engineer: false,
             cost: 5,
         },
         UnitType {
@@ -249,6 +291,7 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: false,
             is_big: false,
             is_air: false,
+            is_engineer: false,
             cost: 4,
         },
         UnitType {
@@ -268,6 +311,7 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: true,
             is_big: false,
             is_air: false,
+            is_engineer: false,
             cost: 4,
         },
         UnitType {
@@ -287,6 +331,7 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: false,
             is_big: true,
             is_air: true,
+            is_engineer: false,
             cost: 10,
         },
         UnitType {
@@ -306,6 +351,7 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: false,
             is_big: false,
             is_air: false,
+            is_engineer: false,
             cost: 2,
         },
         UnitType {
@@ -325,6 +371,7 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: false,
             is_big: false,
             is_air: false,
+            is_engineer: false,
             cost: 2,
         },
         UnitType {
@@ -344,6 +391,7 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: false,
             is_big: false,
             is_air: false,
+            is_engineer: false,
             cost: 3,
         },
         UnitType {
@@ -363,8 +411,29 @@ fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
             is_transporter: false,
             is_big: false,
             is_air: false,
+            is_engineer: false,
             cost: 4,
         },
+        UnitType {
+            name: "engineer".to_owned(),
+            class: UnitClass::Infantry,
+            size: 4,
+            count: 3,
+            armor: 1,
+            toughness: 2,
+            weapon_skill: 4,
+            weapon_type_id: weapon_type_id(weapon_types, "rifle"),
+            move_points: MovePoints{n: 9},
+            attack_points: AttackPoints{n: 2},
+            reactive_attack_points: AttackPoints{n: 1},
+            los_range: 6,
+            cover_los_range: 1,
+            is_transporter: false,
+            is_big: false,
+            is_air: false,
+            is_engineer: true,
+            cost: 3,
+        },
     ]
 }
 
@@ -384,7 +453,7 @@ impl Db {
         }
     }
 
-    fn unit_type_id_opt(&self, name: &str) -> Option<UnitTypeId> {
+    pub fn unit_type_id_opt(&self, name: &str) -> Option<UnitTypeId> {
         for (id, unit_type) in self.unit_types.iter().enumerate() {
             if unit_type.name == name {
                 return Some(UnitTypeId{id: id as i32});
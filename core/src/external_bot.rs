@@ -0,0 +1,555 @@
+//! Bots that run as separate processes and talk to the core
+//! through their stdin and stdout.
+//!
+//! Every message is one line of text. The core sends:
+//!
+//! - `player <id> <map name>` once, when the bot is started;
+//! - `event <event>` for every event that the bot's player can see,
+//!   see the list of events below;
+//! - `turn` when it waits for the next command;
+//! - `error <message>` when the last command couldn't be parsed
+//!   or broke the rules and was ignored.
+//!
+//! The bot answers every `turn` with one command:
+//!
+//! - `end_turn`
+//! - `move <unit id> <fast|hunt> <exact pos> <exact pos>...`
+//! - `group_move <fast|hunt> <pos> <unit id>...`
+//! - `create_unit <unit type name> <exact pos>`
+//! - `attack <attacker id> <defender id>`
+//! - `area_fire <unit id> <pos>`
+//! - `load <transporter id> <passenger id>`
+//! - `unload <transporter id> <passenger id> <exact pos>`
+//! - `reaction_fire <unit id> <normal|hold_fire|overwatch <pos>|ambush <distance>>`
+//! - `smoke <unit id> <pos>`
+//! - `dig_in <unit id>`
+//! - `build_bridge <unit id> <pos>`
+//! - `build_entrenchment <unit id> <pos>`
+//! - `place_minefield <unit id> <pos>`
+//! - `clear_minefield <unit id> <pos>`
+//! - `call_support <artillery|air_strike> <pos>`
+//! - `undo`
+//!
+//! `<pos>` is `x,y` and `<exact pos>` is `x,y,slot` where the slot
+//! is a slot number, `whole`, `air` or `two:<dir number>`.
+//!
+//! Events are written the same way, a missing id or number is `-`
+//! and a flag is `true` or `false`:
+//!
+//! - `move <unit id> <from exact pos> <to exact pos> <fast|hunt> <cost>`
+//! - `undo_move <unit id> <from exact pos> <to exact pos> <cost> <fast|hunt|-> <is entrenched>`
+//! - `end_turn <old player id> <new player id> <is new round>`
+//! - `create_unit <unit>`
+//! - `attack <attacker id> <defender id> <active|reactive> <killed> <suppression>
+//!   <removes move points> <is ambush> <is indirect> <is return fire>`
+//! - `area_fire <attacker id> <pos>`
+//! - `show_unit <unit>`
+//! - `hide_unit <unit id>`
+//! - `load <transporter id> <passenger id> <from exact pos> <to exact pos>`
+//! - `unload <transporter id> <from exact pos> <to exact pos> <unit>`
+//! - `reaction_fire <unit id> <reaction fire mode>`, the mode as in the command
+//! - `dig_in <unit id>`
+//! - `entrench <unit id>`
+//! - `sector_owner <sector id> <player id>`
+//! - `victory_point <player id> <pos> <count>`
+//! - `smoke <object id> <pos> <unit id>`
+//! - `remove_smoke <object id>`
+//! - `reveal_object <object id> <object class> <exact pos> <owner id> <timer> <progress>`
+//! - `construct <object id> <object class> <pos> <unit id> <progress>`
+//! - `clear_minefield <object id> <unit id>`
+//! - `call_support <player id> <artillery|air_strike> <pos>`
+//! - `support_strike <player id> <artillery|air_strike> <pos>`
+//!
+//! `<unit>` is `<unit id> <unit type name> <player id> <exact pos>
+//! <passenger id> <is entrenched>` and `<object class>` is one of
+//! `building`, `road`, `smoke`, `reinforcement_sector`, `minefield`,
+//! `barbed_wire`, `tank_traps`, `bridge` or `entrenchment`.
+//!
+//! A bot that closes its stdout ends all its turns.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{self, Child, Stdio};
+use std::str::{SplitWhitespace};
+use cgmath::{Vector2};
+use db::{Db};
+use dir::{Dir};
+use check::{CommandError};
+use ::{
+    Bot,
+    Command,
+    CoreEvent,
+    ExactPos,
+    FireMode,
+    MapPos,
+    MoveMode,
+    ObjectClass,
+    PlayerId,
+    ReactionFireMode,
+    SlotId,
+    SupportCall,
+    SupportType,
+    UnitId,
+    UnitInfo,
+    MAX_BOT_ERRORS,
+};
+
+fn next_token<'a>(tokens: &mut SplitWhitespace<'a>) -> Result<&'a str, String> {
+    tokens.next().ok_or_else(|| "Not enough arguments".to_owned())
+}
+
+fn parse_number(s: &str) -> Result<i32, String> {
+    s.parse().map_err(|_| format!("Bad number: {}", s))
+}
+
+fn parse_unit_id(tokens: &mut SplitWhitespace) -> Result<UnitId, String> {
+    Ok(UnitId{id: parse_number(next_token(tokens)?)?})
+}
+
+fn parse_map_pos_parts(x: &str, y: &str) -> Result<MapPos, String> {
+    Ok(MapPos{v: Vector2{x: parse_number(x)?, y: parse_number(y)?}})
+}
+
+fn parse_map_pos(tokens: &mut SplitWhitespace) -> Result<MapPos, String> {
+    let token = next_token(tokens)?;
+    let parts: Vec<_> = token.split(',').collect();
+    if parts.len() != 2 {
+        return Err(format!("Bad position: {}", token));
+    }
+    parse_map_pos_parts(parts[0], parts[1])
+}
+
+fn parse_slot_id(s: &str) -> Result<SlotId, String> {
+    match s {
+        "whole" => Ok(SlotId::WholeTile),
+        "air" => Ok(SlotId::Air),
+        _ if s.starts_with("two:") => {
+            let n = parse_number(&s["two:".len()..])?;
+            if !(0..6).contains(&n) {
+                return Err(format!("Bad direction: {}", n));
+            }
+            Ok(SlotId::TwoTiles(Dir::from_int(n)))
+        },
+        _ => s.parse().map(SlotId::Id).map_err(|_| format!("Bad slot: {}", s)),
+    }
+}
+
+fn parse_exact_pos_token(token: &str) -> Result<ExactPos, String> {
+    let parts: Vec<_> = token.split(',').collect();
+    if parts.len() != 3 {
+        return Err(format!("Bad exact position: {}", token));
+    }
+    Ok(ExactPos {
+        map_pos: parse_map_pos_parts(parts[0], parts[1])?,
+        slot_id: parse_slot_id(parts[2])?,
+    })
+}
+
+fn parse_exact_pos(tokens: &mut SplitWhitespace) -> Result<ExactPos, String> {
+    parse_exact_pos_token(next_token(tokens)?)
+}
+
+fn parse_move_mode(tokens: &mut SplitWhitespace) -> Result<MoveMode, String> {
+    match next_token(tokens)? {
+        "fast" => Ok(MoveMode::Fast),
+        "hunt" => Ok(MoveMode::Hunt),
+        token => Err(format!("Bad move mode: {}", token)),
+    }
+}
+
+fn parse_reaction_fire_mode(
+    tokens: &mut SplitWhitespace,
+) -> Result<ReactionFireMode, String> {
+    match next_token(tokens)? {
+        "normal" => Ok(ReactionFireMode::Normal),
+        "hold_fire" => Ok(ReactionFireMode::HoldFire),
+        "overwatch" => Ok(ReactionFireMode::Overwatch{pos: parse_map_pos(tokens)?}),
+        "ambush" => {
+            let distance = parse_number(next_token(tokens)?)?;
+            Ok(ReactionFireMode::Ambush{distance: distance})
+        },
+        token => Err(format!("Bad reaction fire mode: {}", token)),
+    }
+}
+
+fn parse_support_type(tokens: &mut SplitWhitespace) -> Result<SupportType, String> {
+    match next_token(tokens)? {
+        "artillery" => Ok(SupportType::Artillery),
+        "air_strike" => Ok(SupportType::AirStrike),
+        token => Err(format!("Bad support type: {}", token)),
+    }
+}
+
+/// Reads a command in the format described in the module docs
+pub fn parse_command(db: &Db, line: &str) -> Result<Command, String> {
+    let mut tokens = line.split_whitespace();
+    let tokens = &mut tokens;
+    let command = match next_token(tokens)? {
+        "end_turn" => Command::EndTurn,
+        "move" => {
+            let unit_id = parse_unit_id(tokens)?;
+            let mode = parse_move_mode(tokens)?;
+            let path = tokens.map(parse_exact_pos_token).collect::<Result<Vec<_>, _>>()?;
+            if path.len() < 2 {
+                return Err("Path is too short".to_owned());
+            }
+            Command::Move{unit_id: unit_id, path: path, mode: mode}
+        },
+        "group_move" => {
+            let mode = parse_move_mode(tokens)?;
+            let pos = parse_map_pos(tokens)?;
+            let unit_ids = tokens
+                .map(|token| parse_number(token).map(|id| UnitId{id: id}))
+                .collect::<Result<Vec<_>, _>>()?;
+            Command::GroupMove{unit_ids: unit_ids, pos: pos, mode: mode}
+        },
+        "create_unit" => {
+            let name = next_token(tokens)?;
+            let type_id = match db.unit_type_id_opt(name) {
+                Some(type_id) => type_id,
+                None => return Err(format!("Unknown unit type: {}", name)),
+            };
+            Command::CreateUnit{type_id: type_id, pos: parse_exact_pos(tokens)?}
+        },
+        "attack" => Command::AttackUnit {
+            attacker_id: parse_unit_id(tokens)?,
+            defender_id: parse_unit_id(tokens)?,
+        },
+        "area_fire" => Command::AreaFire {
+            unit_id: parse_unit_id(tokens)?,
+            pos: parse_map_pos(tokens)?,
+        },
+        "load" => Command::LoadUnit {
+            transporter_id: parse_unit_id(tokens)?,
+            passenger_id: parse_unit_id(tokens)?,
+        },
+        "unload" => Command::UnloadUnit {
+            transporter_id: parse_unit_id(tokens)?,
+            passenger_id: parse_unit_id(tokens)?,
+            pos: parse_exact_pos(tokens)?,
+        },
+        "reaction_fire" => Command::SetReactionFireMode {
+            unit_id: parse_unit_id(tokens)?,
+            mode: parse_reaction_fire_mode(tokens)?,
+        },
+        "smoke" => Command::Smoke {
+            unit_id: parse_unit_id(tokens)?,
+            pos: parse_map_pos(tokens)?,
+        },
+        "dig_in" => Command::DigIn{unit_id: parse_unit_id(tokens)?},
+        "build_bridge" => Command::BuildBridge {
+            unit_id: parse_unit_id(tokens)?,
+            pos: parse_map_pos(tokens)?,
+        },
+        "build_entrenchment" => Command::BuildEntrenchment {
+            unit_id: parse_unit_id(tokens)?,
+            pos: parse_map_pos(tokens)?,
+        },
+        "place_minefield" => Command::PlaceMinefield {
+            unit_id: parse_unit_id(tokens)?,
+            pos: parse_map_pos(tokens)?,
+        },
+        "clear_minefield" => Command::ClearMinefield {
+            unit_id: parse_unit_id(tokens)?,
+            pos: parse_map_pos(tokens)?,
+        },
+        "call_support" => Command::CallSupport {
+            support_type: parse_support_type(tokens)?,
+            pos: parse_map_pos(tokens)?,
+        },
+        "undo" => Command::Undo,
+        name => return Err(format!("Unknown command: {}", name)),
+    };
+    if let Some(token) = tokens.next() {
+        return Err(format!("Unexpected argument: {}", token));
+    }
+    Ok(command)
+}
+
+fn format_map_pos(pos: MapPos) -> String {
+    format!("{},{}", pos.v.x, pos.v.y)
+}
+
+fn format_exact_pos(pos: ExactPos) -> String {
+    let slot = match pos.slot_id {
+        SlotId::Id(n) => n.to_string(),
+        SlotId::WholeTile => "whole".to_owned(),
+        SlotId::Air => "air".to_owned(),
+        SlotId::TwoTiles(dir) => format!("two:{}", dir.to_int()),
+    };
+    format!("{},{}", format_map_pos(pos.map_pos), slot)
+}
+
+fn format_option<T: ToString>(value: Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "-".to_owned(),
+    }
+}
+
+fn format_move_mode(mode: MoveMode) -> &'static str {
+    match mode {
+        MoveMode::Fast => "fast",
+        MoveMode::Hunt => "hunt",
+    }
+}
+
+fn format_reaction_fire_mode(mode: ReactionFireMode) -> String {
+    match mode {
+        ReactionFireMode::Normal => "normal".to_owned(),
+        ReactionFireMode::HoldFire => "hold_fire".to_owned(),
+        ReactionFireMode::Overwatch{pos} => format!("overwatch {}", format_map_pos(pos)),
+        ReactionFireMode::Ambush{distance} => format!("ambush {}", distance),
+    }
+}
+
+fn format_object_class(class: ObjectClass) -> &'static str {
+    match class {
+        ObjectClass::Building => "building",
+        ObjectClass::Road => "road",
+        ObjectClass::Smoke => "smoke",
+        ObjectClass::ReinforcementSector => "reinforcement_sector",
+        ObjectClass::Minefield => "minefield",
+        ObjectClass::BarbedWire => "barbed_wire",
+        ObjectClass::TankTraps => "tank_traps",
+        ObjectClass::Bridge => "bridge",
+        ObjectClass::Entrenchment => "entrenchment",
+    }
+}
+
+fn format_support_call(call: &SupportCall) -> String {
+    let support_type = match call.support_type {
+        SupportType::Artillery => "artillery",
+        SupportType::AirStrike => "air_strike",
+    };
+    format!("{} {} {}", call.player_id.id, support_type, format_map_pos(call.pos))
+}
+
+fn format_unit_info(db: &Db, unit_info: &UnitInfo) -> String {
+    format!(
+        "{} {} {} {} {} {}",
+        unit_info.unit_id.id,
+        db.unit_type(unit_info.type_id).name,
+        unit_info.player_id.id,
+        format_exact_pos(unit_info.pos),
+        format_option(unit_info.passenger_id.map(|id| id.id)),
+        unit_info.is_entrenched,
+    )
+}
+
+/// Writes an event in the format described in the module docs
+pub fn format_event(db: &Db, event: &CoreEvent) -> String {
+    match *event {
+        CoreEvent::Move{unit_id, from, to, mode, cost} => format!(
+            "move {} {} {} {} {}",
+            unit_id.id,
+            format_exact_pos(from),
+            format_exact_pos(to),
+            format_move_mode(mode),
+            cost.n,
+        ),
+        CoreEvent::UndoMove{unit_id, from, to, cost, move_mode, is_entrenched} => format!(
+            "undo_move {} {} {} {} {} {}",
+            unit_id.id,
+            format_exact_pos(from),
+            format_exact_pos(to),
+            cost.n,
+            format_option(move_mode.map(format_move_mode)),
+            is_entrenched,
+        ),
+        CoreEvent::EndTurn{old_id, new_id, is_new_round} => format!(
+            "end_turn {} {} {}", old_id.id, new_id.id, is_new_round),
+        CoreEvent::CreateUnit{ref unit_info} => format!(
+            "create_unit {}", format_unit_info(db, unit_info)),
+        CoreEvent::AttackUnit{ref attack_info} => format!(
+            "attack {} {} {} {} {} {} {} {} {}",
+            format_option(attack_info.attacker_id.map(|id| id.id)),
+            attack_info.defender_id.id,
+            match attack_info.mode {
+                FireMode::Active => "active",
+                FireMode::Reactive => "reactive",
+            },
+            attack_info.killed,
+            attack_info.suppression,
+            attack_info.remove_move_points,
+            attack_info.is_ambush,
+            attack_info.is_inderect,
+            attack_info.is_return_fire,
+        ),
+        CoreEvent::AreaFire{attacker_id, pos} => format!(
+            "area_fire {} {}",
+            format_option(attacker_id.map(|id| id.id)),
+            format_map_pos(pos),
+        ),
+        CoreEvent::ShowUnit{ref unit_info} => format!(
+            "show_unit {}", format_unit_info(db, unit_info)),
+        CoreEvent::HideUnit{unit_id} => format!("hide_unit {}", unit_id.id),
+        CoreEvent::LoadUnit{transporter_id, passenger_id, from, to} => format!(
+            "load {} {} {} {}",
+            format_option(transporter_id.map(|id| id.id)),
+            passenger_id.id,
+            format_exact_pos(from),
+            format_exact_pos(to),
+        ),
+        CoreEvent::UnloadUnit{ref unit_info, transporter_id, from, to} => format!(
+            "unload {} {} {} {}",
+            format_option(transporter_id.map(|id| id.id)),
+            format_exact_pos(from),
+            format_exact_pos(to),
+            format_unit_info(db, unit_info),
+        ),
+        CoreEvent::SetReactionFireMode{unit_id, mode} => format!(
+            "reaction_fire {} {}", unit_id.id, format_reaction_fire_mode(mode)),
+        CoreEvent::DigIn{unit_id} => format!("dig_in {}", unit_id.id),
+        CoreEvent::Entrench{unit_id} => format!("entrench {}", unit_id.id),
+        CoreEvent::SectorOwnerChanged{sector_id, new_owner_id} => format!(
+            "sector_owner {} {}",
+            sector_id.id,
+            format_option(new_owner_id.map(|id| id.id)),
+        ),
+        CoreEvent::VictoryPoint{player_id, pos, count} => format!(
+            "victory_point {} {} {}", player_id.id, format_map_pos(pos), count),
+        CoreEvent::Smoke{id, pos, unit_id} => format!(
+            "smoke {} {} {}",
+            id.id,
+            format_map_pos(pos),
+            format_option(unit_id.map(|id| id.id)),
+        ),
+        CoreEvent::RemoveSmoke{id} => format!("remove_smoke {}", id.id),
+        CoreEvent::RevealObject{id, ref object} => format!(
+            "reveal_object {} {} {} {} {} {}",
+            id.id,
+            format_object_class(object.class),
+            format_exact_pos(object.pos),
+            format_option(object.owner_id.map(|id| id.id)),
+            format_option(object.timer),
+            format_option(object.progress),
+        ),
+        CoreEvent::Construct{id, pos, class, unit_id, progress} => format!(
+            "construct {} {} {} {} {}",
+            id.id,
+            format_object_class(class),
+            format_map_pos(pos),
+            format_option(unit_id.map(|id| id.id)),
+            format_option(progress),
+        ),
+        CoreEvent::ClearMinefield{id, unit_id} => format!(
+            "clear_minefield {} {}",
+            id.id,
+            format_option(unit_id.map(|id| id.id)),
+        ),
+        CoreEvent::CallSupport{ref call} => format!(
+            "call_support {}", format_support_call(call)),
+        CoreEvent::SupportStrike{ref call} => format!(
+            "support_strike {}", format_support_call(call)),
+    }
+}
+
+pub struct ExternalBot {
+    id: PlayerId,
+    process: Option<Child>,
+    input: Box<BufRead>,
+    output: Box<Write>,
+}
+
+impl ExternalBot {
+    /// Talks to a bot through the given streams
+    pub fn new(
+        id: PlayerId,
+        map_name: &str,
+        input: Box<BufRead>,
+        output: Box<Write>,
+    ) -> ExternalBot {
+        let mut bot = ExternalBot {
+            id: id,
+            process: None,
+            input: input,
+            output: output,
+        };
+        bot.send(&format!("player {} {}", id.id, map_name));
+        bot
+    }
+
+    /// Starts a bot process, `command_line` is the program
+    /// followed by its arguments
+    pub fn spawn(
+        id: PlayerId,
+        map_name: &str,
+        command_line: &[String],
+    ) -> io::Result<ExternalBot> {
+        let (program, args) = match command_line.split_first() {
+            Some(program_and_args) => program_and_args,
+            None => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput, "Empty bot command line")),
+        };
+        let mut process = process::Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let input = BufReader::new(process.stdout.take().unwrap());
+        let output = process.stdin.take().unwrap();
+        let mut bot = ExternalBot::new(id, map_name, Box::new(input), Box::new(output));
+        bot.process = Some(process);
+        Ok(bot)
+    }
+
+    fn send(&mut self, line: &str) {
+        // a dead bot is noticed when its output ends
+        let _ = writeln!(self.output, "{}", line);
+    }
+
+    fn receive(&mut self) -> Option<String> {
+        let _ = self.output.flush();
+        let mut line = String::new();
+        match self.input.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line),
+        }
+    }
+}
+
+impl fmt::Debug for ExternalBot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExternalBot")
+            .field("id", &self.id)
+            .field("process", &self.process)
+            .finish()
+    }
+}
+
+impl Drop for ExternalBot {
+    fn drop(&mut self) {
+        if let Some(ref mut process) = self.process {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+}
+
+impl Bot for ExternalBot {
+    fn apply_event(&mut self, db: &Db, event: &CoreEvent) {
+        let line = format_event(db, event);
+        self.send(&format!("event {}", line));
+    }
+
+    /// A bot that keeps sending lines that can't be parsed ends its turn
+    fn get_command(&mut self, db: &Db) -> Command {
+        for _ in 0..MAX_BOT_ERRORS {
+            self.send("turn");
+            let line = match self.receive() {
+                Some(line) => line,
+                None => return Command::EndTurn,
+            };
+            match parse_command(db, &line) {
+                Ok(command) => return command,
+                Err(message) => self.send(&format!("error {}", message)),
+            }
+        }
+        Command::EndTurn
+    }
+
+    fn report_error(&mut self, _: &Db, _: &Command, error: &CommandError) {
+        self.send(&format!("error {}", error));
+    }
+}
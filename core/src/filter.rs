@@ -1,20 +1,42 @@
-use std::collections::{HashMap, HashSet};
+use std::cmp;
+use std::collections::{HashSet};
 use internal_state::{InternalState};
 use game_state::{GameState};
 use unit::{Unit};
 use db::{Db};
-use fow::{Fow};
-use ::{CoreEvent, AttackInfo, UnitInfo, UnitId, PlayerId, unit_to_info};
+use fow::{Fow, TileVisibility};
+use partial_state::{PartialState};
+use ::{
+    CoreEvent,
+    AttackInfo,
+    UnitInfo,
+    UnitId,
+    Object,
+    ObjectClass,
+    unit_to_info,
+};
+
+fn is_own_object(fow: &Fow, object: &Object) -> bool {
+    match object.owner_id {
+        Some(owner_id) => fow.is_own(owner_id),
+        None => false,
+    }
+}
+
+/// Enemy minefields are hidden until they are discovered
+pub fn is_object_hidden(object: &Object, fow: &Fow) -> bool {
+    object.class == ObjectClass::Minefield
+        && !is_own_object(fow, object)
+}
 
 pub fn get_visible_enemies(
     db: &Db,
     state: &InternalState,
     fow: &Fow,
-    player_id: PlayerId,
 ) -> HashSet<UnitId> {
     let mut visible_enemies = HashSet::new();
     for (&id, unit) in state.units() {
-        if unit.player_id != player_id
+        if !fow.is_own(unit.player_id)
             && fow.is_visible(db, state, unit, unit.pos)
         {
             visible_enemies.insert(id);
@@ -23,8 +45,60 @@ pub fn get_visible_enemies(
     visible_enemies
 }
 
+/// Enemies can tell that a unit is entrenched only from a close look
+fn is_entrenchment_visible(fow: &Fow, unit: &Unit) -> bool {
+    fow.is_own(unit.player_id)
+        || fow.tile_visibility(unit.pos.map_pos) == TileVisibility::Excellent
+}
+
+/// Entrenchments of known enemy units that the player
+/// can tell only now, when their tiles are seen closely
+pub fn reveal_entrenchments(
+    state: &InternalState,
+    fow: &Fow,
+    known_state: &PartialState,
+) -> Vec<CoreEvent> {
+    let mut events = Vec::new();
+    for known_unit in known_state.units().values() {
+        if fow.is_own(known_unit.player_id) || known_unit.is_entrenched {
+            continue;
+        }
+        let unit = state.unit(known_unit.id);
+        if unit.is_entrenched && is_entrenchment_visible(fow, unit) {
+            events.push(CoreEvent::Entrench{unit_id: unit.id});
+        }
+    }
+    events
+}
+
+/// Losses out of the player's sight are not shown, so the player
+/// may think that the defender has more soldiers than it has.
+/// If the defender is killed, it's killed in every view.
+fn known_killed_count(
+    known_state: &PartialState,
+    attack_info: &AttackInfo,
+    is_killed: bool,
+) -> i32 {
+    match known_state.units().get(&attack_info.defender_id) {
+        Some(defender) if is_killed => cmp::max(attack_info.killed, defender.count),
+        _ => attack_info.killed,
+    }
+}
+
+fn unit_to_filtered_info(fow: &Fow, unit: &Unit) -> UnitInfo {
+    UnitInfo {
+        is_entrenched: unit.is_entrenched
+            && is_entrenchment_visible(fow, unit),
+        .. unit_to_info(unit)
+    }
+}
+
+/// `known_state` is what the player knows with the event applied,
+/// units that were shown or hidden by the event itself are in it already
 pub fn show_or_hide_passive_enemies(
-    units: &HashMap<UnitId, Unit>,
+    state: &InternalState,
+    fow: &Fow,
+    known_state: &PartialState,
     active_unit_ids: &HashSet<UnitId>,
     old: &HashSet<UnitId>,
     new: &HashSet<UnitId>,
@@ -32,17 +106,17 @@ pub fn show_or_hide_passive_enemies(
     let mut events = Vec::new();
     let located_units = new.difference(old);
     for id in located_units {
-        if active_unit_ids.contains(id) {
+        if active_unit_ids.contains(id) || known_state.units().contains_key(id) {
             continue;
         }
-        let unit = units.get(id).expect("Can`t find unit");
+        let unit = state.units().get(id).expect("Can`t find unit");
         events.push(CoreEvent::ShowUnit {
-            unit_info: unit_to_info(unit),
+            unit_info: unit_to_filtered_info(fow, unit),
         });
     }
     let lost_units = old.difference(new);
     for &id in lost_units {
-        if active_unit_ids.contains(&id) {
+        if active_unit_ids.contains(&id) || !known_state.units().contains_key(&id) {
             continue;
         }
         events.push(CoreEvent::HideUnit{unit_id: id});
@@ -50,29 +124,75 @@ pub fn show_or_hide_passive_enemies(
     events
 }
 
+/// Finished objects that were built out of the player's sight
+/// and are seen only now. Minefields are found only by stepping
+/// on them or clearing them.
+pub fn reveal_objects(
+    state: &InternalState,
+    fow: &Fow,
+    known_state: &PartialState,
+) -> Vec<CoreEvent> {
+    let mut events = Vec::new();
+    for (&id, object) in state.objects() {
+        if object.class == ObjectClass::Minefield
+            || !object.is_complete()
+            || known_state.objects().contains_key(&id)
+            || !fow.is_tile_visible(object.pos.map_pos)
+        {
+            continue;
+        }
+        events.push(CoreEvent::RevealObject {
+            id: id,
+            object: object.clone(),
+        });
+    }
+    events
+}
+
+fn visible_unit_id(
+    db: &Db,
+    state: &InternalState,
+    fow: &Fow,
+    unit_id: UnitId,
+) -> Option<UnitId> {
+    let unit = state.unit(unit_id);
+    if fow.is_own(unit.player_id)
+        || fow.is_visible(db, state, unit, unit.pos)
+    {
+        Some(unit_id)
+    } else {
+        None
+    }
+}
+
+/// Filtered events and ids of units that are shown by them
+pub type FilteredEvents = (Vec<CoreEvent>, HashSet<UnitId>);
+
 // TODO: join state and fow into TmpPartialState
+/// `known_state` is what the player knew before the event
 pub fn filter_events(
     db: &Db,
     state: &InternalState,
-    player_id: PlayerId,
     fow: &Fow,
+    known_state: &PartialState,
     event: &CoreEvent,
-) -> (Vec<CoreEvent>, HashSet<UnitId>) {
+) -> FilteredEvents {
     let mut active_unit_ids = HashSet::new();
     let mut events = vec![];
     match *event {
         CoreEvent::Move{unit_id, from, to, ..} => {
             let unit = state.unit(unit_id);
-            if unit.player_id == player_id {
+            if fow.is_own(unit.player_id) {
                 events.push(event.clone())
             } else {
-                let prev_vis = fow.is_visible(db, state, unit, from);
+                // the unit is already moved and can be hunting now
+                let prev_vis = known_state.units().contains_key(&unit_id);
                 let next_vis = fow.is_visible(db, state, unit, to);
                 if !prev_vis && next_vis {
                     events.push(CoreEvent::ShowUnit {
                         unit_info: UnitInfo {
                             pos: from,
-                            .. unit_to_info(unit)
+                            .. unit_to_filtered_info(fow, unit)
                         },
                     });
                 }
@@ -89,7 +209,7 @@ pub fn filter_events(
         },
         CoreEvent::CreateUnit{ref unit_info} => {
             let unit = state.unit(unit_info.unit_id);
-            if player_id == unit_info.player_id
+            if fow.is_own(unit_info.player_id)
                 || fow.is_visible(db, state, unit, unit_info.pos)
             {
                 events.push(event.clone());
@@ -97,32 +217,76 @@ pub fn filter_events(
             }
         },
         CoreEvent::AttackUnit{ref attack_info} => {
-            let attacker_id = attack_info.attacker_id
-                .expect("Core must know about everything");
+            let attacker_id = match attack_info.attacker_id {
+                Some(id) => id,
+                None => {
+                    // attacks without attacker (mines) are seen only
+                    // by those who can see the defender
+                    let defender = state.unit(attack_info.defender_id);
+                    if fow.is_own(defender.player_id)
+                        || fow.is_visible(db, state, defender, defender.pos)
+                    {
+                        // this event is filtered before it is applied
+                        let is_killed = defender.count <= attack_info.killed;
+                        active_unit_ids.insert(attack_info.defender_id);
+                        events.push(CoreEvent::AttackUnit {
+                            attack_info: AttackInfo {
+                                killed: known_killed_count(
+                                    known_state, attack_info, is_killed),
+                                .. attack_info.clone()
+                            },
+                        });
+                    }
+                    return (events, active_unit_ids);
+                },
+            };
             let attacker = state.unit(attacker_id);
-            if player_id != attacker.player_id && !attack_info.is_ambush {
+            if !fow.is_own(attacker.player_id) && !attack_info.is_ambush {
                 // show attacker if this is not ambush
-                let attacker = state.unit(attacker_id);
-                if !fow.is_visible(db, state, attacker, attacker.pos) {
+                if !known_state.units().contains_key(&attacker_id) {
                     events.push(CoreEvent::ShowUnit {
-                        unit_info: unit_to_info(attacker),
+                        unit_info: unit_to_filtered_info(fow, attacker),
                     });
                 }
                 active_unit_ids.insert(attacker_id);
             }
             active_unit_ids.insert(attack_info.defender_id); // if defender is killed
-            let is_attacker_visible = player_id == attacker.player_id
+            let is_attacker_visible = fow.is_own(attacker.player_id)
                 || !attack_info.is_ambush;
+            let is_killed = !state.units().contains_key(&attack_info.defender_id);
             let attack_info = AttackInfo {
                 attacker_id: if is_attacker_visible {
                     Some(attacker_id)
                 } else {
                     None
                 },
+                killed: known_killed_count(known_state, attack_info, is_killed),
                 .. attack_info.clone()
             };
             events.push(CoreEvent::AttackUnit{attack_info: attack_info});
         },
+        CoreEvent::AreaFire{attacker_id, pos} => {
+            let attacker_id = attacker_id.expect("Core must know about everything");
+            if fow.is_own(state.unit(attacker_id).player_id) {
+                events.push(event.clone());
+            } else {
+                // the enemy learns only where the shells fall
+                events.push(CoreEvent::AreaFire {
+                    attacker_id: None,
+                    pos: pos,
+                });
+            }
+        },
+        CoreEvent::CallSupport{ref call} => {
+            if fow.is_own(call.player_id) {
+                events.push(event.clone());
+            }
+        },
+        CoreEvent::SupportStrike{ref call} => {
+            if fow.is_own(call.player_id) || fow.is_tile_visible(call.pos) {
+                events.push(event.clone());
+            }
+        },
         CoreEvent::ShowUnit{..} => panic!(),
         CoreEvent::HideUnit{..} => panic!(),
         CoreEvent::LoadUnit{passenger_id, from, to, transporter_id} => {
@@ -132,14 +296,14 @@ pub fn filter_events(
                 db, state, transporter, transporter.pos);
             let is_passenger_vis = fow.is_visible(
                 db, state, passenger, from);
-            if passenger.player_id == player_id {
+            if fow.is_own(passenger.player_id) {
                 events.push(event.clone());
             } else if is_passenger_vis || is_transporter_vis {
                 if !fow.is_visible(db, state, passenger, from) {
                     events.push(CoreEvent::ShowUnit {
                         unit_info: UnitInfo {
                             pos: from,
-                            .. unit_to_info(passenger)
+                            .. unit_to_filtered_info(fow, passenger)
                         },
                     });
                 }
@@ -165,7 +329,7 @@ pub fn filter_events(
                 db, state, transporter, from);
             let is_passenger_vis = fow.is_visible(
                 db, state, passenger, to);
-            if passenger.player_id == player_id {
+            if fow.is_own(passenger.player_id) {
                 events.push(event.clone());
             } else if is_passenger_vis || is_transporter_vis {
                 let filtered_transporter_id = if is_transporter_vis {
@@ -188,7 +352,16 @@ pub fn filter_events(
         },
         CoreEvent::SetReactionFireMode{unit_id, ..} => {
             let unit = state.unit(unit_id);
-            if unit.player_id == player_id {
+            if fow.is_own(unit.player_id) {
+                events.push(event.clone());
+            }
+        },
+        CoreEvent::DigIn{unit_id} | CoreEvent::Entrench{unit_id} => {
+            let unit = state.unit(unit_id);
+            if fow.is_own(unit.player_id)
+                || (known_state.units().contains_key(&unit_id)
+                    && is_entrenchment_visible(fow, unit))
+            {
                 events.push(event.clone());
             }
         },
@@ -205,6 +378,50 @@ pub fn filter_events(
                 });
             }
         },
+        CoreEvent::RevealObject{ref object, ..} => {
+            if !is_own_object(fow, object)
+                && fow.is_tile_visible(object.pos.map_pos)
+            {
+                events.push(event.clone());
+            }
+        },
+        CoreEvent::Construct{id, pos, class, unit_id, progress} => {
+            let unit_id = unit_id.expect("Core must know about everything");
+            // new minefields are hidden from the enemy
+            let is_hidden = class == ObjectClass::Minefield
+                || !fow.is_tile_visible(pos);
+            if !fow.is_own(state.unit(unit_id).player_id) && is_hidden {
+                return (events, active_unit_ids);
+            }
+            events.push(CoreEvent::Construct {
+                id: id,
+                pos: pos,
+                class: class,
+                unit_id: visible_unit_id(db, state, fow, unit_id),
+                progress: progress,
+            });
+        },
+        CoreEvent::UndoMove{unit_id, ..} => {
+            // is never created if anybody else has noticed the move
+            if fow.is_own(state.unit(unit_id).player_id) {
+                events.push(event.clone());
+            }
+        },
+        CoreEvent::ClearMinefield{id, unit_id} => {
+            // is filtered before the minefield is removed and
+            // anybody who sees the tile got the minefield revealed
+            let unit_id = unit_id.expect("Core must know about everything");
+            let object = &state.objects()[&id];
+            if !is_own_object(fow, object)
+                && !fow.is_tile_visible(object.pos.map_pos)
+            {
+                return (events, active_unit_ids);
+            }
+            events.push(CoreEvent::ClearMinefield {
+                id: id,
+                unit_id: visible_unit_id(db, state, fow, unit_id),
+            });
+        },
         CoreEvent::EndTurn{..} |
         CoreEvent::RemoveSmoke{..} |
         CoreEvent::VictoryPoint{..} |
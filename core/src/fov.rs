@@ -31,7 +31,12 @@ fn is_obstacle<S: GameState>(state: &S, pos: MapPos) -> bool {
             ObjectClass::Building |
             ObjectClass::Smoke => return true,
             ObjectClass::ReinforcementSector |
-            ObjectClass::Road => {},
+            ObjectClass::Road |
+            ObjectClass::Minefield |
+            ObjectClass::BarbedWire |
+            ObjectClass::TankTraps |
+            ObjectClass::Bridge |
+            ObjectClass::Entrenchment => {},
         }
     }
     false
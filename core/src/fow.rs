@@ -6,12 +6,12 @@ use map::{Map, Terrain, distance};
 use fov::{fov, simple_fov};
 use db::{Db};
 use unit::{Unit, UnitType, UnitClass};
-use ::{CoreEvent, PlayerId, MapPos, ExactPos, ObjectClass};
+use ::{CoreEvent, MoveMode, PlayerId, MapPos, ExactPos, ObjectClass, SmokeDensity};
 
 #[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
 pub enum TileVisibility {
     No,
-    // Bad,
+    Bad,
     Normal,
     Excellent,
 }
@@ -74,50 +74,118 @@ fn calc_visibility<S: GameState>(
         Terrain::Plain | Terrain::Water => TileVisibility::Excellent,
     };
     for object in state.objects_at(pos) {
-        match object.class {
+        let object_vis = match object.class {
             // TODO: Removed Terrain::City and Terrain::Trees, use Smoke-like objects in logic
-            ObjectClass::Building | ObjectClass::Smoke => {
-                vis = TileVisibility::Normal;
-            }
+            ObjectClass::Building => TileVisibility::Normal,
+            ObjectClass::Smoke => match object.smoke_density().unwrap() {
+                SmokeDensity::Thick => TileVisibility::Bad,
+                SmokeDensity::Thin => TileVisibility::Normal,
+            },
             ObjectClass::Road |
-            ObjectClass::ReinforcementSector => {},
+            ObjectClass::ReinforcementSector |
+            ObjectClass::Minefield |
+            ObjectClass::BarbedWire |
+            ObjectClass::TankTraps |
+            ObjectClass::Bridge |
+            ObjectClass::Entrenchment => continue,
+        };
+        if object_vis < vis {
+            vis = object_vis;
         }
     }
     vis
 }
 
+fn is_visible_on_tile(unit_type: &UnitType, vis: TileVisibility) -> bool {
+    match vis {
+        TileVisibility::Excellent => true,
+        TileVisibility::Normal => match unit_type.class {
+            UnitClass::Infantry => false,
+            UnitClass::Vehicle => true,
+        },
+        TileVisibility::Bad |
+        TileVisibility::No => false,
+    }
+}
+
+fn is_passenger<S: GameState>(state: &S, unit: &Unit) -> bool {
+    state.units().values()
+        .any(|other| other.passenger_id == Some(unit.id))
+}
+
+/// Checks if `spotter` alone currently sees `unit`,
+/// uses the same rules as `Fow::is_visible`
+pub fn is_spotted_by<S: GameState>(
+    db: &Db,
+    state: &S,
+    spotter: &Unit,
+    unit: &Unit,
+) -> bool {
+    if is_passenger(state, unit) {
+        return false;
+    }
+    let spotter_type = db.unit_type(spotter.type_id);
+    let unit_type = db.unit_type(unit.type_id);
+    let origin = spotter.pos.map_pos;
+    let pos = unit.pos.map_pos;
+    if unit_type.is_air {
+        return distance(origin, pos) <= spotter_type.los_range;
+    }
+    let f = if spotter_type.is_air {
+        simple_fov
+    } else {
+        fov
+    };
+    let mut vis = TileVisibility::No;
+    f(state, origin, spotter_type.los_range, &mut |p| {
+        if p == pos {
+            vis = calc_visibility(state, spotter_type, origin, pos);
+        }
+    });
+    is_visible_on_tile(unit_type, vis)
+}
+
 /// Fog of War
 #[derive(Clone, Debug)]
 pub struct Fow {
     map: Map<TileVisibility>,
-    player_id: PlayerId,
+    player_ids: Vec<PlayerId>,
 }
 
 impl Fow {
     pub fn new(map_size: Size2, player_id: PlayerId) -> Fow {
+        Fow::new_shared(map_size, &[player_id])
+    }
+
+    /// Common view of several players, a tile is visible
+    /// if any of them sees it
+    pub fn new_shared(map_size: Size2, player_ids: &[PlayerId]) -> Fow {
         Fow {
             map: Map::new(map_size),
-            player_id: player_id,
+            player_ids: player_ids.to_vec(),
         }
     }
 
+    /// Checks if the player's units are seen through this fog
+    pub fn is_own(&self, player_id: PlayerId) -> bool {
+        self.player_ids.contains(&player_id)
+    }
+
+    pub fn tile_visibility(&self, pos: MapPos) -> TileVisibility {
+        *self.map.tile(pos)
+    }
+
     pub fn is_tile_visible(&self, pos: MapPos) -> bool {
         match *self.map.tile(pos) {
             TileVisibility::Excellent |
-            TileVisibility::Normal => true,
+            TileVisibility::Normal |
+            TileVisibility::Bad => true,
             TileVisibility::No => false,
         }
     }
 
     fn check_terrain_visibility(&self, unit_type: &UnitType, pos: MapPos) -> bool {
-        match *self.map.tile(pos) {
-            TileVisibility::Excellent => true,
-            TileVisibility::Normal => match unit_type.class {
-                UnitClass::Infantry => false,
-                UnitClass::Vehicle => true,
-            },
-            TileVisibility::No => false,
-        }
+        is_visible_on_tile(unit_type, *self.map.tile(pos))
     }
 
     pub fn is_visible(
@@ -147,19 +215,43 @@ impl Fow {
                 }
             }
         }
+        if unit.move_mode == Some(MoveMode::Hunt)
+            && !self.is_observed_closely(db, state, pos.map_pos)
+        {
+            return false;
+        }
         self.check_terrain_visibility(unit_type, pos.map_pos)
     }
 
+    /// Hunting units are spotted only from half of the usual range
+    fn is_observed_closely(
+        &self,
+        db: &Db,
+        state: &InternalState,
+        pos: MapPos,
+    ) -> bool {
+        for unit in state.units().values() {
+            if !self.is_own(unit.player_id) {
+                continue;
+            }
+            let unit_type = db.unit_type(unit.type_id);
+            if distance(unit.pos.map_pos, pos) <= unit_type.los_range / 2 {
+                return true;
+            }
+        }
+        false
+    }
+
     fn clear(&mut self) {
         for pos in self.map.get_iter() {
             *self.map.tile_mut(pos) = TileVisibility::No;
         }
     }
 
-    fn reset(&mut self, db: &Db, state: &InternalState) {
+    pub fn reset(&mut self, db: &Db, state: &InternalState) {
         self.clear();
         for unit in state.units().values() {
-            if unit.player_id == self.player_id {
+            if self.is_own(unit.player_id) {
                 fov_unit(db, state, &mut self.map, unit);
             }
         }
@@ -174,19 +266,25 @@ impl Fow {
         match *event {
             CoreEvent::Move{unit_id, to, ..} => {
                 let unit = state.unit(unit_id);
-                if unit.player_id == self.player_id {
+                if self.is_own(unit.player_id) {
                     fov_unit_in_pos(
                         db, state, &mut self.map, unit, to.map_pos);
                 }
             },
+            CoreEvent::UndoMove{unit_id, ..} => {
+                // tiles seen from the undone path are forgotten
+                if self.is_own(state.unit(unit_id).player_id) {
+                    self.reset(db, state);
+                }
+            },
             CoreEvent::EndTurn{new_id, ..} => {
-                if self.player_id == new_id {
+                if self.is_own(new_id) {
                     self.reset(db, state);
                 }
             },
             CoreEvent::CreateUnit{ref unit_info} => {
                 let unit = state.unit(unit_info.unit_id);
-                if self.player_id == unit_info.player_id {
+                if self.is_own(unit_info.player_id) {
                     fov_unit(db, state, &mut self.map, unit);
                 }
             },
@@ -200,7 +298,7 @@ impl Fow {
                 }
             },
             CoreEvent::UnloadUnit{ref unit_info, ..} => {
-                if self.player_id == unit_info.player_id {
+                if self.is_own(unit_info.player_id) {
                     let unit = state.unit(unit_info.unit_id);
                     let pos = unit_info.pos.map_pos;
                     fov_unit_in_pos(db, state, &mut self.map, unit, pos);
@@ -210,9 +308,17 @@ impl Fow {
             CoreEvent::HideUnit{..} |
             CoreEvent::LoadUnit{..} |
             CoreEvent::SetReactionFireMode{..} |
+            CoreEvent::DigIn{..} |
+            CoreEvent::AreaFire{..} |
+            CoreEvent::CallSupport{..} |
+            CoreEvent::SupportStrike{..} |
+            CoreEvent::Entrench{..} |
             CoreEvent::SectorOwnerChanged{..} |
             CoreEvent::Smoke{..} |
             CoreEvent::RemoveSmoke{..} |
+            CoreEvent::RevealObject{..} |
+            CoreEvent::Construct{..} |
+            CoreEvent::ClearMinefield{..} |
             CoreEvent::VictoryPoint{..} => {},
         }
     }
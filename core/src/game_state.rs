@@ -2,7 +2,19 @@ use std::collections::{HashMap};
 use unit::{Unit};
 use db::{Db};
 use map::{Map, Terrain};
-use ::{CoreEvent, UnitId, ObjectId, Object, MapPos, Sector, SectorId, PlayerId, Score, objects_at};
+use ::{
+    CoreEvent,
+    UnitId,
+    ObjectId,
+    Object,
+    MapPos,
+    Sector,
+    SectorId,
+    PlayerId,
+    Score,
+    SupportCall,
+    objects_at,
+};
 
 pub trait GameState {
     fn map(&self) -> &Map<Terrain>;
@@ -11,6 +23,12 @@ pub trait GameState {
     fn sectors(&self) -> &HashMap<SectorId, Sector>;
     fn score(&self) -> &HashMap<PlayerId, Score>;
     fn reinforcement_points(&self) -> &HashMap<PlayerId, i32>;
+    fn support_points(&self) -> &HashMap<PlayerId, i32>;
+    fn support_calls(&self) -> &[SupportCall];
+
+    /// Checks if the unit is seen by the player whose view
+    /// this is, the full state hides nothing
+    fn is_unit_visible(&self, db: &Db, unit: &Unit) -> bool;
 
     fn unit(&self, id: UnitId) -> &Unit {
         &self.units()[&id]
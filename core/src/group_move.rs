@@ -0,0 +1,77 @@
+use std::iter;
+use db::{Db};
+use game_state::{GameStateMut};
+use pathfinder::{Pathfinder, truncate_path, path_cost};
+use map::{distance, spiral_iter};
+use ::{
+    CoreEvent,
+    MovePoints,
+    MoveMode,
+    UnitId,
+    MapPos,
+    ExactPos,
+    move_cost_modifier,
+    get_free_exact_pos,
+};
+
+/// Members of a group stop anywhere within this distance from the destination
+pub const GROUP_MOVE_RADIUS: i32 = 1;
+
+/// Plans paths for a group of units heading to the same area.
+///
+/// Units closest to the destination go first, so they don't block
+/// the others. Every path is planned over a copy of the state where
+/// all previous members have already moved, so the slots never conflict.
+pub fn plan_group_move<S: GameStateMut + Clone>(
+    db: &Db,
+    state: &S,
+    unit_ids: &[UnitId],
+    destination: MapPos,
+    mode: MoveMode,
+) -> Vec<(UnitId, Vec<ExactPos>)> {
+    let mut unit_ids = unit_ids.to_vec();
+    unit_ids.sort_by_key(|&id| {
+        distance(state.unit(id).pos.map_pos, destination)
+    });
+    let mut state = state.clone();
+    let mut pathfinder = Pathfinder::new(state.map().size());
+    let mut plan = Vec::new();
+    for unit_id in unit_ids {
+        let (path, cost) = {
+            let unit = state.unit(unit_id);
+            if distance(unit.pos.map_pos, destination) <= GROUP_MOVE_RADIUS {
+                continue;
+            }
+            pathfinder.fill_map(db, &state, unit);
+            let paths: Vec<_> = iter::once(destination)
+                .chain(spiral_iter(destination, GROUP_MOVE_RADIUS))
+                .filter(|&pos| state.map().is_inboard(pos))
+                .filter_map(|pos| get_free_exact_pos(db, &state, unit.type_id, pos))
+                .filter_map(|pos| pathfinder.get_path(pos))
+                .filter_map(|path| truncate_path(db, &state, &path, unit, mode)
+                    .map(|truncated| (path, truncated)))
+                .collect();
+            // prefer slots that can be reached during this turn
+            let path = paths.iter()
+                .find(|&(path, truncated)| path.len() == truncated.len())
+                .or_else(|| paths.first());
+            let path = match path {
+                Some((_, truncated)) => truncated.clone(),
+                None => continue,
+            };
+            let cost = MovePoints {
+                n: path_cost(db, &state, unit, &path).n * move_cost_modifier(mode),
+            };
+            (path, cost)
+        };
+        state.apply_event(db, &CoreEvent::Move {
+            unit_id: unit_id,
+            from: path[0],
+            to: *path.last().unwrap(),
+            mode: mode,
+            cost: cost,
+        });
+        plan.push((unit_id, path));
+    }
+    plan
+}
@@ -0,0 +1,211 @@
+use game_state::{GameState};
+use map::{Terrain};
+use unit::{Unit, UnitType, UnitClass, WeaponType};
+use db::{Db};
+use fow::{is_spotted_by};
+use misc::{clamp};
+use ::{MoveMode, MapPos, ObjectClass};
+
+/// Odds of an attack, all chances are in percents
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct HitChance {
+    pub hit: i32,
+    pub pierce: i32,
+    pub wound: i32,
+    pub cover_bonus: i32,
+
+    /// Chance that the attack kills anybody at all
+    pub total: i32,
+
+    pub expected_kills: f32,
+    pub expected_suppression: f32,
+}
+
+impl HitChance {
+    fn new(
+        weapon_type: &WeaponType,
+        defender_type: &UnitType,
+        defender: &Unit,
+        hit_test_v: i32,
+        cover_bonus: i32,
+    ) -> HitChance {
+        let pierce_test_v = 10 + -defender_type.armor + weapon_type.ap;
+        let wound_test_v = 5 -defender_type.toughness + weapon_type.damage;
+        let hit_test_v = clamp(hit_test_v, 0, 10);
+        let pierce_test_v = clamp(pierce_test_v, 0, 10);
+        let wound_test_v = clamp(wound_test_v, 0, 10);
+        let total = (hit_test_v * pierce_test_v * wound_test_v) / 10;
+        assert!(0 <= total);
+        assert!(100 > total);
+        let kills_on_hit = expected_kills_on_hit(defender_type, defender);
+        let chance = total as f32 / 100.0;
+        let expected_suppression
+            = defender_suppression(defender, 0) as f32 * (1.0 - chance)
+            + expected_suppression_on_hit(defender, kills_on_hit) * chance;
+        HitChance {
+            hit: hit_test_v * 10,
+            pierce: pierce_test_v * 10,
+            wound: wound_test_v * 10,
+            cover_bonus: cover_bonus,
+            total: total,
+            expected_kills: kills_on_hit * chance,
+            expected_suppression: expected_suppression,
+        }
+    }
+}
+
+/// Infantry loses from one to four soldiers on a successful hit
+fn expected_kills_on_hit(defender_type: &UnitType, defender: &Unit) -> f32 {
+    match defender_type.class {
+        UnitClass::Infantry => {
+            let sum: i32 = (1..5).map(|n| clamp(n, 1, defender.count)).sum();
+            sum as f32 / 4.0
+        },
+        UnitClass::Vehicle => 1.0,
+    }
+}
+
+fn expected_suppression_on_hit(defender: &Unit, kills: f32) -> f32 {
+    let suppression = attack_suppression(0) as f32
+        + (attack_suppression(1) - attack_suppression(0)) as f32 * kills;
+    if defender.is_entrenched {
+        suppression / 2.0
+    } else {
+        suppression
+    }
+}
+
+pub fn attack_suppression(killed: i32) -> i32 {
+    let base_suppression = 10;
+    let per_death_suppression = 20;
+    base_suppression + per_death_suppression * killed
+}
+
+/// Entrenched units are harder to suppress
+pub fn defender_suppression(defender: &Unit, killed: i32) -> i32 {
+    let suppression = attack_suppression(killed);
+    if defender.is_entrenched {
+        suppression / 2
+    } else {
+        suppression
+    }
+}
+
+fn terrain_cover_bonus<S: GameState>(state: &S, pos: MapPos) -> i32 {
+    match *state.map().tile(pos) {
+        Terrain::Plain | Terrain::Water => 0,
+        Terrain::Trees => 2,
+        Terrain::City => 3,
+    }
+}
+
+fn has_entrenchment<S: GameState>(state: &S, pos: MapPos) -> bool {
+    state.objects_at(pos).iter().any(|object| {
+        object.class == ObjectClass::Entrenchment && object.is_complete()
+    })
+}
+
+const ENTRENCHMENT_COVER_BONUS: i32 = 2;
+
+/// Cover that the tile gives to any infantry unit
+pub fn tile_cover_bonus<S: GameState>(state: &S, pos: MapPos) -> i32 {
+    let mut bonus = terrain_cover_bonus(state, pos);
+    if has_entrenchment(state, pos) {
+        bonus += ENTRENCHMENT_COVER_BONUS;
+    }
+    bonus
+}
+
+pub fn cover_bonus<S: GameState>(db: &Db, state: &S, defender: &Unit) -> i32 {
+    let defender_type = db.unit_type(defender.type_id);
+    if defender_type.class != UnitClass::Infantry {
+        return 0;
+    }
+    let pos = defender.pos.map_pos;
+    if defender.is_entrenched && !has_entrenchment(state, pos) {
+        terrain_cover_bonus(state, pos) + ENTRENCHMENT_COVER_BONUS
+    } else {
+        tile_cover_bonus(state, pos)
+    }
+}
+
+/// Indirect fire adjusted by somebody else's reports is less accurate
+fn spotter_penalty<S: GameState>(
+    db: &Db,
+    state: &S,
+    attacker: &Unit,
+    defender: &Unit,
+) -> i32 {
+    let attacker_type = db.unit_type(attacker.type_id);
+    let weapon_type = db.weapon_type(attacker_type.weapon_type_id);
+    if !weapon_type.is_inderect {
+        return 0;
+    }
+    if is_spotted_by(db, state, attacker, defender) {
+        0
+    } else {
+        2
+    }
+}
+
+/// Units that moved fast are less accurate until the end of the turn
+fn move_penalty(unit: &Unit) -> i32 {
+    if unit.move_mode == Some(MoveMode::Fast) {
+        2
+    } else {
+        0
+    }
+}
+
+/// Chance to hit before the situational penalties
+fn hit_test_v(
+    weapon_type: &WeaponType,
+    weapon_skill: i32,
+    defender_type: &UnitType,
+    cover_bonus: i32,
+) -> i32 {
+    -7 - cover_bonus + defender_type.size + weapon_type.accuracy + weapon_skill
+}
+
+pub fn hit_chance<S: GameState>(
+    db: &Db,
+    state: &S,
+    attacker: &Unit,
+    defender: &Unit,
+) -> HitChance {
+    let attacker_type = db.unit_type(attacker.type_id);
+    let defender_type = db.unit_type(defender.type_id);
+    let weapon_type = db.weapon_type(attacker_type.weapon_type_id);
+    let cover_bonus = cover_bonus(db, state, defender);
+    let spotter_penalty = spotter_penalty(db, state, attacker, defender);
+    let hit_test_v = hit_test_v(
+        weapon_type, attacker_type.weapon_skill, defender_type, cover_bonus)
+        - spotter_penalty - move_penalty(attacker);
+    HitChance::new(
+        weapon_type, defender_type, defender, hit_test_v, cover_bonus)
+}
+
+/// Area attacks are not aimed at any particular unit
+pub fn area_hit_chance<S: GameState>(
+    db: &Db,
+    state: &S,
+    weapon_type: &WeaponType,
+    weapon_skill: i32,
+    defender: &Unit,
+) -> HitChance {
+    let defender_type = db.unit_type(defender.type_id);
+    let cover_bonus = cover_bonus(db, state, defender);
+    let area_fire_penalty = 3;
+    let hit_test_v = hit_test_v(
+        weapon_type, weapon_skill, defender_type, cover_bonus) - area_fire_penalty;
+    HitChance::new(
+        weapon_type, defender_type, defender, hit_test_v, cover_bonus)
+}
+
+pub fn mine_hit_chance(db: &Db, defender: &Unit) -> HitChance {
+    let defender_type = db.unit_type(defender.type_id);
+    let weapon_type = db.weapon_type(db.weapon_type_id("mine"));
+    // not every step on a minefield triggers a mine
+    let hit_test_v = 8;
+    HitChance::new(weapon_type, defender_type, defender, hit_test_v, 0)
+}
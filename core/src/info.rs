@@ -0,0 +1,202 @@
+use std::fmt;
+use game_state::{GameState};
+use map::{Terrain};
+use unit::{Unit, UnitClass};
+use db::{Db};
+use pathfinder::{terrain_cost};
+use hit_chance::{tile_cover_bonus};
+use ::{
+    MovePoints,
+    AttackPoints,
+    ReactionFireMode,
+    MoveMode,
+    PlayerId,
+    UnitId,
+    MapPos,
+    ObjectClass,
+};
+
+#[derive(Clone, Debug)]
+pub struct WeaponDescription {
+    pub name: String,
+    pub damage: i32,
+    pub ap: i32,
+    pub accuracy: i32,
+    pub min_distance: i32,
+    pub max_distance: i32,
+    pub is_inderect: bool,
+}
+
+/// Everything a player knows about some unit.
+/// Points are `None` for enemy units.
+#[derive(Clone, Debug)]
+pub struct UnitDescription {
+    pub unit_id: UnitId,
+    pub player_id: PlayerId,
+    pub type_name: String,
+    pub class: UnitClass,
+    pub count: i32,
+    pub max_count: i32,
+    pub morale: i32,
+    pub move_points: Option<MovePoints>,
+    pub max_move_points: MovePoints,
+    pub attack_points: Option<AttackPoints>,
+    pub max_attack_points: AttackPoints,
+    pub reactive_attack_points: Option<AttackPoints>,
+    pub max_reactive_attack_points: AttackPoints,
+    pub reaction_fire_mode: ReactionFireMode,
+    pub move_mode: Option<MoveMode>,
+    pub is_entrenched: bool,
+    pub is_digging_in: bool,
+    pub passenger: Option<String>,
+    pub size: i32,
+    pub armor: i32,
+    pub toughness: i32,
+    pub weapon_skill: i32,
+    pub los_range: i32,
+    pub weapon: WeaponDescription,
+}
+
+#[derive(Clone, Debug)]
+pub struct TerrainDescription {
+    pub pos: MapPos,
+    pub terrain: Terrain,
+
+    /// Only infantry can use cover
+    pub infantry_cover_bonus: i32,
+
+    /// `None` if the tile can't be entered
+    pub infantry_move_cost: Option<MovePoints>,
+    pub vehicle_move_cost: Option<MovePoints>,
+    pub objects: Vec<ObjectClass>,
+}
+
+pub fn describe_unit<S: GameState>(
+    db: &Db,
+    state: &S,
+    unit: &Unit,
+) -> UnitDescription {
+    let unit_type = db.unit_type(unit.type_id);
+    let weapon_type = db.weapon_type(unit_type.weapon_type_id);
+    let passenger = unit.passenger_id
+        .and_then(|id| state.units().get(&id))
+        .map(|passenger| db.unit_type(passenger.type_id).name.clone());
+    UnitDescription {
+        unit_id: unit.id,
+        player_id: unit.player_id,
+        type_name: unit_type.name.clone(),
+        class: unit_type.class,
+        count: unit.count,
+        max_count: unit_type.count,
+        morale: unit.morale,
+        move_points: unit.move_points,
+        max_move_points: unit_type.move_points,
+        attack_points: unit.attack_points,
+        max_attack_points: unit_type.attack_points,
+        reactive_attack_points: unit.reactive_attack_points,
+        max_reactive_attack_points: unit_type.reactive_attack_points,
+        reaction_fire_mode: unit.reaction_fire_mode,
+        move_mode: unit.move_mode,
+        is_entrenched: unit.is_entrenched,
+        is_digging_in: unit.is_digging_in,
+        passenger: passenger,
+        size: unit_type.size,
+        armor: unit_type.armor,
+        toughness: unit_type.toughness,
+        weapon_skill: unit_type.weapon_skill,
+        los_range: unit_type.los_range,
+        weapon: WeaponDescription {
+            name: weapon_type.name.clone(),
+            damage: weapon_type.damage,
+            ap: weapon_type.ap,
+            accuracy: weapon_type.accuracy,
+            min_distance: weapon_type.min_distance,
+            max_distance: weapon_type.max_distance,
+            is_inderect: weapon_type.is_inderect,
+        },
+    }
+}
+
+pub fn describe_terrain<S: GameState>(state: &S, pos: MapPos) -> TerrainDescription {
+    TerrainDescription {
+        pos: pos,
+        terrain: *state.map().tile(pos),
+        infantry_cover_bonus: tile_cover_bonus(state, pos),
+        infantry_move_cost: terrain_cost(state, UnitClass::Infantry, pos),
+        vehicle_move_cost: terrain_cost(state, UnitClass::Vehicle, pos),
+        objects: state.objects_at(pos).iter()
+            .map(|object| object.class)
+            .collect(),
+    }
+}
+
+fn points_to_string(points: Option<i32>, max: i32) -> String {
+    match points {
+        Some(n) => format!("{}/{}", n, max),
+        None => format!("?/{}", max),
+    }
+}
+
+impl fmt::Display for UnitDescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} ({:?})", self.type_name, self.class)?;
+        writeln!(f, "player: {}", self.player_id.id)?;
+        writeln!(f, "count: {}/{}", self.count, self.max_count)?;
+        writeln!(f, "morale: {}", self.morale)?;
+        writeln!(f, "move points: {}", points_to_string(
+            self.move_points.map(|mp| mp.n), self.max_move_points.n))?;
+        writeln!(f, "attack points: {}", points_to_string(
+            self.attack_points.map(|ap| ap.n), self.max_attack_points.n))?;
+        writeln!(f, "reactive attack points: {}", points_to_string(
+            self.reactive_attack_points.map(|ap| ap.n),
+            self.max_reactive_attack_points.n,
+        ))?;
+        writeln!(f, "reaction fire: {:?}", self.reaction_fire_mode)?;
+        if let Some(move_mode) = self.move_mode {
+            writeln!(f, "moved: {:?}", move_mode)?;
+        }
+        if self.is_entrenched {
+            writeln!(f, "entrenched")?;
+        }
+        if self.is_digging_in {
+            writeln!(f, "digging in")?;
+        }
+        if let Some(ref passenger) = self.passenger {
+            writeln!(f, "passenger: {}", passenger)?;
+        }
+        writeln!(f, "size: {}, armor: {}, toughness: {}",
+            self.size, self.armor, self.toughness)?;
+        writeln!(f, "weapon skill: {}, los range: {}",
+            self.weapon_skill, self.los_range)?;
+        let weapon = &self.weapon;
+        writeln!(f, "weapon: {}{}", weapon.name,
+            if weapon.is_inderect { " (indirect)" } else { "" })?;
+        writeln!(f, "  damage: {}, ap: {}, accuracy: {}",
+            weapon.damage, weapon.ap, weapon.accuracy)?;
+        write!(f, "  range: {}-{}", weapon.min_distance, weapon.max_distance)
+    }
+}
+
+struct MoveCost(Option<MovePoints>);
+
+impl fmt::Display for MoveCost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(cost) => write!(f, "{}", cost.n),
+            None => write!(f, "impassable"),
+        }
+    }
+}
+
+impl fmt::Display for TerrainDescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:?}", self.terrain)?;
+        writeln!(f, "infantry cover: +{}", self.infantry_cover_bonus)?;
+        writeln!(f, "move cost: infantry {}, vehicles {}",
+            MoveCost(self.infantry_move_cost), MoveCost(self.vehicle_move_cost))?;
+        for class in &self.objects {
+            writeln!(f, "{:?}", class)?;
+        }
+        Ok(())
+    }
+}
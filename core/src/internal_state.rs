@@ -22,10 +22,14 @@ use ::{
     Sector,
     SectorId,
     Score,
+    SupportCall,
     MovePoints,
     AttackPoints,
+    SMOKE_TIMER,
     get_free_slot_for_building,
 };
+use filter::{is_object_hidden};
+use fow::{Fow};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum InfoLevel {
@@ -33,6 +37,9 @@ pub enum InfoLevel {
     Partial,
 }
 
+/// Every player gets this many reinforcement points at the end of the turn
+pub const REINFORCEMENT_POINTS_PER_TURN: i32 = 10;
+
 #[derive(Clone, Debug)]
 pub struct InternalState {
     units: HashMap<UnitId, Unit>,
@@ -41,6 +48,8 @@ pub struct InternalState {
     sectors: HashMap<SectorId, Sector>,
     score: HashMap<PlayerId, Score>,
     reinforcement_points: HashMap<PlayerId, i32>, // TODO: i32 -> ???
+    support_points: HashMap<PlayerId, i32>,
+    support_calls: Vec<SupportCall>,
 }
 
 impl InternalState {
@@ -51,6 +60,9 @@ impl InternalState {
         let mut reinforcement_points = HashMap::new();
         reinforcement_points.insert(PlayerId{id: 0}, 10);
         reinforcement_points.insert(PlayerId{id: 1}, 10);
+        let mut support_points = HashMap::new();
+        support_points.insert(PlayerId{id: 0}, 3);
+        support_points.insert(PlayerId{id: 1}, 3);
         let (map, objects, sectors) = load_map(map_name);
         InternalState {
             units: HashMap::new(),
@@ -59,9 +71,16 @@ impl InternalState {
             sectors: sectors,
             score: score,
             reinforcement_points: reinforcement_points,
+            support_points: support_points,
+            support_calls: Vec::new(),
         }
     }
 
+    /// Removes objects that can not be seen through the fog
+    pub fn remove_hidden_objects(&mut self, fow: &Fow) {
+        self.objects.retain(|_, object| !is_object_hidden(object, fow));
+    }
+
     /// Converts active ap (attack points) to reactive
     fn convert_ap(&mut self, db: &Db, player_id: PlayerId) {
         for (_, unit) in &mut self.units {
@@ -103,6 +122,18 @@ impl InternalState {
         }
     }
 
+    /// Engineer work takes the whole turn
+    fn stop_unit_for_work(&mut self, unit_id: UnitId) {
+        if let Some(unit) = self.units.get_mut(&unit_id) {
+            if let Some(ref mut move_points) = unit.move_points {
+                move_points.n = 0;
+            }
+            if let Some(ref mut attack_points) = unit.attack_points {
+                attack_points.n = 0;
+            }
+        }
+    }
+
     fn add_unit(&mut self, db: &Db, unit_info: &UnitInfo, info_level: InfoLevel) {
         assert!(self.units.get(&unit_info.unit_id).is_none());
         let unit_type = db.unit_type(unit_info.type_id);
@@ -113,6 +144,11 @@ impl InternalState {
             return;
         }
         *reinforcement_points -= cost;
+        self.insert_unit(db, unit_info, info_level);
+    }
+
+    fn insert_unit(&mut self, db: &Db, unit_info: &UnitInfo, info_level: InfoLevel) {
+        let unit_type = db.unit_type(unit_info.type_id);
         self.units.insert(unit_info.unit_id, Unit {
             id: unit_info.unit_id,
             pos: unit_info.pos,
@@ -141,8 +177,33 @@ impl InternalState {
             } else {
                 None
             },
+            is_entrenched: unit_info.is_entrenched,
+            move_mode: None,
+            is_digging_in: false,
         });
     }
+
+    /// Puts an enemy unit where it's only guessed to be, for free
+    pub fn add_guessed_unit(&mut self, db: &Db, unit_info: &UnitInfo) {
+        assert!(!self.units.contains_key(&unit_info.unit_id));
+        self.insert_unit(db, unit_info, InfoLevel::Partial);
+    }
+
+    /// Assumes that enemy units we know only partially have all their points
+    pub fn guess_unknown_points(&mut self, db: &Db) {
+        for unit in self.units.values_mut() {
+            let unit_type = db.unit_type(unit.type_id);
+            if unit.move_points.is_none() {
+                unit.move_points = Some(unit_type.move_points);
+            }
+            if unit.attack_points.is_none() {
+                unit.attack_points = Some(unit_type.attack_points);
+            }
+            if unit.reactive_attack_points.is_none() {
+                unit.reactive_attack_points = Some(unit_type.reactive_attack_points);
+            }
+        }
+    }
 }
 
 impl GameState for InternalState {
@@ -169,15 +230,30 @@ impl GameState for InternalState {
     fn reinforcement_points(&self) -> &HashMap<PlayerId, i32> {
         &self.reinforcement_points
     }
+
+    fn support_points(&self) -> &HashMap<PlayerId, i32> {
+        &self.support_points
+    }
+
+    fn support_calls(&self) -> &[SupportCall] {
+        &self.support_calls
+    }
+
+    fn is_unit_visible(&self, _: &Db, _: &Unit) -> bool {
+        true
+    }
 }
 
 impl GameStateMut for InternalState {
     fn apply_event(&mut self, db: &Db, event: &CoreEvent) {
         match *event {
-            CoreEvent::Move{unit_id, to, cost, ..} => {
+            CoreEvent::Move{unit_id, to, cost, mode, ..} => {
                 {
                     let unit = self.units.get_mut(&unit_id).unwrap();
                     unit.pos = to;
+                    unit.is_entrenched = false;
+                    unit.move_mode = Some(mode);
+                    unit.is_digging_in = false;
                     if let Some(ref mut move_points) = unit.move_points {
                         assert!(move_points.n > 0);
                         move_points.n -= cost.n;
@@ -189,19 +265,41 @@ impl GameStateMut for InternalState {
                     passenger.pos = to;
                 }
             },
-            CoreEvent::EndTurn{new_id, old_id} => {
+            CoreEvent::UndoMove{unit_id, to, cost, move_mode, is_entrenched, ..} => {
+                {
+                    let unit = self.units.get_mut(&unit_id).unwrap();
+                    unit.pos = to;
+                    unit.is_entrenched = is_entrenched;
+                    unit.move_mode = move_mode;
+                    if let Some(ref mut move_points) = unit.move_points {
+                        move_points.n += cost.n;
+                    }
+                }
+                if let Some(passenger_id) = self.units[&unit_id].passenger_id {
+                    let passenger = self.units.get_mut(&passenger_id).unwrap();
+                    passenger.pos = to;
+                }
+            },
+            CoreEvent::EndTurn{new_id, old_id, is_new_round} => {
                 {
                     let reinforcement_points = self.reinforcement_points
                         .get_mut(&old_id).unwrap();
-                    *reinforcement_points += 10;
+                    *reinforcement_points += REINFORCEMENT_POINTS_PER_TURN;
                 }
+                *self.support_points.get_mut(&old_id).unwrap() += 1;
                 self.refresh_units(db, new_id);
                 self.convert_ap(db, old_id);
-                // TODO: timer ticks on every player's turn! O.o
-                for (_, object) in &mut self.objects {
-                    if let Some(ref mut timer) = object.timer {
-                        *timer -= 1;
-                        assert!(*timer >= 0);
+                for unit in self.units.values_mut() {
+                    if unit.player_id == old_id {
+                        unit.move_mode = None;
+                    }
+                }
+                if is_new_round {
+                    for (_, object) in &mut self.objects {
+                        if let Some(ref mut timer) = object.timer {
+                            *timer -= 1;
+                            assert!(*timer >= 0);
+                        }
                     }
                 }
             },
@@ -249,8 +347,20 @@ impl GameStateMut for InternalState {
                     }
                 }
             },
+            CoreEvent::AreaFire{attacker_id, ..} => {
+                let attacker_id = match attacker_id {
+                    Some(attacker_id) => attacker_id,
+                    None => return,
+                };
+                if let Some(unit) = self.units.get_mut(&attacker_id) {
+                    if let Some(ref mut attack_points) = unit.attack_points {
+                        assert!(attack_points.n >= 1);
+                        attack_points.n -= 1;
+                    }
+                }
+            },
             CoreEvent::ShowUnit{ref unit_info} => {
-                self.add_unit(db, unit_info, InfoLevel::Partial);
+                self.insert_unit(db, unit_info, InfoLevel::Partial);
             },
             CoreEvent::HideUnit{unit_id} => {
                 assert!(self.units.get(&unit_id).is_some());
@@ -266,6 +376,8 @@ impl GameStateMut for InternalState {
                 let passenger = self.units.get_mut(&passenger_id)
                     .expect("Bad passenger_id");
                 passenger.pos = to;
+                passenger.is_entrenched = false;
+                passenger.is_digging_in = false;
                 if let Some(ref mut move_points) = passenger.move_points {
                     move_points.n = 0;
                 }
@@ -280,13 +392,25 @@ impl GameStateMut for InternalState {
                     unit.pos = unit_info.pos;
                     return;
                 }
-                self.add_unit(db, unit_info, InfoLevel::Partial);
+                self.insert_unit(db, unit_info, InfoLevel::Partial);
             },
             CoreEvent::SetReactionFireMode{unit_id, mode} => {
                 self.units.get_mut(&unit_id)
                     .expect("Bad unit id")
                     .reaction_fire_mode = mode;
             },
+            CoreEvent::DigIn{unit_id} => {
+                let unit = self.units.get_mut(&unit_id).expect("Bad unit id");
+                unit.is_digging_in = true;
+                if let Some(ref mut move_points) = unit.move_points {
+                    move_points.n = 0;
+                }
+            },
+            CoreEvent::Entrench{unit_id} => {
+                let unit = self.units.get_mut(&unit_id).expect("Bad unit id");
+                unit.is_entrenched = true;
+                unit.is_digging_in = false;
+            },
             CoreEvent::SectorOwnerChanged{sector_id, new_owner_id} => {
                 let sector = self.sectors.get_mut(&sector_id).unwrap();
                 sector.owner_id = new_owner_id;
@@ -302,20 +426,70 @@ impl GameStateMut for InternalState {
                         }
                     }
                 }
-                // TODO: if there is already smoke in tile then just restart its timer
+                if let Some(object) = self.objects.get_mut(&id) {
+                    assert_eq!(object.class, ObjectClass::Smoke);
+                    object.timer = Some(SMOKE_TIMER);
+                    return;
+                }
                 self.objects.insert(id, Object {
                     class: ObjectClass::Smoke,
                     pos: ExactPos {
                         map_pos: pos,
                         slot_id: SlotId::WholeTile,
                     },
-                    timer: Some(5),
+                    timer: Some(SMOKE_TIMER),
                     owner_id: None,
+                    progress: None,
                 });
             },
             CoreEvent::RemoveSmoke{id} => {
                 self.objects.remove(&id);
             },
+            CoreEvent::RevealObject{id, ref object} => {
+                self.objects.entry(id).or_insert_with(|| object.clone());
+            },
+            CoreEvent::Construct{id, pos, class, unit_id, progress} => {
+                if let Some(unit_id) = unit_id {
+                    self.stop_unit_for_work(unit_id);
+                }
+                // minefields belong to the engineer's player
+                let owner_id = match (class, unit_id) {
+                    (ObjectClass::Minefield, Some(unit_id)) => {
+                        Some(self.units[&unit_id].player_id)
+                    },
+                    _ => None,
+                };
+                let object = self.objects.entry(id).or_insert_with(|| Object {
+                    class: class,
+                    pos: ExactPos {
+                        map_pos: pos,
+                        slot_id: SlotId::WholeTile,
+                    },
+                    timer: None,
+                    owner_id: owner_id,
+                    progress: Some(0),
+                });
+                assert_eq!(object.class, class);
+                object.progress = progress;
+            },
+            CoreEvent::CallSupport{ref call} => {
+                *self.support_points.get_mut(&call.player_id).unwrap()
+                    -= call.support_type.cost();
+                self.support_calls.push(call.clone());
+            },
+            CoreEvent::SupportStrike{ref call} => {
+                if let Some(index) = self.support_calls.iter()
+                    .position(|c| c == call)
+                {
+                    self.support_calls.remove(index);
+                }
+            },
+            CoreEvent::ClearMinefield{id, unit_id} => {
+                if let Some(unit_id) = unit_id {
+                    self.stop_unit_for_work(unit_id);
+                }
+                self.objects.remove(&id);
+            },
         }
     }
 }
@@ -339,6 +513,7 @@ fn add_road(objects: &mut HashMap<ObjectId, Object>, path: &[MapPos]) {
             },
             timer: None,
             owner_id: None,
+            progress: None,
         };
         add_object(objects, object);
     }
@@ -357,6 +532,26 @@ fn add_reinforcement_sector(
         },
         timer: None,
         owner_id: owner_id,
+        progress: None,
+    };
+    add_object(objects, object);
+}
+
+fn add_obstacle(
+    objects: &mut HashMap<ObjectId, Object>,
+    pos: MapPos,
+    class: ObjectClass,
+    owner_id: Option<PlayerId>,
+) {
+    let object = Object {
+        class: class,
+        pos: ExactPos {
+            map_pos: pos,
+            slot_id: SlotId::WholeTile,
+        },
+        timer: None,
+        owner_id: owner_id,
+        progress: None,
     };
     add_object(objects, object);
 }
@@ -376,6 +571,7 @@ fn add_buildings(
             pos: obj_pos,
             timer: None,
             owner_id: None,
+            progress: None,
         };
         add_object(objects, object);
     }
@@ -395,6 +591,7 @@ fn add_big_building(
         },
         timer: None,
         owner_id: None,
+        progress: None,
     };
     add_object(objects, object);
 }
@@ -492,6 +689,20 @@ fn load_map_01() -> MapInfo {
             Some(PlayerId{id: player_index}),
         );
     }
+    for &((x, y), class, owner_id) in &[
+        ((7, 2), ObjectClass::Minefield, Some(PlayerId{id: 1})),
+        ((7, 6), ObjectClass::Minefield, Some(PlayerId{id: 1})),
+        ((4, 7), ObjectClass::BarbedWire, None),
+        ((4, 8), ObjectClass::BarbedWire, None),
+        ((3, 1), ObjectClass::TankTraps, None),
+    ] {
+        add_obstacle(
+            &mut objects,
+            MapPos{v: Vector2{x: x, y: y}},
+            class,
+            owner_id,
+        );
+    }
     sectors.insert(
         SectorId{id: 0},
         Sector {
@@ -548,6 +759,17 @@ fn load_map_02() -> MapInfo {
             Some(PlayerId{id: player_index}),
         );
     }
+    for &((x, y), owner_id) in &[
+        ((2, 10), PlayerId{id: 0}),
+        ((6, 10), PlayerId{id: 1}),
+    ] {
+        add_obstacle(
+            &mut objects,
+            MapPos{v: Vector2{x: x, y: y}},
+            ObjectClass::Minefield,
+            Some(owner_id),
+        );
+    }
     sectors.insert(
         SectorId{id: 0},
         Sector {
@@ -568,3 +790,112 @@ fn load_map_02() -> MapInfo {
     );
     (map, objects, sectors)
 }
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use db::{Db};
+    use game_state::{GameState, GameStateMut};
+    use internal_state::{InternalState};
+    use ::{
+        CoreEvent,
+        PlayerId,
+        UnitId,
+        UnitInfo,
+        MapPos,
+        ExactPos,
+        SlotId,
+        ObjectId,
+        SmokeDensity,
+        SMOKE_TIMER,
+    };
+
+    fn end_round(db: &Db, state: &mut InternalState) {
+        state.apply_event(db, &CoreEvent::EndTurn {
+            old_id: PlayerId{id: 0},
+            new_id: PlayerId{id: 1},
+            is_new_round: false,
+        });
+        state.apply_event(db, &CoreEvent::EndTurn {
+            old_id: PlayerId{id: 1},
+            new_id: PlayerId{id: 0},
+            is_new_round: true,
+        });
+    }
+
+    fn smoke_event(id: ObjectId) -> CoreEvent {
+        CoreEvent::Smoke {
+            id: id,
+            pos: MapPos{v: Vector2{x: 4, y: 5}},
+            unit_id: None,
+        }
+    }
+
+    #[test]
+    fn test_smoke_timer_ticks_once_per_round() {
+        let db = Db::new();
+        let mut state = InternalState::new("map02");
+        let id = ObjectId{id: 100};
+        state.apply_event(&db, &smoke_event(id));
+        state.apply_event(&db, &CoreEvent::EndTurn {
+            old_id: PlayerId{id: 0},
+            new_id: PlayerId{id: 1},
+            is_new_round: false,
+        });
+        assert_eq!(state.objects()[&id].timer, Some(SMOKE_TIMER));
+        state.apply_event(&db, &CoreEvent::EndTurn {
+            old_id: PlayerId{id: 1},
+            new_id: PlayerId{id: 0},
+            is_new_round: true,
+        });
+        assert_eq!(state.objects()[&id].timer, Some(SMOKE_TIMER - 1));
+        assert_eq!(state.objects()[&id].smoke_density(), Some(SmokeDensity::Thick));
+        end_round(&db, &mut state);
+        assert_eq!(state.objects()[&id].timer, Some(0));
+        assert_eq!(state.objects()[&id].smoke_density(), Some(SmokeDensity::Thin));
+    }
+
+    #[test]
+    fn test_smoke_refresh_restarts_timer() {
+        let db = Db::new();
+        let mut state = InternalState::new("map02");
+        let id = ObjectId{id: 100};
+        let objects_count = state.objects().len();
+        state.apply_event(&db, &smoke_event(id));
+        end_round(&db, &mut state);
+        end_round(&db, &mut state);
+        assert_eq!(state.objects()[&id].smoke_density(), Some(SmokeDensity::Thin));
+        state.apply_event(&db, &smoke_event(id));
+        assert_eq!(state.objects().len(), objects_count + 1);
+        assert_eq!(state.objects()[&id].timer, Some(SMOKE_TIMER));
+        assert_eq!(state.objects()[&id].smoke_density(), Some(SmokeDensity::Thick));
+    }
+
+    #[test]
+    fn test_shown_enemy_costs_nothing() {
+        let db = Db::new();
+        let mut state = InternalState::new("map02");
+        let player_id = PlayerId{id: 1};
+        let points = state.reinforcement_points()[&player_id];
+        let type_id = db.unit_type_id("soldier");
+        // more units than the player's points would buy
+        let count = points / db.unit_type(type_id).cost + 1;
+        for id in 0..count {
+            state.apply_event(&db, &CoreEvent::ShowUnit {
+                unit_info: UnitInfo {
+                    unit_id: UnitId{id: id},
+                    pos: ExactPos {
+                        map_pos: MapPos{v: Vector2{x: id, y: 0}},
+                        slot_id: SlotId::Id(0),
+                    },
+                    type_id: type_id,
+                    player_id: player_id,
+                    passenger_id: None,
+                    is_entrenched: false,
+                },
+            });
+        }
+        assert_eq!(state.units().len(), count as usize);
+        assert_eq!(state.reinforcement_points()[&player_id], points);
+    }
+}
@@ -12,15 +12,22 @@ pub mod pathfinder;
 pub mod misc;
 pub mod types;
 pub mod check;
+pub mod hit_chance;
+pub mod info;
+pub mod group_move;
+pub mod external_bot;
+pub mod search_ai;
 
 mod ai;
 mod fov;
+mod threat_map;
 mod fow;
 mod internal_state;
 mod filter;
 
 use rand::{thread_rng, Rng};
-use std::{cmp, fmt};
+use std::{cmp, fmt, io};
+use std::time::{Duration};
 use std::collections::{HashMap, HashSet, VecDeque};
 use cgmath::{Vector2};
 use types::{Size2};
@@ -28,19 +35,29 @@ use misc::{clamp};
 use internal_state::{InternalState};
 use game_state::{GameState, GameStateMut};
 use partial_state::{PartialState};
-use map::{Map, Terrain};
+use map::{Map, Terrain, distance};
 use pathfinder::{tile_cost};
-use unit::{Unit, UnitTypeId, UnitClass};
+use unit::{Unit, WeaponType, UnitTypeId, UnitClass};
 use db::{Db};
 use ai::{Ai};
+use search_ai::{SearchAi, Budget, DEFAULT_TIME_BUDGET_MS};
+use external_bot::{ExternalBot};
 use fow::{Fow};
-use dir::{Dir};
-use check::{check_command, check_attack};
+use dir::{Dir, dirs};
+use check::{check_command, check_attack, CommandError};
+use group_move::{plan_group_move};
+use hit_chance::{
+    hit_chance,
+    area_hit_chance,
+    mine_hit_chance,
+    attack_suppression,
+    defender_suppression,
+};
 
 #[derive(Clone, Copy, Debug)]
 pub struct Score{pub n: i32}
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct MovePoints{pub n: i32}
 
 #[derive(Clone, Copy, Debug)]
@@ -52,6 +69,9 @@ pub struct PlayerId{pub id: i32}
 #[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub struct UnitId{pub id: i32}
 
+#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct ObserverId{pub id: i32}
+
 #[derive(PartialOrd, PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub struct SectorId{pub id: i32}
 
@@ -184,6 +204,11 @@ pub enum ObjectClass {
     Road,
     Smoke,
     ReinforcementSector,
+    Minefield,
+    BarbedWire,
+    TankTraps,
+    Bridge,
+    Entrenchment,
 }
 
 #[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Copy)]
@@ -191,12 +216,72 @@ pub struct ObjectId {
     pub id: i32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Object {
     pub pos: ExactPos,
     pub class: ObjectClass,
     pub timer: Option<i32>,
     pub owner_id: Option<PlayerId>,
+    /// Turns of construction work already done, `None` for complete objects
+    pub progress: Option<i32>,
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum SmokeDensity {
+    Thin,
+    Thick,
+}
+
+/// Number of full rounds a fresh smoke screen stays on the map
+pub const SMOKE_TIMER: i32 = 2;
+
+impl Object {
+    /// Smoke is thick until its last round, then it starts to dissipate
+    pub fn smoke_density(&self) -> Option<SmokeDensity> {
+        if self.class != ObjectClass::Smoke {
+            return None;
+        }
+        match self.timer {
+            Some(timer) if timer > 0 => Some(SmokeDensity::Thick),
+            _ => Some(SmokeDensity::Thin),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.progress.is_none()
+    }
+}
+
+/// Number of engineer turns needed to finish an object
+pub fn construction_time(class: ObjectClass) -> i32 {
+    match class {
+        ObjectClass::Bridge => 3,
+        ObjectClass::Entrenchment => 2,
+        ObjectClass::Minefield => 1,
+        ObjectClass::Building |
+        ObjectClass::Road |
+        ObjectClass::Smoke |
+        ObjectClass::ReinforcementSector |
+        ObjectClass::BarbedWire |
+        ObjectClass::TankTraps => panic!("Can`t construct {:?}", class),
+    }
+}
+
+pub fn find_object_at<S: GameState>(
+    state: &S,
+    pos: MapPos,
+    class: ObjectClass,
+) -> Option<ObjectId> {
+    for (&id, object) in state.objects() {
+        if object.class == class && object.pos.map_pos == pos {
+            return Some(id);
+        }
+    }
+    None
+}
+
+pub fn smoke_at<S: GameState>(state: &S, pos: MapPos) -> Option<ObjectId> {
+    find_object_at(state, pos, ObjectClass::Smoke)
 }
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -221,36 +306,97 @@ pub enum FireMode {
 pub enum ReactionFireMode {
     Normal,
     HoldFire,
+    /// Fire only at enemies inside the sector around `pos`
+    Overwatch{pos: MapPos},
+    /// Hold fire until an enemy comes within `distance` tiles
+    Ambush{distance: i32},
+}
+
+impl ReactionFireMode {
+    pub fn is_target_allowed(&self, from: MapPos, target_pos: MapPos) -> bool {
+        match *self {
+            ReactionFireMode::Normal => true,
+            ReactionFireMode::HoldFire => false,
+            ReactionFireMode::Overwatch{pos} => {
+                distance(pos, target_pos) <= OVERWATCH_SECTOR_RADIUS
+            },
+            ReactionFireMode::Ambush{distance: ambush_distance} => {
+                distance(from, target_pos) <= ambush_distance
+            },
+        }
+    }
 }
 
+pub const OVERWATCH_SECTOR_RADIUS: i32 = 1;
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum MoveMode {
     Fast,
     Hunt,
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SupportType {
+    Artillery,
+    AirStrike,
+}
+
+impl SupportType {
+    pub fn cost(&self) -> i32 {
+        match *self {
+            SupportType::Artillery => 3,
+            SupportType::AirStrike => 4,
+        }
+    }
+
+    fn weapon_type_name(&self) -> &str {
+        match *self {
+            SupportType::Artillery => "off_map_artillery",
+            SupportType::AirStrike => "air_strike",
+        }
+    }
+}
+
+/// Off-map support arrives one turn after it was called
+#[derive(Clone, PartialEq, Debug)]
+pub struct SupportCall {
+    pub player_id: PlayerId,
+    pub pos: MapPos,
+    pub support_type: SupportType,
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub enum Command {
     Move{unit_id: UnitId, path: Vec<ExactPos>, mode: MoveMode},
+    GroupMove{unit_ids: Vec<UnitId>, pos: MapPos, mode: MoveMode},
     EndTurn,
     CreateUnit{pos: ExactPos, type_id: UnitTypeId},
     AttackUnit{attacker_id: UnitId, defender_id: UnitId},
+    AreaFire{unit_id: UnitId, pos: MapPos},
     LoadUnit{transporter_id: UnitId, passenger_id: UnitId},
     UnloadUnit{transporter_id: UnitId, passenger_id: UnitId, pos: ExactPos},
     SetReactionFireMode{unit_id: UnitId, mode: ReactionFireMode},
     Smoke{unit_id: UnitId, pos: MapPos},
+    DigIn{unit_id: UnitId},
+    BuildBridge{unit_id: UnitId, pos: MapPos},
+    BuildEntrenchment{unit_id: UnitId, pos: MapPos},
+    PlaceMinefield{unit_id: UnitId, pos: MapPos},
+    ClearMinefield{unit_id: UnitId, pos: MapPos},
+    CallSupport{pos: MapPos, support_type: SupportType},
+    Undo,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct UnitInfo {
     pub unit_id: UnitId,
     pub pos: ExactPos,
     pub type_id: UnitTypeId,
     pub player_id: PlayerId,
     pub passenger_id: Option<UnitId>,
+    pub is_entrenched: bool,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct AttackInfo {
     pub attacker_id: Option<UnitId>,
     pub defender_id: UnitId,
@@ -260,9 +406,10 @@ pub struct AttackInfo {
     pub remove_move_points: bool,
     pub is_ambush: bool,
     pub is_inderect: bool,
+    pub is_return_fire: bool,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum CoreEvent {
     Move {
         unit_id: UnitId,
@@ -271,9 +418,21 @@ pub enum CoreEvent {
         mode: MoveMode,
         cost: MovePoints,
     },
+    /// The owner's fog is recomputed from the current positions
+    UndoMove {
+        unit_id: UnitId,
+        from: ExactPos,
+        to: ExactPos,
+        cost: MovePoints,
+        move_mode: Option<MoveMode>,
+        is_entrenched: bool,
+    },
     EndTurn {
         old_id: PlayerId,
         new_id: PlayerId,
+        /// The turn passes back to the first player,
+        /// object timers tick only then
+        is_new_round: bool,
     },
     CreateUnit {
         unit_info: UnitInfo,
@@ -281,6 +440,10 @@ pub enum CoreEvent {
     AttackUnit {
         attack_info: AttackInfo,
     },
+    AreaFire {
+        attacker_id: Option<UnitId>,
+        pos: MapPos,
+    },
     ShowUnit {
         unit_info: UnitInfo,
     },
@@ -303,6 +466,14 @@ pub enum CoreEvent {
         unit_id: UnitId,
         mode: ReactionFireMode,
     },
+    /// The unit gets entrenched when its player's
+    /// next turn begins if it doesn't move till then
+    DigIn {
+        unit_id: UnitId,
+    },
+    Entrench {
+        unit_id: UnitId,
+    },
     SectorOwnerChanged {
         sector_id: SectorId,
         new_owner_id: Option<PlayerId>,
@@ -322,6 +493,29 @@ pub enum CoreEvent {
     RemoveSmoke {
         id: ObjectId,
     },
+    RevealObject {
+        id: ObjectId,
+        object: Object,
+    },
+    /// `progress` is the state of the object after this turn of work,
+    /// so those who haven't seen the previous turns get it right too
+    Construct {
+        id: ObjectId,
+        pos: MapPos,
+        class: ObjectClass,
+        unit_id: Option<UnitId>,
+        progress: Option<i32>,
+    },
+    ClearMinefield {
+        id: ObjectId,
+        unit_id: Option<UnitId>,
+    },
+    CallSupport {
+        call: SupportCall,
+    },
+    SupportStrike {
+        call: SupportCall,
+    },
 }
 
 pub fn move_cost_modifier(mode: MoveMode) -> i32 {
@@ -391,6 +585,7 @@ pub fn unit_to_info(unit: &Unit) -> UnitInfo {
         type_id: unit.type_id,
         player_id: unit.player_id,
         passenger_id: unit.passenger_id,
+        is_entrenched: unit.is_entrenched,
     }
 }
 
@@ -399,61 +594,45 @@ struct PlayerInfo {
     events: VecDeque<CoreEvent>,
     fow: Fow,
     visible_enemies: HashSet<UnitId>,
+
+    /// Everything the player knows, commands are checked against it
+    game_state: PartialState,
 }
 
-pub fn print_unit_info(db: &Db, unit: &Unit) {
-    let unit_type = db.unit_type(unit.type_id);
-    let weapon_type = db.weapon_type(unit_type.weapon_type_id);
-    println!("unit:");
-    println!("  player_id: {}", unit.player_id.id);
-    if let Some(move_points) = unit.move_points {
-        println!("  move_points: {}", move_points.n);
-    } else {
-        println!("  move_points: ?");
-    }
-    if let Some(attack_points) = unit.attack_points {
-        println!("  attack_points: {}", attack_points.n);
-    } else {
-        println!("  attack_points: ?");
-    }
-    if let Some(reactive_attack_points) = unit.reactive_attack_points {
-        println!("  reactive_attack_points: {}", reactive_attack_points.n);
-    } else {
-        println!("  reactive_attack_points: ?");
-    }
-    println!("  count: {}", unit.count);
-    println!("  morale: {}", unit.morale);
-    println!("type:");
-    println!("  name: {}", unit_type.name);
-    match unit_type.class {
-        UnitClass::Infantry => println!("  class: Infantry"),
-        UnitClass::Vehicle => println!("  class: Vehicle"),
-    }
-    println!("  count: {}", unit_type.count);
-    println!("  size: {}", unit_type.size);
-    println!("  armor: {}", unit_type.armor);
-    println!("  toughness: {}", unit_type.toughness);
-    println!("  weapon_skill: {}", unit_type.weapon_skill);
-    println!("  mp: {}", unit_type.move_points.n);
-    println!("  ap: {}", unit_type.attack_points.n);
-    println!("  reactive_ap: {}", unit_type.reactive_attack_points.n);
-    println!("  los_range: {}", unit_type.los_range);
-    println!("  cover_los_range: {}", unit_type.cover_los_range);
-    println!("weapon:");
-    println!("  name: {}", weapon_type.name);
-    println!("  damage: {}", weapon_type.damage);
-    println!("  ap: {}", weapon_type.ap);
-    println!("  accuracy: {}", weapon_type.accuracy);
-    println!("  max_distance: {}", weapon_type.max_distance);
-}
-
-pub fn print_terrain_info<S: GameState>(state: &S, pos: MapPos) {
-    match *state.map().tile(pos) {
-        Terrain::City => println!("City"),
-        Terrain::Trees => println!("Trees"),
-        Terrain::Plain => println!("Plain"),
-        Terrain::Water => println!("Water"),
-    }
+/// What an observer is allowed to see
+#[derive(Clone, PartialEq, Debug)]
+pub enum ObserverVision {
+    /// Unfiltered events, exactly as they are applied to the game state
+    Full,
+
+    /// What these players see together: a tile is visible
+    /// if any of them sees it and every event is filtered
+    /// once for this common view
+    Players(Vec<PlayerId>),
+}
+
+#[derive(Clone, Debug)]
+enum ObserverInfo {
+    Full(VecDeque<CoreEvent>),
+    Players(Box<PlayerInfo>),
+}
+
+/// Filtered events of every player and every observer
+type AllFilteredEvents = (
+    Vec<(PlayerId, filter::FilteredEvents)>,
+    Vec<(ObserverId, filter::FilteredEvents)>,
+);
+
+/// State of the unit and its owner's Fow before the last move
+#[derive(Clone, Debug)]
+struct MoveSnapshot {
+    unit: Unit,
+    cost: MovePoints,
+}
+
+fn attack_test(hit_chance: i32) -> bool {
+    let r = thread_rng().gen_range(0, 100);
+    r < hit_chance
 }
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -475,53 +654,294 @@ impl Default for GameType {
     }
 }
 
+/// What an AI player cares about
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AiPersonality {
+    /// Only takes sectors and avoids enemy fire at almost any cost
+    Passive,
+
+    /// Hunts enemies only when it's ahead on score
+    Defensive,
+
+    /// Hunts enemies whenever they are closer than sectors
+    /// and ignores reaction fire
+    Aggressive,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AiDifficulty {
+    /// No smoke, no reaction fire tricks, no focus fire, no saving up
+    Easy,
+    Normal,
+    Hard,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct AiProfile {
+    pub personality: AiPersonality,
+    pub difficulty: AiDifficulty,
+
+    /// The AI gets all events unfiltered, so it knows the whole
+    /// `InternalState`
+    pub is_cheating: bool,
+}
+
+impl Default for AiProfile {
+    fn default() -> AiProfile {
+        AiProfile {
+            personality: AiPersonality::Defensive,
+            difficulty: AiDifficulty::Normal,
+            is_cheating: false,
+        }
+    }
+}
+
+/// Plays for a player. Gets the same filtered events as a human
+/// player would and is asked for commands until it ends the turn
+/// or sends `MAX_BOT_ERRORS` bad commands in a row.
+pub trait Bot: fmt::Debug {
+    fn apply_event(&mut self, db: &Db, event: &CoreEvent);
+
+    fn get_command(&mut self, db: &Db) -> Command;
+
+    /// Called when the last command broke the rules and was ignored
+    fn report_error(&mut self, _db: &Db, _command: &Command, _error: &CommandError) {}
+}
+
+/// Consecutive bad commands after which a bot's turn is ended
+pub const MAX_BOT_ERRORS: i32 = 10;
+
+/// Creates a bot that plays for the given player
+pub type BotFactory = fn(PlayerId, &Options) -> io::Result<Box<Bot>>;
+
+/// The built-in AI, configured by `Options::ai_profiles`
+pub fn new_default_bot(player_id: PlayerId, options: &Options) -> io::Result<Box<Bot>> {
+    let profile = options.ai_profiles.get(&player_id).cloned().unwrap_or_default();
+    Ok(Box::new(Ai::new(player_id, &options.map_name, profile)))
+}
+
+/// AI that plays out candidate commands on copies of the state
+/// and picks the best on average
+pub fn new_search_bot(player_id: PlayerId, options: &Options) -> io::Result<Box<Bot>> {
+    let budget = Budget::Time(Duration::from_millis(DEFAULT_TIME_BUDGET_MS));
+    Ok(Box::new(SearchAi::new(player_id, &options.map_name, budget)))
+}
+
+/// Bot running as a separate process, started with the command line
+/// from `Options::bot_commands`. See `external_bot` for the protocol.
+pub fn new_external_bot(player_id: PlayerId, options: &Options) -> io::Result<Box<Bot>> {
+    let command_line = match options.bot_commands.get(&player_id) {
+        Some(command_line) => command_line,
+        None => return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("No command line for the bot of player {}", player_id.id),
+        )),
+    };
+    let bot = ExternalBot::spawn(player_id, &options.map_name, command_line)?;
+    Ok(Box::new(bot))
+}
+
 #[derive(Clone, Debug)]
 pub struct Options {
     pub game_type: GameType,
     pub map_name: String,
+
+    /// Profiles of AI players, missing ones get the default profile
+    pub ai_profiles: HashMap<PlayerId, AiProfile>,
+
+    /// Players controlled by bots. In `SingleVsAi` games
+    /// the second player gets the default bot if it's not set here.
+    pub bots: HashMap<PlayerId, BotFactory>,
+
+    /// Command lines of external bot processes, used by `new_external_bot`
+    pub bot_commands: HashMap<PlayerId, Vec<String>>,
 }
 
-#[derive(Clone, Debug)]
+/// Runs the game. It's not `Clone` as it owns the bots and a bot can be
+/// an external process, copy a `PartialState` to try commands out.
+#[derive(Debug)]
 pub struct Core {
     state: InternalState,
     players: Vec<Player>,
     current_player_id: PlayerId,
     db: Db,
-    ai: Ai,
+    bots: HashMap<PlayerId, Box<Bot>>,
+    cheating_bot_ids: HashSet<PlayerId>,
     players_info: HashMap<PlayerId, PlayerInfo>,
+    observers: HashMap<ObserverId, ObserverInfo>,
+    last_move: Option<MoveSnapshot>,
 }
 
-fn get_players_list(game_type: GameType) -> Vec<Player> {
-    vec!(
-        Player {
-            id: PlayerId{id: 0},
-            class: PlayerClass::Human,
-        },
+fn get_bot_factories(options: &Options) -> HashMap<PlayerId, BotFactory> {
+    let mut bots = options.bots.clone();
+    if options.game_type == GameType::SingleVsAi {
+        bots.entry(PlayerId{id: 1}).or_insert(new_default_bot);
+    }
+    bots
+}
+
+fn get_players_list(bots: &HashMap<PlayerId, BotFactory>) -> Vec<Player> {
+    (0..2).map(|id| {
+        let id = PlayerId{id: id};
         Player {
-            id: PlayerId{id: 1},
-            class: match game_type {
-                GameType::SingleVsAi => PlayerClass::Ai,
-                GameType::Hotseat => PlayerClass::Human,
+            id: id,
+            class: if bots.contains_key(&id) {
+                PlayerClass::Ai
+            } else {
+                PlayerClass::Human
             },
-        },
-    )
+        }
+    }).collect()
 }
 
-fn get_player_info_lists(map_size: Size2) -> HashMap<PlayerId, PlayerInfo> {
-    let mut map = HashMap::new();
-    map.insert(PlayerId{id: 0}, PlayerInfo {
-        fow: Fow::new(map_size, PlayerId{id: 0}),
-        events: VecDeque::new(),
-        visible_enemies: HashSet::new(),
-    });
-    map.insert(PlayerId{id: 1}, PlayerInfo {
-        fow: Fow::new(map_size, PlayerId{id: 1}),
+fn new_player_info(db: &Db, state: &InternalState, player_id: PlayerId) -> PlayerInfo {
+    new_shared_info(db, state, &[player_id])
+}
+
+/// Common view of several players for observers
+fn new_shared_info(db: &Db, state: &InternalState, player_ids: &[PlayerId]) -> PlayerInfo {
+    let mut fow = Fow::new_shared(state.map().size(), player_ids);
+    fow.reset(db, state);
+    PlayerInfo {
+        visible_enemies: filter::get_visible_enemies(db, state, &fow),
+        fow: fow,
         events: VecDeque::new(),
-        visible_enemies: HashSet::new(),
-    });
+        game_state: PartialState::from_state_shared(db, state, player_ids),
+    }
+}
+
+/// Applies the filtered events to what the player knows and adds
+/// the events about enemies that were found or lost because of them
+fn apply_filtered_events(
+    db: &Db,
+    state: &InternalState,
+    i: &mut PlayerInfo,
+    (filtered_events, active_unit_ids): filter::FilteredEvents,
+) {
+    for event in filtered_events {
+        i.fow.apply_event(db, state, &event);
+        i.game_state.apply_event(db, &event);
+        i.events.push_back(event);
+        let new_visible_enemies = filter::get_visible_enemies(db, state, &i.fow);
+        let show_hide_events = filter::show_or_hide_passive_enemies(
+            state,
+            &i.fow,
+            &i.game_state,
+            &active_unit_ids,
+            &i.visible_enemies,
+            &new_visible_enemies,
+        );
+        for event in &show_hide_events {
+            i.game_state.apply_event(db, event);
+        }
+        i.events.extend(show_hide_events);
+        let entrench_events = filter::reveal_entrenchments(
+            state,
+            &i.fow,
+            &i.game_state,
+        );
+        for event in &entrench_events {
+            i.game_state.apply_event(db, event);
+        }
+        i.events.extend(entrench_events);
+        let reveal_events = filter::reveal_objects(state, &i.fow, &i.game_state);
+        for event in &reveal_events {
+            i.game_state.apply_event(db, event);
+        }
+        i.events.extend(reveal_events);
+        i.visible_enemies = new_visible_enemies;
+    }
+}
+
+fn get_player_info_lists(db: &Db, state: &InternalState) -> HashMap<PlayerId, PlayerInfo> {
+    let mut map = HashMap::new();
+    map.insert(PlayerId{id: 0}, new_player_info(db, state, PlayerId{id: 0}));
+    map.insert(PlayerId{id: 1}, new_player_info(db, state, PlayerId{id: 1}));
     map
 }
 
+/// Rules without the players' points of view: every player's
+/// fog is updated with the unfiltered events
+struct Sandbox<'a> {
+    db: &'a Db,
+    state: InternalState,
+    fows: HashMap<PlayerId, Fow>,
+    visible_enemies: HashMap<PlayerId, HashSet<UnitId>>,
+    events: Vec<CoreEvent>,
+}
+
+impl<'a> Sandbox<'a> {
+    fn new(db: &'a Db, state: InternalState) -> Sandbox<'a> {
+        let mut fows = HashMap::new();
+        let mut visible_enemies = HashMap::new();
+        for &player_id in state.score().keys() {
+            let mut fow = Fow::new(state.map().size(), player_id);
+            fow.reset(db, &state);
+            visible_enemies.insert(
+                player_id, filter::get_visible_enemies(db, &state, &fow));
+            fows.insert(player_id, fow);
+        }
+        Sandbox {
+            db: db,
+            state: state,
+            fows: fows,
+            visible_enemies: visible_enemies,
+            events: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Rules for Sandbox<'a> {
+    fn db(&self) -> &Db {
+        self.db
+    }
+
+    fn state(&self) -> &InternalState {
+        &self.state
+    }
+
+    fn fow(&self, player_id: PlayerId) -> &Fow {
+        &self.fows[&player_id]
+    }
+
+    fn visible_enemies(&self, player_id: PlayerId) -> &HashSet<UnitId> {
+        &self.visible_enemies[&player_id]
+    }
+
+    fn do_core_event(&mut self, event: &CoreEvent) {
+        self.state.apply_event(self.db, event);
+        for (&player_id, fow) in &mut self.fows {
+            fow.apply_event(self.db, &self.state, event);
+            self.visible_enemies.insert(
+                player_id,
+                filter::get_visible_enemies(self.db, &self.state, fow),
+            );
+        }
+        self.events.push(event.clone());
+    }
+}
+
+/// Plays the command on a copy of the player's view with all the usual
+/// rules, random hits and reaction fire included. Units that the view
+/// only guesses about take part too. Returns the new view and the events
+/// that happened, the original view is not touched.
+pub fn simulate_command(
+    db: &Db,
+    state: &PartialState,
+    player_id: PlayerId,
+    command: &Command,
+) -> Result<(PartialState, Vec<CoreEvent>), CommandError> {
+    check_command(db, player_id, state, command)?;
+    let mut sandbox = Sandbox::new(db, state.internal_state().clone());
+    sandbox.do_unit_command(player_id, command);
+    sandbox.update_sectors();
+    let mut state = state.clone();
+    for event in &sandbox.events {
+        state.apply_event(db, event);
+    }
+    Ok((state, sandbox.events))
+}
+
 pub fn objects_at(objects: &HashMap<ObjectId, Object>, pos: MapPos) -> Vec<&Object> {
     let mut objects_at = Vec::new();
     for object in objects.values() {
@@ -587,13 +1007,21 @@ pub fn get_free_slot_id<S: GameState>(
         }
         return Some(SlotId::Air);
     }
+    if is_blocked_by_tank_traps(state, unit_type.class, pos) {
+        return None;
+    }
     if unit_type.is_big {
         for object in &objects_at {
             match object.class {
                 ObjectClass::Building => return None,
                 ObjectClass::Smoke |
                 ObjectClass::ReinforcementSector |
-                ObjectClass::Road => {},
+                ObjectClass::Road |
+                ObjectClass::Minefield |
+                ObjectClass::BarbedWire |
+                ObjectClass::TankTraps |
+                ObjectClass::Bridge |
+                ObjectClass::Entrenchment => {},
             }
         }
         if units_at.is_empty() {
@@ -621,7 +1049,12 @@ pub fn get_free_slot_id<S: GameState>(
                         ObjectClass::Building => return None,
                         ObjectClass::Smoke |
                         ObjectClass::ReinforcementSector |
-                        ObjectClass::Road => {},
+                        ObjectClass::Road |
+                        ObjectClass::Minefield |
+                        ObjectClass::BarbedWire |
+                        ObjectClass::TankTraps |
+                        ObjectClass::Bridge |
+                        ObjectClass::Entrenchment => {},
                     }
                 }
                 SlotId::TwoTiles(_) | SlotId::Air => {},
@@ -637,6 +1070,16 @@ pub fn get_free_slot_id<S: GameState>(
     None
 }
 
+/// Tank traps can't be crossed by vehicles at all
+pub fn is_blocked_by_tank_traps<S: GameState>(
+    state: &S,
+    class: UnitClass,
+    pos: MapPos,
+) -> bool {
+    class == UnitClass::Vehicle && state.objects_at(pos).iter()
+        .any(|object| object.class == ObjectClass::TankTraps)
+}
+
 pub fn get_slots_count(map: &Map<Terrain>, pos: MapPos) -> i32 {
     match *map.tile(pos) {
         Terrain::Water => 1,
@@ -655,6 +1098,9 @@ pub fn is_exact_pos_free<S: GameState>(
 ) -> bool {
     let units_at = state.units_at(pos.map_pos);
     let unit_type = db.unit_type(type_id);
+    if !unit_type.is_air && is_blocked_by_tank_traps(state, unit_type.class, pos.map_pos) {
+        return false;
+    }
     if unit_type.is_big && !unit_type.is_air {
         return units_at.is_empty();
     }
@@ -672,26 +1118,21 @@ pub fn is_exact_pos_free<S: GameState>(
     true
 }
 
-impl Core {
-    pub fn new(options: &Options) -> Core {
-        let state = InternalState::new(&options.map_name);
-        let map_size = state.map().size();
-        Core {
-            state: state,
-            players: get_players_list(options.game_type),
-            current_player_id: PlayerId{id: 0},
-            db: Db::new(),
-            ai: Ai::new(PlayerId{id:1}, &options.map_name),
-            players_info: get_player_info_lists(map_size),
-        }
-    }
+/// Game rules shared by `Core` and the sandbox of `simulate_command`
+trait Rules {
+    fn db(&self) -> &Db;
 
-    pub fn db(&self) -> &Db {
-        &self.db
-    }
+    fn state(&self) -> &InternalState;
 
-    fn get_new_unit_id(&mut self) -> UnitId {
-        let mut next_id = match self.state.units().keys().max() {
+    fn fow(&self, player_id: PlayerId) -> &Fow;
+
+    fn visible_enemies(&self, player_id: PlayerId) -> &HashSet<UnitId>;
+
+    /// Applies the event to the state and updates what the players see
+    fn do_core_event(&mut self, event: &CoreEvent);
+
+    fn get_new_unit_id(&self) -> UnitId {
+        let mut next_id = match self.state().units().keys().max() {
             Some(&id) => id,
             None => UnitId{id: 0},
         };
@@ -699,8 +1140,8 @@ impl Core {
         next_id
     }
 
-    fn get_new_object_id(&mut self) -> ObjectId {
-        let mut next_id = match self.state.objects().keys().max() {
+    fn get_new_object_id(&self) -> ObjectId {
+        let mut next_id = match self.state().objects().keys().max() {
             Some(&id) => id,
             None => ObjectId{id: 0},
         };
@@ -708,16 +1149,20 @@ impl Core {
         next_id
     }
 
-    pub fn map_size(&self) -> Size2 {
-        self.state.map().size()
+    /// Smoke on an already smoked tile refreshes the existing object
+    fn get_smoke_id(&self, pos: MapPos) -> ObjectId {
+        match smoke_at(self.state(), pos) {
+            Some(id) => id,
+            None => self.get_new_object_id(),
+        }
     }
 
-    fn get_killed_count(&self, attacker: &Unit, defender: &Unit) -> i32 {
-        let hit = self.attack_test(attacker, defender);
+    fn roll_killed_count(&self, hit_chance: i32, defender: &Unit) -> i32 {
+        let hit = attack_test(hit_chance);
         if !hit {
             return 0;
         }
-        let defender_type = self.db.unit_type(defender.type_id);
+        let defender_type = self.db().unit_type(defender.type_id);
         match defender_type.class {
             UnitClass::Infantry => {
                 clamp(thread_rng().gen_range(1, 5), 1, defender.count)
@@ -726,69 +1171,18 @@ impl Core {
         }
     }
 
-    fn cover_bonus(&self, defender: &Unit) -> i32 {
-        let defender_type = self.db.unit_type(defender.type_id);
-        if defender_type.class == UnitClass::Infantry {
-            match *self.state.map().tile(defender.pos) {
-                Terrain::Plain | Terrain::Water => 0,
-                Terrain::Trees => 2,
-                Terrain::City => 3,
-            }
-        } else {
-            0
-        }
-    }
-
-    // TODO: i32 -> HitChance
-    pub fn hit_chance(&self, attacker: &Unit, defender: &Unit) -> i32 {
-        let attacker_type = self.db.unit_type(attacker.type_id);
-        let defender_type = self.db.unit_type(defender.type_id);
-        let weapon_type = self.db.weapon_type(attacker_type.weapon_type_id);
-        let cover_bonus = self.cover_bonus(defender);
-        let hit_test_v = -7 - cover_bonus + defender_type.size
-            + weapon_type.accuracy + attacker_type.weapon_skill;
-        let pierce_test_v = 10 + -defender_type.armor + weapon_type.ap;
-        let wound_test_v = 5 -defender_type.toughness + weapon_type.damage;
-        let hit_test_v = clamp(hit_test_v, 0, 10);
-        let pierce_test_v = clamp(pierce_test_v, 0, 10);
-        let wound_test_v = clamp(wound_test_v, 0, 10);
-        let k = (hit_test_v * pierce_test_v * wound_test_v) / 10;
-        assert!(0 <= k);
-        assert!(100 > k);
-        k
-    }
-
-    fn attack_test(&self, attacker: &Unit, defender: &Unit) -> bool {
-        let k = self.hit_chance(attacker, defender);
-        let r = thread_rng().gen_range(0, 100);
-        r < k
-    }
-
-    pub fn player(&self) -> &Player {
-        &self.players[self.player_id().id as usize]
-    }
-
-    pub fn player_id(&self) -> PlayerId {
-        self.current_player_id
-    }
-
-    pub fn get_event(&mut self) -> Option<CoreEvent> {
-        let mut i = self.players_info.get_mut(&self.current_player_id)
-            .expect("core: Can`t get current player`s info");
-        i.events.pop_front()
-    }
-
     fn command_attack_unit_to_event(
         &self,
         attacker_id: UnitId,
         defender_id: UnitId,
         fire_mode: FireMode,
+        is_return_fire: bool,
     ) -> Option<CoreEvent> {
-        let attacker = self.state.unit(attacker_id);
-        let defender = self.state.unit(defender_id);
+        let attacker = self.state().unit(attacker_id);
+        let defender = self.state().unit(defender_id);
         let check_attack_result = check_attack(
-            &self.db,
-            &self.state,
+            self.db(),
+            self.state(),
             attacker,
             defender,
             fire_mode,
@@ -796,27 +1190,34 @@ impl Core {
         if check_attack_result.is_err() {
             return None;
         }
-        let attacker_type = self.db.unit_type(attacker.type_id);
-        let weapon_type = self.db.weapon_type(attacker_type.weapon_type_id);
+        let attacker_type = self.db().unit_type(attacker.type_id);
+        let weapon_type = self.db().weapon_type(attacker_type.weapon_type_id);
+        let mut hit_chance = hit_chance(self.db(), self.state(), attacker, defender).total;
+        if is_return_fire {
+            // shooting back while being suppressed
+            hit_chance = hit_chance * attacker.morale / 100;
+        }
         let killed = cmp::min(
-            defender.count, self.get_killed_count(attacker, defender));
-        let fow = &self.players_info[&defender.player_id].fow;
+            defender.count, self.roll_killed_count(hit_chance, defender));
+        let fow = self.fow(defender.player_id);
         let is_visible = fow.is_visible(
-            &self.db, &self.state, attacker, attacker.pos);
-        let ambush_chance = 70;
+            self.db(), self.state(), attacker, attacker.pos);
+        let ambush_chance = match (fire_mode, attacker.reaction_fire_mode) {
+            (FireMode::Reactive, ReactionFireMode::Ambush{..}) => 90,
+            _ => 70,
+        };
         let is_ambush = !is_visible
             && thread_rng().gen_range(1, 100) <= ambush_chance;
-        let base_suppression = 10;
-        let per_death_suppression = 20;
         let attack_info = AttackInfo {
             attacker_id: Some(attacker_id),
             defender_id: defender_id,
             killed: killed,
             mode: fire_mode,
-            suppression: base_suppression + per_death_suppression * killed,
+            suppression: defender_suppression(defender, killed),
             remove_move_points: false,
             is_ambush: is_ambush,
             is_inderect: weapon_type.is_inderect,
+            is_return_fire: is_return_fire,
         };
         Some(CoreEvent::AttackUnit{attack_info: attack_info})
     }
@@ -827,17 +1228,18 @@ impl Core {
         attacker: &Unit,
     ) -> bool {
         assert!(attacker.player_id != defender.player_id);
-        if attacker.reaction_fire_mode == ReactionFireMode::HoldFire {
+        let mode = attacker.reaction_fire_mode;
+        if !mode.is_target_allowed(attacker.pos.map_pos, defender.pos.map_pos) {
             return false;
         }
         // TODO: move to `check_attack`
-        let fow = &self.players_info[&attacker.player_id].fow;
-        if !fow.is_visible(&self.db, &self.state, defender, defender.pos) {
+        let fow = self.fow(attacker.player_id);
+        if !fow.is_visible(self.db(), self.state(), defender, defender.pos) {
             return false;
         }
         let check_attack_result = check_attack(
-            &self.db,
-            &self.state,
+            self.db(),
+            self.state(),
             attacker,
             defender,
             FireMode::Reactive,
@@ -845,13 +1247,27 @@ impl Core {
         check_attack_result.is_ok()
     }
 
-    fn reaction_fire_internal(&mut self, unit_id: UnitId, stop_on_attack: bool) -> ReactionFireResult {
-        let unit_ids: Vec<_> = self.state.units().keys().cloned().collect();
+    /// `returned_fire_id` is a unit that has already shot back
+    /// and must not fire at the same target again
+    fn reaction_fire_internal(
+        &mut self,
+        unit_id: UnitId,
+        move_mode: Option<MoveMode>,
+        returned_fire_id: Option<UnitId>,
+    ) -> ReactionFireResult {
+        let stop_on_attack = move_mode == Some(MoveMode::Fast);
+        let unit_ids: Vec<_> = self.state().units().keys().cloned().collect();
         let mut result = ReactionFireResult::None;
         for enemy_unit_id in unit_ids {
+            if returned_fire_id == Some(enemy_unit_id) {
+                continue;
+            }
             let event = {
-                let enemy_unit = self.state.unit(enemy_unit_id);
-                let unit = self.state.unit(unit_id);
+                let enemy_unit = match self.state().units().get(&enemy_unit_id) {
+                    Some(unit) => unit,
+                    None => continue, // killed by return fire
+                };
+                let unit = self.state().unit(unit_id);
                 if enemy_unit.player_id == unit.player_id {
                     continue;
                 }
@@ -859,10 +1275,10 @@ impl Core {
                     continue;
                 }
                 let event = self.command_attack_unit_to_event(
-                    enemy_unit.id, unit_id, FireMode::Reactive);
+                    enemy_unit.id, unit_id, FireMode::Reactive, false);
                 if let Some(CoreEvent::AttackUnit{mut attack_info}) = event {
-                    let hit_chance = self.hit_chance(enemy_unit, unit);
-                    let unit_type = self.db.unit_type(unit.type_id);
+                    let hit_chance = hit_chance(self.db(), self.state(), enemy_unit, unit).total;
+                    let unit_type = self.db().unit_type(unit.type_id);
                     if hit_chance > 15 && !unit_type.is_air && stop_on_attack {
                         attack_info.remove_move_points = true;
                     }
@@ -873,71 +1289,351 @@ impl Core {
             };
             self.do_core_event(&event);
             result = ReactionFireResult::Attacked;
-            if self.state.units().get(&unit_id).is_none() {
+            if self.state().units().get(&unit_id).is_none() {
                 return ReactionFireResult::Killed;
             }
+            if move_mode == Some(MoveMode::Hunt) {
+                self.return_fire(unit_id, enemy_unit_id);
+            }
         }
         result
     }
 
+    /// Shoots back at whoever has just fired at the unit,
+    /// happens before any other unit can react
+    fn return_fire(&mut self, unit_id: UnitId, shooter_id: UnitId) {
+        let event = {
+            let unit = match self.state().units().get(&unit_id) {
+                Some(unit) => unit,
+                None => return,
+            };
+            let shooter = self.state().unit(shooter_id);
+            let mode = unit.reaction_fire_mode;
+            if !mode.is_target_allowed(unit.pos.map_pos, shooter.pos.map_pos) {
+                return;
+            }
+            let fow = self.fow(unit.player_id);
+            if !fow.is_visible(self.db(), self.state(), shooter, shooter.pos) {
+                return;
+            }
+            self.command_attack_unit_to_event(
+                unit_id, shooter_id, FireMode::Reactive, true)
+        };
+        if let Some(ref event) = event {
+            self.do_core_event(event);
+        }
+    }
+
     fn reaction_fire(&mut self, unit_id: UnitId) {
-        self.reaction_fire_internal(unit_id, false);
+        self.reaction_fire_internal(unit_id, None, None);
     }
 
-    pub fn next_player_id(&self, id: PlayerId) -> PlayerId {
-        let old_id = id.id;
-        let max_id = self.players.len() as i32;
-        PlayerId{id: if old_id + 1 == max_id {
-            0
+    fn find_enemy_minefield(&self, unit: &Unit) -> Option<ObjectId> {
+        for (&id, object) in self.state().objects() {
+            if object.class == ObjectClass::Minefield
+                && object.pos.map_pos == unit.pos.map_pos
+                && object.owner_id != Some(unit.player_id)
+            {
+                return Some(id);
+            }
+        }
+        None
+    }
+
+    /// Minefields attack enemy units that enter their tile
+    fn minefield_attack(&mut self, unit_id: UnitId) -> ReactionFireResult {
+        let events = {
+            let unit = self.state().unit(unit_id);
+            let unit_type = self.db().unit_type(unit.type_id);
+            if unit_type.is_air {
+                return ReactionFireResult::None;
+            }
+            let object_id = match self.find_enemy_minefield(unit) {
+                Some(id) => id,
+                None => return ReactionFireResult::None,
+            };
+            let hit_chance = mine_hit_chance(self.db(), unit).total;
+            let killed = cmp::min(
+                unit.count, self.roll_killed_count(hit_chance, unit));
+            let attack_info = AttackInfo {
+                attacker_id: None,
+                defender_id: unit_id,
+                killed: killed,
+                mode: FireMode::Reactive,
+                suppression: attack_suppression(killed),
+                remove_move_points: true,
+                is_ambush: false,
+                is_inderect: false,
+                is_return_fire: false,
+            };
+            vec![
+                CoreEvent::RevealObject {
+                    id: object_id,
+                    object: self.state().objects()[&object_id].clone(),
+                },
+                CoreEvent::AttackUnit{attack_info: attack_info},
+            ]
+        };
+        for event in &events {
+            self.do_core_event(event);
+        }
+        if self.state().units().get(&unit_id).is_none() {
+            ReactionFireResult::Killed
         } else {
-            old_id + 1
-        }}
+            ReactionFireResult::Attacked
+        }
     }
 
-    fn simulation_step(&mut self, command: Command) {
-        if let Err(err) = check_command(
-            &self.db,
-            self.current_player_id,
-            &self.state,
-            &command,
-        ) {
-            println!("Bad command: {:?}", err);
-            return;
+    fn get_scattered_pos(&self, pos: MapPos) -> MapPos {
+        let scatter_chance = 30;
+        if thread_rng().gen_range(0, 100) >= scatter_chance {
+            return pos;
         }
-        match command {
-            Command::EndTurn => {
-                let old_id = self.current_player_id;
-                let new_id = self.next_player_id(old_id);
-                // TODO: extruct func
-                let mut end_turn_events = Vec::new();
-                for sector in self.state.sectors().values() {
-                    if let Some(player_id) = sector.owner_id {
-                        if player_id != new_id {
-                            continue;
-                        }
-                        end_turn_events.push(CoreEvent::VictoryPoint {
-                            player_id: player_id,
-                            pos: sector.center(),
-                            count: 1,
-                        });
-                    }
+        let dir = Dir::from_int(thread_rng().gen_range(0, 6));
+        let scattered_pos = Dir::get_neighbour_pos(pos, dir);
+        if self.state().map().is_inboard(scattered_pos) {
+            scattered_pos
+        } else {
+            pos
+        }
+    }
+
+    /// Shells hit every ground unit in the tile, including friendly ones
+    fn area_attack_events(
+        &self,
+        weapon_type: &WeaponType,
+        weapon_skill: i32,
+        pos: MapPos,
+    ) -> Vec<CoreEvent> {
+        let mut events = Vec::new();
+        let units_at = self.state().units_at(pos);
+        let passenger_ids: Vec<_> = units_at.iter()
+            .filter_map(|unit| unit.passenger_id).collect();
+        for defender in units_at {
+            let defender_type = self.db().unit_type(defender.type_id);
+            if defender_type.is_air || passenger_ids.contains(&defender.id) {
+                continue;
+            }
+            let hit_chance = area_hit_chance(
+                self.db(), self.state(), weapon_type, weapon_skill, defender).total;
+            let killed = cmp::min(
+                defender.count, self.roll_killed_count(hit_chance, defender));
+            events.push(CoreEvent::AttackUnit {
+                attack_info: AttackInfo {
+                    attacker_id: None,
+                    defender_id: defender.id,
+                    killed: killed,
+                    mode: FireMode::Active,
+                    suppression: defender_suppression(defender, killed),
+                    remove_move_points: false,
+                    is_ambush: false,
+                    is_inderect: true,
+                    is_return_fire: false,
+                },
+            });
+        }
+        events
+    }
+
+    fn area_fire(&mut self, unit_id: UnitId, pos: MapPos) {
+        let pos = self.get_scattered_pos(pos);
+        let mut events = vec![CoreEvent::AreaFire {
+            attacker_id: Some(unit_id),
+            pos: pos,
+        }];
+        {
+            let attacker_type = self.db().unit_type(self.state().unit(unit_id).type_id);
+            let weapon_type = self.db().weapon_type(attacker_type.weapon_type_id);
+            events.extend(self.area_attack_events(
+                weapon_type, attacker_type.weapon_skill, pos));
+        }
+        for event in &events {
+            self.do_core_event(event);
+        }
+        // shells can scatter back on the shooter
+        if self.state().units().get(&unit_id).is_some() {
+            self.reaction_fire(unit_id);
+        }
+    }
+
+    /// Artillery shells the whole neighbourhood of the target tile,
+    /// an air strike hits only the target tile
+    fn support_strike(&mut self, call: &SupportCall) {
+        let mut positions = vec![call.pos];
+        if call.support_type == SupportType::Artillery {
+            for dir in dirs() {
+                let pos = Dir::get_neighbour_pos(call.pos, dir);
+                if self.state().map().is_inboard(pos) {
+                    positions.push(pos);
                 }
-                for (&object_id, object) in self.state.objects() {
-                    if let Some(timer) = object.timer {
-                        if timer == 0 {
-                            end_turn_events.push(CoreEvent::RemoveSmoke {
-                                id: object_id,
-                            });
-                        }
+            }
+        }
+        let mut events = vec![CoreEvent::SupportStrike{call: call.clone()}];
+        {
+            let weapon_type_id = self.db().weapon_type_id(
+                call.support_type.weapon_type_name());
+            let weapon_type = self.db().weapon_type(weapon_type_id);
+            // off-map weapons have no crew here, their
+            // accuracy already includes the crew's skill
+            let weapon_skill = 0;
+            for pos in positions {
+                events.extend(self.area_attack_events(
+                    weapon_type, weapon_skill, pos));
+            }
+        }
+        for event in &events {
+            self.do_core_event(event);
+        }
+    }
+
+    /// One turn of engineer work on a bridge, an entrenchment or a minefield
+    fn construct(&mut self, unit_id: UnitId, pos: MapPos, class: ObjectClass) {
+        // minefields take one turn and a minefield that is
+        // already on the tile can belong to the enemy
+        let (id, progress) = match find_object_at(self.state(), pos, class) {
+            Some(id) if class != ObjectClass::Minefield => {
+                let progress = self.state().objects()[&id].progress
+                    .expect("Object is already constructed");
+                (id, progress + 1)
+            },
+            _ => (self.get_new_object_id(), 1),
+        };
+        self.do_core_event(&CoreEvent::Construct {
+            id: id,
+            pos: pos,
+            class: class,
+            unit_id: Some(unit_id),
+            progress: if progress < construction_time(class) {
+                Some(progress)
+            } else {
+                None
+            },
+        });
+        self.reaction_fire(unit_id);
+    }
+
+    fn clear_minefield(&mut self, unit_id: UnitId, pos: MapPos) {
+        let id = find_object_at(self.state(), pos, ObjectClass::Minefield)
+            .expect("No minefield to clear");
+        // everybody who sees the tile must know what is cleared
+        let events = [
+            CoreEvent::RevealObject {
+                id: id,
+                object: self.state().objects()[&id].clone(),
+            },
+            CoreEvent::ClearMinefield {
+                id: id,
+                unit_id: Some(unit_id),
+            },
+        ];
+        for event in &events {
+            self.do_core_event(event);
+        }
+        self.reaction_fire(unit_id);
+    }
+
+    /// Returns `false` if the move was interrupted
+    fn move_unit(
+        &mut self,
+        unit_id: UnitId,
+        path: &[ExactPos],
+        mode: MoveMode,
+    ) -> bool {
+        for window in path.windows(2) {
+            if !self.move_unit_step(unit_id, window[0], window[1], mode) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Returns `false` if the move was interrupted
+    fn move_unit_step(
+        &mut self,
+        unit_id: UnitId,
+        from: ExactPos,
+        to: ExactPos,
+        mode: MoveMode,
+    ) -> bool {
+        let player_id = self.state().unit(unit_id).player_id;
+        let event = {
+            let unit = self.state().unit(unit_id);
+            let cost = MovePoints {
+                n: tile_cost(self.db(), self.state(), unit, from, to).n
+                    * move_cost_modifier(mode)
+            };
+            CoreEvent::Move {
+                unit_id: unit_id,
+                from: from,
+                to: to,
+                mode: mode,
+                cost: cost,
+            }
+        };
+        let pre_visible_enemies = self.visible_enemies(player_id).clone();
+        self.do_core_event(&event);
+        let minefield_result = self.minefield_attack(unit_id);
+        if minefield_result == ReactionFireResult::Killed {
+            return false;
+        }
+        let reaction_fire_result = self.reaction_fire_internal(
+            unit_id, Some(mode), None);
+        if minefield_result != ReactionFireResult::None
+            || reaction_fire_result != ReactionFireResult::None
+        {
+            return false;
+        }
+        pre_visible_enemies == *self.visible_enemies(player_id)
+    }
+
+    /// All members step in turns, so the whole
+    /// group stops if anybody runs into trouble
+    fn group_move(&mut self, plan: &[(UnitId, Vec<ExactPos>)], mode: MoveMode) {
+        let unit_ids: Vec<_> = plan.iter().map(|&(id, _)| id).collect();
+        // index of every member's current position in its path
+        let mut steps = vec![0; plan.len()];
+        loop {
+            let mut is_anybody_moved = false;
+            for (&(unit_id, ref path), step) in plan.iter().zip(&mut steps) {
+                if *step + 1 >= path.len() {
+                    continue;
+                }
+                let to = path[*step + 1];
+                let type_id = self.state().unit(unit_id).type_id;
+                if !is_exact_pos_free(self.db(), self.state(), type_id, to) {
+                    let is_blocked_by_group = self.state().units_at(to.map_pos)
+                        .iter()
+                        .any(|unit| unit_ids.contains(&unit.id));
+                    if is_blocked_by_group {
+                        // waits till the other member moves on
+                        continue;
                     }
+                    // the plan knows nothing about hidden enemies
+                    return;
                 }
-                for event in end_turn_events {
-                    self.do_core_event(&event);
+                if !self.move_unit_step(unit_id, path[*step], to, mode) {
+                    return;
                 }
-                self.do_core_event(&CoreEvent::EndTurn {
-                    old_id: old_id,
-                    new_id: new_id,
-                });
+                *step += 1;
+                is_anybody_moved = true;
+            }
+            if !is_anybody_moved {
+                return;
+            }
+        }
+    }
+
+    /// Plays a command that has already been checked. Only `Core`
+    /// knows the order of the turns and the history of the moves.
+    fn do_unit_command(&mut self, player_id: PlayerId, command: &Command) {
+        match *command {
+            Command::EndTurn | Command::Undo => {},
+            Command::Move{unit_id, ref path, mode} => {
+                self.move_unit(unit_id, path, mode);
+            },
+            Command::GroupMove{ref unit_ids, pos, mode} => {
+                let plan = plan_group_move(self.db(), self.state(), unit_ids, pos, mode);
+                self.group_move(&plan, mode);
             },
             Command::CreateUnit{pos, type_id} => {
                 let event = CoreEvent::CreateUnit {
@@ -945,56 +1641,31 @@ impl Core {
                         unit_id: self.get_new_unit_id(),
                         pos: pos,
                         type_id: type_id,
-                        player_id: self.current_player_id,
+                        player_id: player_id,
                         passenger_id: None,
+                        is_entrenched: false,
                     },
                 };
                 self.do_core_event(&event);
             },
-            Command::Move{unit_id, path, mode} => {
-                let player_id = self.state.unit(unit_id).player_id;
-                for window in path.windows(2) {
-                    let from = window[0];
-                    let to = window[1];
-                    let event = {
-                        let unit = self.state.unit(unit_id);
-                        let cost = MovePoints {
-                            n: tile_cost(&self.db, &self.state, unit, from, to).n
-                                * move_cost_modifier(mode)
-                        };
-                        CoreEvent::Move {
-                            unit_id: unit_id,
-                            from: from,
-                            to: to,
-                            mode: mode,
-                            cost: cost,
-                        }
-                    };
-                    let pre_visible_enemies = self.players_info[&player_id]
-                        .visible_enemies.clone();
-                    self.do_core_event(&event);
-                    let reaction_fire_result = self.reaction_fire_internal(
-                        unit_id, mode == MoveMode::Fast);
-                    if reaction_fire_result != ReactionFireResult::None {
-                        break;
-                    }
-                    let i = &self.players_info[&player_id];
-                    if pre_visible_enemies != i.visible_enemies {
-                        break;
-                    }
-                }
-            },
             Command::AttackUnit{attacker_id, defender_id} => {
                 if let Some(ref event) = self.command_attack_unit_to_event(
-                    attacker_id, defender_id, FireMode::Active)
+                    attacker_id, defender_id, FireMode::Active, false)
                 {
                     self.do_core_event(event);
-                    self.reaction_fire(attacker_id);
+                    self.return_fire(defender_id, attacker_id);
+                    if self.state().units().get(&attacker_id).is_some() {
+                        self.reaction_fire_internal(
+                            attacker_id, None, Some(defender_id));
+                    }
                 }
             },
+            Command::AreaFire{unit_id, pos} => {
+                self.area_fire(unit_id, pos);
+            },
             Command::LoadUnit{transporter_id, passenger_id} => {
-                let from = self.state.unit(passenger_id).pos;
-                let to = self.state.unit(transporter_id).pos;
+                let from = self.state().unit(passenger_id).pos;
+                let to = self.state().unit(transporter_id).pos;
                 self.do_core_event(&CoreEvent::LoadUnit {
                     transporter_id: Some(transporter_id),
                     passenger_id: passenger_id,
@@ -1004,8 +1675,8 @@ impl Core {
             },
             Command::UnloadUnit{transporter_id, passenger_id, pos} => {
                 let event = {
-                    let passenger = self.state.unit(passenger_id);
-                    let from = self.state.unit(transporter_id).pos;
+                    let passenger = self.state().unit(passenger_id);
+                    let from = self.state().unit(transporter_id).pos;
                     CoreEvent::UnloadUnit {
                         transporter_id: Some(transporter_id),
                         unit_info: UnitInfo {
@@ -1026,7 +1697,7 @@ impl Core {
                 });
             },
             Command::Smoke{unit_id, pos} => {
-                let id = self.get_new_object_id();
+                let id = self.get_smoke_id(pos);
                 self.do_core_event(&CoreEvent::Smoke {
                     id: id,
                     unit_id: Some(unit_id),
@@ -1034,9 +1705,9 @@ impl Core {
                 });
                 let mut dir = Dir::from_int(thread_rng().gen_range(0, 5));
                 let additional_smoke_count = {
-                    let unit = self.state.unit(unit_id);
-                    let unit_type = self.db.unit_type(unit.type_id);
-                    let weapon_type = self.db.weapon_type(unit_type.weapon_type_id);
+                    let unit = self.state().unit(unit_id);
+                    let unit_type = self.db().unit_type(unit.type_id);
+                    let weapon_type = self.db().weapon_type(unit_type.weapon_type_id);
                     weapon_type.smoke.unwrap()
                 };
                 assert!(additional_smoke_count <= 3);
@@ -1046,37 +1717,361 @@ impl Core {
                         dir_index -= 6;
                     }
                     dir = Dir::from_int(dir_index);
-                    let id = self.get_new_object_id();
+                    let smoke_pos = Dir::get_neighbour_pos(pos, dir);
+                    if !self.state().map().is_inboard(smoke_pos) {
+                        continue;
+                    }
+                    let id = self.get_smoke_id(smoke_pos);
                     self.do_core_event(&CoreEvent::Smoke {
                         id: id,
                         unit_id: Some(unit_id),
-                        pos: Dir::get_neighbour_pos(pos, dir),
+                        pos: smoke_pos,
                     });
                 }
                 self.reaction_fire(unit_id);
             },
+            Command::DigIn{unit_id} => {
+                self.do_core_event(&CoreEvent::DigIn{unit_id: unit_id});
+            },
+            Command::BuildBridge{unit_id, pos} => {
+                self.construct(unit_id, pos, ObjectClass::Bridge);
+            },
+            Command::BuildEntrenchment{unit_id, pos} => {
+                self.construct(unit_id, pos, ObjectClass::Entrenchment);
+            },
+            Command::PlaceMinefield{unit_id, pos} => {
+                self.construct(unit_id, pos, ObjectClass::Minefield);
+            },
+            Command::ClearMinefield{unit_id, pos} => {
+                self.clear_minefield(unit_id, pos);
+            },
+            Command::CallSupport{pos, support_type} => {
+                self.do_core_event(&CoreEvent::CallSupport {
+                    call: SupportCall {
+                        player_id: player_id,
+                        pos: pos,
+                        support_type: support_type,
+                    },
+                });
+            },
+        }
+    }
+
+    /// Returns `true` if any sector has changed its owner
+    fn update_sectors(&mut self) -> bool {
+        let events = check_sectors(self.db(), self.state());
+        for event in &events {
+            self.do_core_event(event);
+        }
+        !events.is_empty()
+    }
+}
+
+impl Core {
+    /// Fails if some bot can't be created
+    pub fn new(options: &Options) -> io::Result<Core> {
+        let db = Db::new();
+        let state = InternalState::new(&options.map_name);
+        let players_info = get_player_info_lists(&db, &state);
+        let bot_factories = get_bot_factories(options);
+        let mut bots = HashMap::new();
+        for (&id, factory) in &bot_factories {
+            bots.insert(id, factory(id, options)?);
+        }
+        let cheating_bot_ids = options.ai_profiles.iter()
+            .filter(|&(id, profile)| bot_factories.contains_key(id) && profile.is_cheating)
+            .map(|(&id, _)| id)
+            .collect();
+        Ok(Core {
+            state: state,
+            players: get_players_list(&bot_factories),
+            current_player_id: PlayerId{id: 0},
+            db: db,
+            bots: bots,
+            cheating_bot_ids: cheating_bot_ids,
+            players_info: players_info,
+            observers: HashMap::new(),
+            last_move: None,
+        })
+    }
+
+    pub fn db(&self) -> &Db {
+        &self.db
+    }
+
+    pub fn map_size(&self) -> Size2 {
+        self.state.map().size()
+    }
+
+    pub fn player(&self) -> &Player {
+        self.player_by_id(self.player_id())
+    }
+
+    /// Players are kept in the turn order, not in the order of their ids
+    fn player_by_id(&self, id: PlayerId) -> &Player {
+        self.players.iter()
+            .find(|player| player.id == id)
+            .expect("core: Can`t find the player")
+    }
+
+    pub fn player_id(&self) -> PlayerId {
+        self.current_player_id
+    }
+
+    pub fn get_event(&mut self) -> Option<CoreEvent> {
+        let mut i = self.players_info.get_mut(&self.current_player_id)
+            .expect("core: Can`t get current player`s info");
+        i.events.pop_front()
+    }
+
+    /// Observer receives only events that happen after it was added
+    pub fn add_observer(&mut self, vision: ObserverVision) -> ObserverId {
+        let id = match self.observers.keys().max() {
+            Some(&id) => ObserverId{id: id.id + 1},
+            None => ObserverId{id: 0},
+        };
+        let info = match vision {
+            ObserverVision::Full => ObserverInfo::Full(VecDeque::new()),
+            ObserverVision::Players(ref player_ids) => ObserverInfo::Players(
+                Box::new(new_shared_info(&self.db, &self.state, player_ids))),
+        };
+        self.observers.insert(id, info);
+        id
+    }
+
+    pub fn remove_observer(&mut self, observer_id: ObserverId) {
+        self.observers.remove(&observer_id);
+    }
+
+    pub fn get_observer_event(&mut self, observer_id: ObserverId) -> Option<CoreEvent> {
+        match *self.observers.get_mut(&observer_id)
+            .expect("core: Can`t get observer`s info")
+        {
+            ObserverInfo::Full(ref mut events) => events.pop_front(),
+            ObserverInfo::Players(ref mut i) => i.events.pop_front(),
+        }
+    }
+
+    pub fn next_player_id(&self, id: PlayerId) -> PlayerId {
+        let index = self.players.iter()
+            .position(|player| player.id == id)
+            .unwrap();
+        self.players[(index + 1) % self.players.len()].id
+    }
+
+    /// Object timers are measured in full rounds: they tick only
+    /// when the turn passes back to the first player in the list.
+    fn is_new_round(&self, new_id: PlayerId) -> bool {
+        self.players[0].id == new_id
+    }
+
+    /// Checks the command against what the player knows first,
+    /// so that the errors give away nothing about the fog
+    fn check_player_command(
+        &self,
+        player_id: PlayerId,
+        command: &Command,
+    ) -> Result<(), CommandError> {
+        if *command == Command::Undo && self.last_move.is_none() {
+            return Err(CommandError::NothingToUndo);
+        }
+        let is_cheating = self.cheating_bot_ids.contains(&player_id);
+        if !is_cheating {
+            let game_state = &self.players_info[&player_id].game_state;
+            check_command(&self.db, player_id, game_state, command)?;
+        }
+        check_command(&self.db, player_id, &self.state, command).map_err(|err| {
+            // the player's view allows the command, so the real
+            // reason is something that the player can't see
+            if is_cheating {
+                err
+            } else {
+                CommandError::UnseenObstacle
+            }
+        })?;
+        let fow = &self.players_info[&player_id].fow;
+        match *command {
+            // the full state hides nothing, so cheating bots
+            // need a spotter in their real fog
+            Command::AttackUnit{attacker_id, defender_id} if is_cheating => {
+                let attacker_type = self.db.unit_type(
+                    self.state.unit(attacker_id).type_id);
+                let weapon_type = self.db.weapon_type(attacker_type.weapon_type_id);
+                let defender = self.state.unit(defender_id);
+                let is_spotted = fow.is_visible(
+                    &self.db, &self.state, defender, defender.pos);
+                if weapon_type.is_inderect && !is_spotted {
+                    Err(CommandError::NoSpotter)
+                } else {
+                    Ok(())
+                }
+            },
+            // support can be called only onto a tile that the player sees
+            Command::CallSupport{pos, ..}
+                if !fow.is_tile_visible(pos) =>
+            {
+                Err(CommandError::NoLos)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn simulation_step(&mut self, command: Command) {
+        let player_id = self.current_player_id;
+        if let Err(err) = self.check_player_command(player_id, &command) {
+            println!("Bad command: {:?}", err);
+            return;
+        }
+        let last_move = self.last_move.take();
+        match command {
+            Command::EndTurn => {
+                let old_id = self.current_player_id;
+                let new_id = self.next_player_id(old_id);
+                // TODO: extruct func
+                let mut end_turn_events = Vec::new();
+                let support_calls: Vec<_> = self.state.support_calls().iter()
+                    .filter(|call| call.player_id == new_id)
+                    .cloned()
+                    .collect();
+                for call in &support_calls {
+                    self.support_strike(call);
+                }
+                for sector in self.state.sectors().values() {
+                    if let Some(player_id) = sector.owner_id {
+                        if player_id != new_id {
+                            continue;
+                        }
+                        end_turn_events.push(CoreEvent::VictoryPoint {
+                            player_id: player_id,
+                            pos: sector.center(),
+                            count: 1,
+                        });
+                    }
+                }
+                for unit in self.state.units().values() {
+                    if unit.player_id == new_id && unit.is_digging_in {
+                        end_turn_events.push(CoreEvent::Entrench{unit_id: unit.id});
+                    }
+                }
+                let is_new_round = self.is_new_round(new_id);
+                if is_new_round {
+                    for (&object_id, object) in self.state.objects() {
+                        if let Some(timer) = object.timer {
+                            if timer == 0 {
+                                end_turn_events.push(CoreEvent::RemoveSmoke {
+                                    id: object_id,
+                                });
+                            }
+                        }
+                    }
+                }
+                for event in end_turn_events {
+                    self.do_core_event(&event);
+                }
+                self.do_core_event(&CoreEvent::EndTurn {
+                    old_id: old_id,
+                    new_id: new_id,
+                    is_new_round: is_new_round,
+                });
+            },
+            Command::Move{unit_id, path, mode} => {
+                let player_id = self.state.unit(unit_id).player_id;
+                let mut snapshot = MoveSnapshot {
+                    unit: self.state.unit(unit_id).clone(),
+                    cost: MovePoints{n: 0},
+                };
+                let other_events_count = self.other_players_events_count(player_id);
+                let is_completed = self.move_unit(unit_id, &path, mode);
+                // nobody else may learn anything about this move
+                if is_completed
+                    && other_events_count == self.other_players_events_count(player_id)
+                {
+                    let move_points = self.state.unit(unit_id).move_points;
+                    snapshot.cost.n = snapshot.unit.move_points.unwrap().n
+                        - move_points.unwrap().n;
+                    self.last_move = Some(snapshot);
+                }
+            },
+            Command::GroupMove{unit_ids, pos, mode} => {
+                let plan = {
+                    let game_state = &self.players_info[&player_id].game_state;
+                    plan_group_move(&self.db, game_state, &unit_ids, pos, mode)
+                };
+                self.group_move(&plan, mode);
+            },
+            Command::Undo => {
+                let snapshot = last_move.expect("Checked by `check_player_command`");
+                self.undo_move(snapshot);
+            },
+            _ => self.do_unit_command(player_id, &command),
         };
-        let sector_events = check_sectors(&self.db, &self.state);
-        for event in sector_events {
-            self.do_core_event(&event);
+        if self.update_sectors() {
+            self.last_move = None;
         }
     }
 
+    pub fn can_undo(&self) -> bool {
+        self.last_move.is_some()
+    }
+
+    fn other_players_events_count(&self, player_id: PlayerId) -> usize {
+        self.players_info.iter()
+            .filter(|&(&id, _)| id != player_id)
+            .map(|(_, i)| i.events.len())
+            .sum()
+    }
+
+    fn undo_move(&mut self, snapshot: MoveSnapshot) {
+        let unit = snapshot.unit;
+        let from = self.state.unit(unit.id).pos;
+        self.do_core_event(&CoreEvent::UndoMove {
+            unit_id: unit.id,
+            from: from,
+            to: unit.pos,
+            cost: snapshot.cost,
+            move_mode: unit.move_mode,
+            is_entrenched: unit.is_entrenched,
+        });
+    }
+
     pub fn do_command(&mut self, command: Command) {
         self.simulation_step(command);
     }
 
-    fn do_ai(&mut self) {
+    /// Lets the bot of the current player play until it ends the turn.
+    ///
+    /// A bot starts its turn on its own right after a human player's
+    /// turn, games between bots are driven by calling this every turn.
+    pub fn do_bot_turn(&mut self) {
+        let player_id = self.player_id();
+        let mut bot = match self.bots.remove(&player_id) {
+            Some(bot) => bot,
+            None => return,
+        };
+        let mut errors_count = 0;
         loop {
             while let Some(event) = self.get_event() {
-                self.ai.apply_event(&self.db, &event);
+                bot.apply_event(&self.db, &event);
             }
-            let command = self.ai.get_command(&self.db);
+            let command = bot.get_command(&self.db);
+            let result = self.check_player_command(player_id, &command);
+            if let Err(err) = result {
+                bot.report_error(&self.db, &command, &err);
+                errors_count += 1;
+                if errors_count < MAX_BOT_ERRORS {
+                    continue;
+                }
+                // a bot that keeps breaking the rules loses the rest of its turn
+                self.do_command(Command::EndTurn);
+                break;
+            }
+            errors_count = 0;
             self.do_command(command.clone());
             if command == Command::EndTurn {
-                return;
+                break;
             }
         }
+        self.bots.insert(player_id, bot);
     }
 
     fn handle_end_turn_event(&mut self, old_id: PlayerId, new_id: PlayerId) {
@@ -1088,45 +2083,115 @@ impl Core {
                 break;
             }
         }
+        let is_after_human = self.player_by_id(old_id).class == PlayerClass::Human;
         if self.player().class == PlayerClass::Ai
             && new_id == self.player_id()
+            && is_after_human
         {
-            self.do_ai();
+            self.do_bot_turn();
         }
     }
 
-    fn do_core_event(&mut self, event: &CoreEvent) {
-        self.state.apply_event(&self.db, event);
+    /// Filters the event for every player and every observer
+    /// that does not see everything
+    fn filter_events(
+        &self,
+        event: &CoreEvent,
+    ) -> AllFilteredEvents {
+        let mut filtered_events = Vec::new();
         for player in &self.players {
-            let (filtered_events, active_unit_ids) = filter::filter_events(
+            let i = &self.players_info[&player.id];
+            filtered_events.push((player.id, filter::filter_events(
                 &self.db,
                 &self.state,
-                player.id,
-                &self.players_info[&player.id].fow,
+                &i.fow,
+                &i.game_state,
                 event,
-            );
-            let mut i = self.players_info.get_mut(&player.id)
-                .expect("core: Can`t get player`s info");
-            for event in filtered_events {
-                i.fow.apply_event(&self.db, &self.state, &event);
-                i.events.push_back(event);
-                let new_visible_enemies = filter::get_visible_enemies(
+            )));
+        }
+        let mut observers_events = Vec::new();
+        for (&observer_id, observer) in &self.observers {
+            let filtered_events = match *observer {
+                ObserverInfo::Full(..) => (Vec::new(), HashSet::new()),
+                ObserverInfo::Players(ref i) => filter::filter_events(
                     &self.db,
                     &self.state,
                     &i.fow,
-                    player.id,
-                );
-                let show_hide_events = filter::show_or_hide_passive_enemies(
-                    self.state.units(),
-                    &active_unit_ids,
-                    &i.visible_enemies,
-                    &new_visible_enemies,
-                );
-                i.events.extend(show_hide_events);
-                i.visible_enemies = new_visible_enemies;
+                    &i.game_state,
+                    event,
+                ),
+            };
+            observers_events.push((observer_id, filtered_events));
+        }
+        (filtered_events, observers_events)
+    }
+}
+
+impl Rules for Core {
+    fn db(&self) -> &Db {
+        &self.db
+    }
+
+    fn state(&self) -> &InternalState {
+        &self.state
+    }
+
+    fn fow(&self, player_id: PlayerId) -> &Fow {
+        &self.players_info[&player_id].fow
+    }
+
+    fn visible_enemies(&self, player_id: PlayerId) -> &HashSet<UnitId> {
+        &self.players_info[&player_id].visible_enemies
+    }
+
+    fn do_core_event(&mut self, event: &CoreEvent) {
+        let (filtered_events, observers_events) = match *event {
+            CoreEvent::AttackUnit{ref attack_info}
+                if attack_info.attacker_id.is_none() =>
+            {
+                // only the defender tells who sees an attack without
+                // an attacker and it can be killed by the attack,
+                // so this event is filtered before it is applied
+                let filtered_events = self.filter_events(event);
+                self.state.apply_event(&self.db, event);
+                filtered_events
+            },
+            CoreEvent::ClearMinefield{..} => {
+                // the minefield tells who sees this
+                let filtered_events = self.filter_events(event);
+                self.state.apply_event(&self.db, event);
+                filtered_events
+            },
+            _ => {
+                self.state.apply_event(&self.db, event);
+                self.filter_events(event)
+            },
+        };
+        for (player_id, filtered_events) in filtered_events {
+            let sees_everything = self.cheating_bot_ids.contains(&player_id);
+            let i = self.players_info.get_mut(&player_id)
+                .expect("core: Can`t get player`s info");
+            let first_new_event = i.events.len();
+            apply_filtered_events(&self.db, &self.state, i, filtered_events);
+            if sees_everything {
+                // the raw event instead of everything that was filtered
+                i.events.truncate(first_new_event);
+                i.events.push_back(event.clone());
+            }
+        }
+        for (observer_id, filtered_events) in observers_events {
+            match *self.observers.get_mut(&observer_id)
+                .expect("core: Can`t get observer`s info")
+            {
+                ObserverInfo::Full(ref mut events) => {
+                    events.push_back(event.clone());
+                },
+                ObserverInfo::Players(ref mut i) => {
+                    apply_filtered_events(&self.db, &self.state, i, filtered_events);
+                },
             }
         }
-        if let CoreEvent::EndTurn{old_id, new_id} = *event {
+        if let CoreEvent::EndTurn{old_id, new_id, ..} = *event {
             self.handle_end_turn_event(old_id, new_id);
         }
     }
@@ -1134,11 +2199,136 @@ impl Core {
 
 #[cfg(test)]
 mod tests {
+    use std::io;
+    use std::collections::{HashMap};
     use cgmath::{Vector2};
-    use ::{Sector, MapPos};
+    use db::{Db};
+    use threat_map::{ThreatMap};
+    use search_ai::{SearchAi, Budget};
+    use external_bot::{ExternalBot, parse_command, format_event};
+    use game_state::{GameState, GameStateMut};
+    use partial_state::{PartialState};
+    use ::{
+        Core,
+        Rules,
+        UnitId,
+        ObserverId,
+        ObserverVision,
+        AiProfile,
+        AiPersonality,
+        AiDifficulty,
+        Bot,
+        BotFactory,
+        MAX_BOT_ERRORS,
+        new_default_bot,
+        simulate_command,
+        new_external_bot,
+        CoreEvent,
+        Options,
+        GameType,
+        Command,
+        MoveMode,
+        MovePoints,
+        ReactionFireMode,
+        OVERWATCH_SECTOR_RADIUS,
+        FireMode,
+        AttackInfo,
+        SupportType,
+        Sector,
+        MapPos,
+        ExactPos,
+        SlotId,
+        PlayerId,
+        Object,
+        ObjectId,
+        ObjectClass,
+        UnitInfo,
+        get_free_exact_pos,
+        find_object_at,
+        construction_time,
+        smoke_at,
+    };
+    use check::{check_command, CommandError};
+    use hit_chance::{hit_chance, attack_suppression, defender_suppression};
+    use info::{describe_unit, describe_terrain};
+    use map::{Terrain, distance};
+    use group_move::{GROUP_MOVE_RADIUS};
+    use pathfinder::{tile_cost};
+    use ai::{Ai};
 
-    #[test]
-    fn test_center_1() {
+    fn new_core_on_map(map_name: &str) -> Core {
+        Core::new(&Options {
+            game_type: GameType::Hotseat,
+            map_name: map_name.to_owned(),
+            ai_profiles: HashMap::new(),
+            bots: HashMap::new(),
+            bot_commands: HashMap::new(),
+        }).unwrap()
+    }
+
+    fn new_core() -> Core {
+        new_core_on_map("map02")
+    }
+
+    /// Player 1 is controlled by the AI
+    fn new_ai_core() -> Core {
+        Core::new(&Options {
+            game_type: GameType::SingleVsAi,
+            map_name: "map02".to_owned(),
+            ai_profiles: HashMap::new(),
+            bots: HashMap::new(),
+            bot_commands: HashMap::new(),
+        }).unwrap()
+    }
+
+    /// Puts a unit of the current player anywhere on the map
+    fn place_unit(core: &mut Core, type_name: &str, pos: MapPos) -> UnitId {
+        let player_id = core.player_id();
+        place_player_unit(core, player_id, type_name, pos)
+    }
+
+    fn place_player_unit(
+        core: &mut Core,
+        player_id: PlayerId,
+        type_name: &str,
+        pos: MapPos,
+    ) -> UnitId {
+        let type_id = core.db().unit_type_id(type_name);
+        let pos = get_free_exact_pos(core.db(), &core.state, type_id, pos).unwrap();
+        let unit_id = core.get_new_unit_id();
+        core.do_core_event(&CoreEvent::CreateUnit {
+            unit_info: UnitInfo {
+                unit_id: unit_id,
+                pos: pos,
+                type_id: type_id,
+                player_id: player_id,
+                passenger_id: None,
+                is_entrenched: false,
+            },
+        });
+        unit_id
+    }
+
+    fn create_unit(core: &mut Core, type_name: &str, pos: MapPos) -> UnitId {
+        let type_id = core.db().unit_type_id(type_name);
+        let pos = get_free_exact_pos(core.db(), &core.state, type_id, pos).unwrap();
+        core.do_command(Command::CreateUnit{pos: pos, type_id: type_id});
+        *core.state.units().keys().max().unwrap()
+    }
+
+    fn end_round(core: &mut Core) {
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::EndTurn);
+    }
+
+    fn smoke_count(core: &Core) -> usize {
+        core.state.objects().values()
+            .filter(|object| object.class == ObjectClass::Smoke)
+            .count()
+    }
+
+    #[test]
+    fn test_center_1() {
         let real = Sector {
             positions: vec![
                 MapPos{v: Vector2{x: 5, y: 0}},
@@ -1168,4 +2358,1816 @@ mod tests {
         let expected = MapPos{v: Vector2{x: 6, y: 1}};
         assert_eq!(expected, real);
     }
+
+    #[test]
+    fn test_smoke_lifecycle() {
+        let mut core = new_core();
+        let unit_id = create_unit(&mut core, "mortar", MapPos{v: Vector2{x: 0, y: 4}});
+        end_round(&mut core);
+        let target_pos = MapPos{v: Vector2{x: 3, y: 4}};
+        core.do_command(Command::Smoke{unit_id: unit_id, pos: target_pos});
+        let smoke_id = smoke_at(&core.state, target_pos).unwrap();
+        let count = smoke_count(&core);
+        assert!(count >= 1);
+        end_round(&mut core);
+        core.do_command(Command::Smoke{unit_id: unit_id, pos: target_pos});
+        assert_eq!(smoke_at(&core.state, target_pos), Some(smoke_id));
+        assert_eq!(core.state.objects()[&smoke_id].timer, Some(::SMOKE_TIMER));
+        for _ in 0 .. ::SMOKE_TIMER {
+            end_round(&mut core);
+            assert!(smoke_at(&core.state, target_pos).is_some());
+        }
+        end_round(&mut core);
+        assert_eq!(smoke_count(&core), 0);
+    }
+
+    fn is_new_round_after_end_turn(core: &mut Core) -> bool {
+        core.do_command(Command::EndTurn);
+        let mut is_new_round = None;
+        while let Some(event) = core.get_event() {
+            if let CoreEvent::EndTurn{is_new_round: value, ..} = event {
+                is_new_round = Some(value);
+            }
+        }
+        is_new_round.unwrap()
+    }
+
+    #[test]
+    fn test_new_round_follows_players_list() {
+        let mut core = new_core();
+        assert!(!is_new_round_after_end_turn(&mut core));
+        assert!(is_new_round_after_end_turn(&mut core));
+        core.players.reverse();
+        assert!(is_new_round_after_end_turn(&mut core));
+        assert_eq!(core.player_id(), PlayerId{id: 1});
+        assert_eq!(core.player().id, PlayerId{id: 1});
+        assert!(!is_new_round_after_end_turn(&mut core));
+        assert_eq!(core.player_id(), PlayerId{id: 0});
+        assert_eq!(core.player().id, PlayerId{id: 0});
+    }
+
+    #[test]
+    fn test_enemy_minefields_are_hidden() {
+        let state = PartialState::new("map01", PlayerId{id: 0});
+        for object in state.objects().values() {
+            assert!(object.class != ObjectClass::Minefield);
+        }
+        let state = PartialState::new("map01", PlayerId{id: 1});
+        assert!(state.objects().values().any(
+            |object| object.class == ObjectClass::Minefield));
+    }
+
+    #[test]
+    fn test_minefield_stops_moving_unit() {
+        let mut core = new_core();
+        let unit_id = create_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        end_round(&mut core);
+        let mine_pos = MapPos{v: Vector2{x: 1, y: 4}};
+        let mine_id = ObjectId{id: 100};
+        core.state.apply_event(&core.db, &CoreEvent::RevealObject {
+            id: mine_id,
+            object: Object {
+                pos: ExactPos{map_pos: mine_pos, slot_id: SlotId::WholeTile},
+                class: ObjectClass::Minefield,
+                timer: None,
+                owner_id: Some(PlayerId{id: 1}),
+                progress: None,
+            },
+        });
+        let from = core.state.unit(unit_id).pos;
+        let to = get_free_exact_pos(
+            &core.db, &core.state, core.state.unit(unit_id).type_id, mine_pos).unwrap();
+        let next_pos = MapPos{v: Vector2{x: 2, y: 4}};
+        let next = ExactPos{map_pos: next_pos, slot_id: to.slot_id};
+        let count = core.state.unit(unit_id).count;
+        core.do_command(Command::Move {
+            unit_id: unit_id,
+            path: vec![from, to, next],
+            mode: MoveMode::Fast,
+        });
+        let mut is_mine_revealed = false;
+        let mut killed = None;
+        for event in &core.players_info[&PlayerId{id: 0}].events {
+            match *event {
+                CoreEvent::RevealObject{id, ..} => {
+                    is_mine_revealed |= id == mine_id;
+                },
+                CoreEvent::AttackUnit{ref attack_info}
+                    if attack_info.defender_id == unit_id =>
+                {
+                    assert!(attack_info.attacker_id.is_none());
+                    killed = Some(attack_info.killed);
+                },
+                _ => {},
+            }
+        }
+        assert!(is_mine_revealed);
+        let killed = killed.expect("The mine must attack the unit");
+        match core.state.units().get(&unit_id) {
+            Some(unit) => {
+                assert!(killed < count);
+                assert_eq!(unit.count, count - killed);
+                assert_eq!(unit.pos.map_pos, mine_pos);
+                assert_eq!(unit.move_points.unwrap().n, 0);
+            },
+            None => assert_eq!(killed, count),
+        }
+        for event in &core.players_info[&PlayerId{id: 1}].events {
+            if let CoreEvent::RevealObject{..} = *event {
+                panic!("Mine owner must not get reveal events");
+            }
+        }
+    }
+
+    #[test]
+    fn test_engineer_places_minefield() {
+        let mut core = new_core();
+        let engineer_pos = MapPos{v: Vector2{x: 1, y: 8}};
+        let mine_pos = MapPos{v: Vector2{x: 2, y: 8}};
+        let unit_id = place_unit(&mut core, "engineer", engineer_pos);
+        end_round(&mut core);
+        let command = Command::PlaceMinefield{unit_id: unit_id, pos: mine_pos};
+        core.do_command(command.clone());
+        let id = find_object_at(&core.state, mine_pos, ObjectClass::Minefield).unwrap();
+        let object = &core.state.objects()[&id];
+        assert_eq!(object.owner_id, Some(PlayerId{id: 0}));
+        assert!(object.is_complete());
+        assert_eq!(core.state.unit(unit_id).move_points.unwrap().n, 0);
+        for event in &core.players_info[&PlayerId{id: 1}].events {
+            if let CoreEvent::Construct{..} = *event {
+                panic!("Enemy must not see new minefields");
+            }
+        }
+        end_round(&mut core);
+        assert_eq!(
+            check_command(&core.db, PlayerId{id: 0}, &core.state, &command),
+            Err(CommandError::AlreadyConstructed),
+        );
+    }
+
+    #[test]
+    fn test_tank_traps_stop_vehicles() {
+        let core = new_core_on_map("map01");
+        let pos = MapPos{v: Vector2{x: 3, y: 1}};
+        let jeep_id = core.db.unit_type_id("jeep");
+        let soldier_id = core.db.unit_type_id("soldier");
+        assert!(get_free_exact_pos(&core.db, &core.state, jeep_id, pos).is_none());
+        assert!(get_free_exact_pos(&core.db, &core.state, soldier_id, pos).is_some());
+        let terrain = describe_terrain(&core.state, pos);
+        assert!(terrain.infantry_move_cost.is_some());
+        assert!(terrain.vehicle_move_cost.is_none());
+    }
+
+    #[test]
+    fn test_engineer_builds_bridge() {
+        let mut core = new_core_on_map("map01");
+        let engineer_pos = MapPos{v: Vector2{x: 6, y: 9}};
+        let water_pos = MapPos{v: Vector2{x: 5, y: 9}};
+        let unit_id = place_unit(&mut core, "engineer", engineer_pos);
+        let water_exact_pos = ExactPos{map_pos: water_pos, slot_id: SlotId::Id(0)};
+        let water_cost = |core: &Core| {
+            let unit = core.state.unit(unit_id);
+            tile_cost(&core.db, &core.state, unit, unit.pos, water_exact_pos).n
+        };
+        let impassable_cost = water_cost(&core);
+        let command = Command::BuildBridge{unit_id: unit_id, pos: water_pos};
+        for turn in 0 .. construction_time(ObjectClass::Bridge) {
+            end_round(&mut core);
+            core.do_command(command.clone());
+            let id = find_object_at(&core.state, water_pos, ObjectClass::Bridge).unwrap();
+            let progress = core.state.objects()[&id].progress;
+            if turn + 1 < construction_time(ObjectClass::Bridge) {
+                assert_eq!(progress, Some(turn + 1));
+                assert_eq!(water_cost(&core), impassable_cost);
+                assert_eq!(
+                    check_command(&core.db, PlayerId{id: 0}, &core.state, &command),
+                    Err(CommandError::NotEnoughMovePoints),
+                );
+            } else {
+                assert_eq!(progress, None);
+            }
+        }
+        assert!(water_cost(&core) < impassable_cost);
+        for event in &core.players_info[&PlayerId{id: 1}].events {
+            if let CoreEvent::Construct{..} = *event {
+                panic!("The bridge is out of the enemy's sight");
+            }
+        }
+        end_round(&mut core);
+        assert_eq!(
+            check_command(&core.db, PlayerId{id: 0}, &core.state, &command),
+            Err(CommandError::AlreadyConstructed),
+        );
+        // the enemy learns about the bridge once it sees the tile
+        let bridge_id = find_object_at(&core.state, water_pos, ObjectClass::Bridge).unwrap();
+        let is_bridge_known = |core: &Core| {
+            core.players_info[&PlayerId{id: 1}].game_state.objects().contains_key(&bridge_id)
+        };
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", MapPos{v: Vector2{x: 3, y: 0}});
+        core.do_command(Command::EndTurn);
+        assert!(!is_bridge_known(&core));
+        for y in 1..water_pos.v.y {
+            let event = move_event(
+                &core, enemy_id, MapPos{v: Vector2{x: 3, y: y}}, MoveMode::Fast);
+            core.do_core_event(&event);
+            if is_bridge_known(&core) {
+                break;
+            }
+        }
+        assert!(is_bridge_known(&core));
+        assert!(core.players_info[&PlayerId{id: 1}].events.iter().any(|event| {
+            match *event {
+                CoreEvent::RevealObject{id, ..} => id == bridge_id,
+                _ => false,
+            }
+        }));
+    }
+
+    #[test]
+    fn test_entrenchment_gives_cover() {
+        let mut core = new_core();
+        let pos = MapPos{v: Vector2{x: 2, y: 5}};
+        let unit_id = place_unit(&mut core, "engineer", pos);
+        let attacker_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 4, y: 5}});
+        let hit_chance = |core: &Core| hit_chance(
+            &core.db,
+            &core.state,
+            core.state.unit(attacker_id),
+            core.state.unit(unit_id),
+        ).total;
+        let open_field_hit_chance = hit_chance(&core);
+        for _ in 0 .. construction_time(ObjectClass::Entrenchment) {
+            end_round(&mut core);
+            assert_eq!(hit_chance(&core), open_field_hit_chance);
+            core.do_command(Command::BuildEntrenchment{unit_id: unit_id, pos: pos});
+        }
+        assert!(hit_chance(&core) < open_field_hit_chance);
+    }
+
+    #[test]
+    fn test_engineer_clears_enemy_minefield() {
+        let mut core = new_core_on_map("map01");
+        let mine_pos = MapPos{v: Vector2{x: 7, y: 6}};
+        let unit_id = place_unit(&mut core, "engineer", MapPos{v: Vector2{x: 8, y: 6}});
+        let soldier_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 8, y: 6}});
+        end_round(&mut core);
+        let mine_id = find_object_at(&core.state, mine_pos, ObjectClass::Minefield).unwrap();
+        assert_eq!(
+            check_command(&core.db, PlayerId{id: 0}, &core.state, &Command::ClearMinefield {
+                unit_id: soldier_id,
+                pos: mine_pos,
+            }),
+            Err(CommandError::BadUnitType),
+        );
+        let command = Command::ClearMinefield{unit_id: unit_id, pos: mine_pos};
+        assert_eq!(
+            core.check_player_command(PlayerId{id: 0}, &command),
+            Err(CommandError::NoMinefield),
+        );
+        let object = core.state.objects()[&mine_id].clone();
+        core.do_core_event(&CoreEvent::RevealObject{id: mine_id, object: object});
+        core.do_command(command);
+        assert!(core.state.objects().get(&mine_id).is_none());
+        let mut is_mine_revealed = false;
+        let mut is_mine_cleared = false;
+        for event in &core.players_info[&PlayerId{id: 0}].events {
+            match *event {
+                CoreEvent::RevealObject{id, ..} => {
+                    is_mine_revealed |= id == mine_id;
+                },
+                CoreEvent::ClearMinefield{id, ..} => {
+                    is_mine_cleared |= is_mine_revealed && id == mine_id;
+                },
+                _ => {},
+            }
+        }
+        assert!(is_mine_cleared);
+        // the owner learns about it even without seeing the tile
+        let is_owner_told = core.players_info[&PlayerId{id: 1}].events.iter()
+            .any(|event| match *event {
+                CoreEvent::ClearMinefield{id, ..} => id == mine_id,
+                _ => false,
+            });
+        assert!(is_owner_told);
+    }
+
+    #[test]
+    fn test_dig_in() {
+        let mut core = new_core();
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 2, y: 5}});
+        let hidden_unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 3, y: 7}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", MapPos{v: Vector2{x: 5, y: 5}});
+        core.do_core_event(&CoreEvent::SetReactionFireMode {
+            unit_id: enemy_id,
+            mode: ReactionFireMode::HoldFire,
+        });
+        end_round(&mut core);
+        let hit_chance = |core: &Core| hit_chance(
+            &core.db,
+            &core.state,
+            core.state.unit(enemy_id),
+            core.state.unit(unit_id),
+        ).total;
+        let open_field_hit_chance = hit_chance(&core);
+        core.do_command(Command::DigIn{unit_id: unit_id});
+        core.do_command(Command::DigIn{unit_id: hidden_unit_id});
+        {
+            let unit = core.state.unit(unit_id);
+            assert!(unit.is_digging_in);
+            assert!(!unit.is_entrenched);
+            assert_eq!(unit.move_points.unwrap().n, 0);
+            assert_eq!(hit_chance(&core), open_field_hit_chance);
+        }
+        // the enemy turn has to pass first
+        core.do_command(Command::EndTurn);
+        assert!(!core.state.unit(unit_id).is_entrenched);
+        core.do_command(Command::EndTurn);
+        {
+            let unit = core.state.unit(unit_id);
+            assert!(unit.is_entrenched);
+            assert!(!unit.is_digging_in);
+            assert!(hit_chance(&core) < open_field_hit_chance);
+            assert!(defender_suppression(unit, 1) < attack_suppression(1));
+        }
+        let mut seen_dig_in_ids = Vec::new();
+        let mut seen_entrench_ids = Vec::new();
+        for event in &core.players_info[&PlayerId{id: 1}].events {
+            match *event {
+                CoreEvent::DigIn{unit_id} => seen_dig_in_ids.push(unit_id),
+                CoreEvent::Entrench{unit_id} => seen_entrench_ids.push(unit_id),
+                _ => {},
+            }
+        }
+        assert_eq!(seen_dig_in_ids, vec![unit_id]);
+        assert_eq!(seen_entrench_ids, vec![unit_id]);
+        assert_eq!(
+            check_command(&core.db, PlayerId{id: 0}, &core.state, &Command::DigIn {
+                unit_id: unit_id,
+            }),
+            Err(CommandError::AlreadyEntrenched),
+        );
+        end_round(&mut core);
+        let from = core.state.unit(unit_id).pos;
+        let to = get_free_exact_pos(
+            &core.db,
+            &core.state,
+            core.state.unit(unit_id).type_id,
+            MapPos{v: Vector2{x: 2, y: 4}},
+        ).unwrap();
+        core.do_command(Command::Move {
+            unit_id: unit_id,
+            path: vec![from, to],
+            mode: MoveMode::Fast,
+        });
+        assert!(!core.state.unit(unit_id).is_entrenched);
+    }
+
+    #[test]
+    fn test_area_fire_hides_shooter() {
+        let mut core = new_core();
+        let target_pos = MapPos{v: Vector2{x: 3, y: 4}};
+        let unit_id = place_unit(&mut core, "mortar", MapPos{v: Vector2{x: 0, y: 4}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", target_pos);
+        core.do_core_event(&CoreEvent::SetReactionFireMode {
+            unit_id: enemy_id,
+            mode: ReactionFireMode::HoldFire,
+        });
+        end_round(&mut core);
+        let attack_points = core.state.unit(unit_id).attack_points.unwrap().n;
+        core.do_command(Command::AreaFire{unit_id: unit_id, pos: target_pos});
+        assert_eq!(core.state.unit(unit_id).attack_points.unwrap().n, attack_points - 1);
+        let mut impact_pos = None;
+        for event in &core.players_info[&PlayerId{id: 0}].events {
+            if let CoreEvent::AreaFire{attacker_id, pos} = *event {
+                assert_eq!(attacker_id, Some(unit_id));
+                impact_pos = Some(pos);
+            }
+        }
+        let impact_pos = impact_pos.unwrap();
+        let mut is_enemy_attacked = false;
+        for event in &core.players_info[&PlayerId{id: 1}].events {
+            match *event {
+                CoreEvent::AreaFire{attacker_id, pos} => {
+                    assert_eq!(attacker_id, None);
+                    assert_eq!(pos, impact_pos);
+                },
+                CoreEvent::AttackUnit{ref attack_info} => {
+                    assert_eq!(attack_info.attacker_id, None);
+                    is_enemy_attacked |= attack_info.defender_id == enemy_id;
+                },
+                _ => {},
+            }
+        }
+        assert_eq!(is_enemy_attacked, impact_pos == target_pos);
+        if impact_pos == target_pos {
+            if let Some(enemy) = core.state.units().get(&enemy_id) {
+                // suppressed even if nobody was killed
+                assert!(enemy.morale < 100);
+            }
+        }
+    }
+
+    #[test]
+    fn test_shooter_entrenchment_is_revealed() {
+        let mut core = new_core();
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 3, y: 7}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", MapPos{v: Vector2{x: 3, y: 9}});
+        end_round(&mut core);
+        core.do_command(Command::DigIn{unit_id: unit_id});
+        end_round(&mut core);
+        assert!(core.state.unit(unit_id).is_entrenched);
+        core.do_core_event(&CoreEvent::AttackUnit {
+            attack_info: AttackInfo {
+                attacker_id: Some(unit_id),
+                defender_id: enemy_id,
+                mode: FireMode::Active,
+                killed: 0,
+                suppression: 0,
+                remove_move_points: false,
+                is_ambush: false,
+                is_inderect: false,
+                is_return_fire: false,
+            },
+        });
+        // the shot's tile is seen closely only after the shooter is shown
+        let mut is_shown = false;
+        let mut is_entrenchment_revealed = false;
+        for event in &core.players_info[&PlayerId{id: 1}].events {
+            match *event {
+                CoreEvent::ShowUnit{ref unit_info} if unit_info.unit_id == unit_id => {
+                    assert!(!unit_info.is_entrenched);
+                    is_shown = true;
+                },
+                CoreEvent::Entrench{unit_id: id} if id == unit_id => {
+                    is_entrenchment_revealed = is_shown;
+                },
+                _ => {},
+            }
+        }
+        assert!(is_entrenchment_revealed);
+        let game_state = &core.players_info[&PlayerId{id: 1}].game_state;
+        assert!(game_state.unit(unit_id).is_entrenched);
+    }
+
+    #[test]
+    fn test_enemy_with_unseen_losses_is_killed() {
+        let mut core = new_core();
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", MapPos{v: Vector2{x: 8, y: 10}});
+        end_round(&mut core);
+        let is_enemy_known = |core: &Core| {
+            core.players_info[&PlayerId{id: 0}].game_state.units().contains_key(&enemy_id)
+        };
+        assert!(!is_enemy_known(&core));
+        let mut attack_info = AttackInfo {
+            attacker_id: None,
+            defender_id: enemy_id,
+            mode: FireMode::Active,
+            killed: 1,
+            suppression: 0,
+            remove_move_points: false,
+            is_ambush: false,
+            is_inderect: false,
+            is_return_fire: false,
+        };
+        core.do_core_event(&CoreEvent::AttackUnit{attack_info: attack_info.clone()});
+        let event = move_event(
+            &core, enemy_id, MapPos{v: Vector2{x: 1, y: 4}}, MoveMode::Fast);
+        core.do_core_event(&event);
+        assert!(is_enemy_known(&core));
+        // the player has seen the enemy only at full strength
+        attack_info.attacker_id = Some(unit_id);
+        attack_info.killed = core.state.unit(enemy_id).count;
+        core.do_core_event(&CoreEvent::AttackUnit{attack_info: attack_info});
+        assert!(!core.state.units().contains_key(&enemy_id));
+        assert!(!is_enemy_known(&core));
+    }
+
+    #[test]
+    fn test_support_strike_is_delayed() {
+        let mut core = new_core();
+        let target_pos = MapPos{v: Vector2{x: 3, y: 4}};
+        place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", target_pos);
+        let player_id = PlayerId{id: 0};
+        let check = |core: &Core, pos, support_type| core.check_player_command(
+            player_id, &Command::CallSupport {
+                pos: pos,
+                support_type: support_type,
+            });
+        assert_eq!(
+            check(&core, target_pos, SupportType::AirStrike),
+            Err(CommandError::NotEnoughSupportPoints),
+        );
+        assert_eq!(
+            check(&core, MapPos{v: Vector2{x: 8, y: 10}}, SupportType::Artillery),
+            Err(CommandError::NoLos),
+        );
+        core.do_command(Command::CallSupport {
+            pos: target_pos,
+            support_type: SupportType::Artillery,
+        });
+        assert_eq!(core.state.support_points()[&player_id], 0);
+        assert_eq!(core.state.support_calls().len(), 1);
+        let strike_events_count = |core: &Core| {
+            let mut count = 0;
+            for event in &core.players_info[&PlayerId{id: 1}].events {
+                match *event {
+                    CoreEvent::CallSupport{..} => panic!("Enemy must not know"),
+                    CoreEvent::SupportStrike{..} => count += 1,
+                    CoreEvent::AttackUnit{ref attack_info} => {
+                        assert_eq!(attack_info.defender_id, enemy_id);
+                        count += 1;
+                    },
+                    _ => {},
+                }
+            }
+            count
+        };
+        core.do_command(Command::EndTurn);
+        assert_eq!(strike_events_count(&core), 0);
+        core.do_command(Command::EndTurn);
+        assert_eq!(strike_events_count(&core), 2);
+        assert!(core.state.support_calls().is_empty());
+    }
+
+    #[test]
+    fn test_indirect_fire_needs_spotter() {
+        let mut core = new_core();
+        // infantry in the trees can be seen only from up close
+        let enemy_pos = MapPos{v: Vector2{x: 3, y: 6}};
+        let mortar_id = place_unit(&mut core, "mortar", MapPos{v: Vector2{x: 0, y: 6}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", enemy_pos);
+        end_round(&mut core);
+        let attack = Command::AttackUnit {
+            attacker_id: mortar_id,
+            defender_id: enemy_id,
+        };
+        // the player doesn't know about the enemy at all
+        assert_eq!(
+            core.check_player_command(PlayerId{id: 0}, &attack),
+            Err(CommandError::BadDefenderId),
+        );
+        core.cheating_bot_ids.insert(PlayerId{id: 0});
+        assert_eq!(
+            core.check_player_command(PlayerId{id: 0}, &attack),
+            Err(CommandError::NoSpotter),
+        );
+        core.cheating_bot_ids.clear();
+        let scout_id = place_unit(&mut core, "scout", MapPos{v: Vector2{x: 4, y: 6}});
+        assert_eq!(core.check_player_command(PlayerId{id: 0}, &attack), Ok(()));
+        // the player's own view decides if the target is spotted
+        let mut view = core.players_info[&PlayerId{id: 0}].game_state.clone();
+        assert_eq!(check_command(&core.db, PlayerId{id: 0}, &view, &attack), Ok(()));
+        view.apply_event(&core.db, &CoreEvent::AttackUnit {
+            attack_info: AttackInfo {
+                attacker_id: None,
+                defender_id: scout_id,
+                mode: FireMode::Active,
+                killed: core.state.unit(scout_id).count,
+                suppression: 0,
+                remove_move_points: false,
+                is_ambush: false,
+                is_inderect: false,
+                is_return_fire: false,
+            },
+        });
+        view.apply_event(&core.db, &CoreEvent::EndTurn {
+            old_id: PlayerId{id: 1},
+            new_id: PlayerId{id: 0},
+            is_new_round: true,
+        });
+        assert!(view.units().contains_key(&enemy_id));
+        assert_eq!(
+            check_command(&core.db, PlayerId{id: 0}, &view, &attack),
+            Err(CommandError::NoSpotter),
+        );
+        let spotted_chance = {
+            let mortar = core.state.unit(mortar_id);
+            let enemy = core.state.unit(enemy_id);
+            hit_chance(&core.db, &core.state, mortar, enemy).total
+        };
+        let close_mortar_id = place_unit(
+            &mut core, "mortar", MapPos{v: Vector2{x: 2, y: 6}});
+        let own_chance = {
+            let mortar = core.state.unit(close_mortar_id);
+            let enemy = core.state.unit(enemy_id);
+            hit_chance(&core.db, &core.state, mortar, enemy).total
+        };
+        assert!(own_chance > spotted_chance);
+    }
+
+    #[test]
+    fn test_overwatch_and_ambush_modes() {
+        let mut core = new_core();
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", MapPos{v: Vector2{x: 3, y: 4}});
+        end_round(&mut core);
+        let can_attack = |core: &Core| core.can_unit_make_reaction_attack(
+            core.state.unit(enemy_id), core.state.unit(unit_id));
+        assert!(can_attack(&core));
+        let set_mode = |core: &mut Core, mode| core.do_command(
+            Command::SetReactionFireMode{unit_id: unit_id, mode: mode});
+        let enemy_pos = MapPos{v: Vector2{x: 3, y: 4}};
+        set_mode(&mut core, ReactionFireMode::Overwatch{pos: enemy_pos});
+        assert!(can_attack(&core));
+        let other_pos = MapPos{v: Vector2{x: 1, y: 6}};
+        set_mode(&mut core, ReactionFireMode::Overwatch{pos: other_pos});
+        assert!(!can_attack(&core));
+        set_mode(&mut core, ReactionFireMode::Ambush{distance: 2});
+        assert!(!can_attack(&core));
+        set_mode(&mut core, ReactionFireMode::Ambush{distance: 3});
+        assert!(can_attack(&core));
+        let far_pos = MapPos{v: Vector2{x: 2, y: 8}};
+        assert_eq!(
+            check_command(&core.db, PlayerId{id: 0}, &core.state, &Command::SetReactionFireMode {
+                unit_id: unit_id,
+                mode: ReactionFireMode::Overwatch{pos: far_pos},
+            }),
+            Err(CommandError::OutOfRange),
+        );
+    }
+
+    fn move_event(core: &Core, unit_id: UnitId, to: MapPos, mode: MoveMode) -> CoreEvent {
+        let unit = core.state.unit(unit_id);
+        CoreEvent::Move {
+            unit_id: unit_id,
+            from: unit.pos,
+            to: get_free_exact_pos(&core.db, &core.state, unit.type_id, to).unwrap(),
+            mode: mode,
+            cost: MovePoints{n: 1},
+        }
+    }
+
+    #[test]
+    fn test_hunting_unit_is_hard_to_spot() {
+        let mut core = new_core();
+        place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", MapPos{v: Vector2{x: 5, y: 4}});
+        end_round(&mut core);
+        let is_enemy_visible = |core: &Core| {
+            let enemy = core.state.unit(enemy_id);
+            core.players_info[&PlayerId{id: 0}].fow.is_visible(
+                &core.db, &core.state, enemy, enemy.pos)
+        };
+        let event = move_event(
+            &core, enemy_id, MapPos{v: Vector2{x: 4, y: 4}}, MoveMode::Hunt);
+        core.do_core_event(&event);
+        assert!(!is_enemy_visible(&core));
+        let event = move_event(
+            &core, enemy_id, MapPos{v: Vector2{x: 5, y: 4}}, MoveMode::Fast);
+        core.do_core_event(&event);
+        assert!(is_enemy_visible(&core));
+    }
+
+    #[test]
+    fn test_fast_move_accuracy_penalty() {
+        let mut core = new_core();
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", MapPos{v: Vector2{x: 3, y: 4}});
+        end_round(&mut core);
+        let hit_chance = |core: &Core| hit_chance(
+            &core.db,
+            &core.state,
+            core.state.unit(unit_id),
+            core.state.unit(enemy_id),
+        ).total;
+        let initial_chance = hit_chance(&core);
+        let event = move_event(
+            &core, unit_id, MapPos{v: Vector2{x: 1, y: 4}}, MoveMode::Hunt);
+        core.do_core_event(&event);
+        let hunt_chance = hit_chance(&core);
+        assert_eq!(hunt_chance, initial_chance);
+        let event = move_event(
+            &core, unit_id, MapPos{v: Vector2{x: 0, y: 4}}, MoveMode::Fast);
+        core.do_core_event(&event);
+        assert!(hit_chance(&core) < initial_chance);
+        core.do_command(Command::EndTurn);
+        assert_eq!(hit_chance(&core), initial_chance);
+    }
+
+    #[test]
+    fn test_hunting_unit_snap_fires() {
+        let mut core = new_core();
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "medium_tank", MapPos{v: Vector2{x: 4, y: 4}});
+        end_round(&mut core);
+        core.do_command(Command::EndTurn);
+        let from = core.state.unit(enemy_id).pos;
+        let to = get_free_exact_pos(
+            &core.db,
+            &core.state,
+            core.state.unit(enemy_id).type_id,
+            MapPos{v: Vector2{x: 3, y: 4}},
+        ).unwrap();
+        core.do_command(Command::Move {
+            unit_id: enemy_id,
+            path: vec![from, to],
+            mode: MoveMode::Hunt,
+        });
+        let mut is_reaction_fire = false;
+        let mut is_snap_fire = false;
+        for event in &core.players_info[&PlayerId{id: 1}].events {
+            if let CoreEvent::AttackUnit{ref attack_info} = *event {
+                is_reaction_fire |= attack_info.attacker_id == Some(unit_id);
+                is_snap_fire |= attack_info.attacker_id == Some(enemy_id)
+                    && attack_info.defender_id == unit_id
+                    && attack_info.is_return_fire;
+            }
+        }
+        assert!(is_reaction_fire);
+        assert!(is_snap_fire);
+    }
+
+    #[test]
+    fn test_defender_returns_fire() {
+        let mut core = new_core();
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "medium_tank", MapPos{v: Vector2{x: 3, y: 4}});
+        end_round(&mut core);
+        core.do_command(Command::AttackUnit {
+            attacker_id: unit_id,
+            defender_id: enemy_id,
+        });
+        let mut attackers = Vec::new();
+        for event in &core.players_info[&PlayerId{id: 0}].events {
+            if let CoreEvent::AttackUnit{ref attack_info} = *event {
+                attackers.push((attack_info.attacker_id, attack_info.is_return_fire));
+            }
+        }
+        assert_eq!(attackers, vec![
+            (Some(unit_id), false),
+            (Some(enemy_id), true),
+        ]);
+    }
+
+    #[test]
+    fn test_hit_chance_from_partial_state() {
+        let mut core = new_core();
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", MapPos{v: Vector2{x: 3, y: 4}});
+        end_round(&mut core);
+        let mut state = PartialState::new("map02", PlayerId{id: 0});
+        for event in &core.players_info[&PlayerId{id: 0}].events {
+            state.apply_event(&core.db, event);
+        }
+        let chance = hit_chance(
+            &core.db, &state, state.unit(unit_id), state.unit(enemy_id));
+        let full_chance = hit_chance(
+            &core.db,
+            &core.state,
+            core.state.unit(unit_id),
+            core.state.unit(enemy_id),
+        );
+        assert_eq!(chance, full_chance);
+        assert_eq!(chance.cover_bonus, 0);
+        assert_eq!(chance.total, chance.hit * chance.pierce * chance.wound / 10000);
+        assert!(chance.total > 0);
+        assert!(chance.expected_kills > 0.0);
+        let count = core.state.unit(enemy_id).count as f32;
+        assert!(chance.expected_kills < count);
+        let miss_suppression = attack_suppression(0) as f32;
+        assert!(chance.expected_suppression > miss_suppression);
+    }
+
+    #[test]
+    fn test_describe_unit_and_terrain() {
+        let mut core = new_core();
+        let trees_pos = MapPos{v: Vector2{x: 3, y: 6}};
+        let unit_id = place_unit(&mut core, "soldier", trees_pos);
+        let terrain = describe_terrain(&core.state, trees_pos);
+        assert_eq!(terrain.terrain, Terrain::Trees);
+        assert_eq!(terrain.infantry_cover_bonus, 2);
+        let infantry_move_cost = terrain.infantry_move_cost.unwrap();
+        assert!(terrain.vehicle_move_cost.unwrap().n > infantry_move_cost.n);
+        let unit = describe_unit(&core.db, &core.state, core.state.unit(unit_id));
+        assert_eq!(unit.type_name, "soldier");
+        assert_eq!(unit.count, unit.max_count);
+        assert_eq!(unit.weapon.name, "rifle");
+        assert!(unit.to_string().starts_with("soldier"));
+    }
+
+    fn move_command(core: &Core, unit_id: UnitId, to: MapPos) -> Command {
+        let unit = core.state.unit(unit_id);
+        let to = get_free_exact_pos(&core.db, &core.state, unit.type_id, to).unwrap();
+        Command::Move {
+            unit_id: unit_id,
+            path: vec![unit.pos, to],
+            mode: MoveMode::Fast,
+        }
+    }
+
+    #[test]
+    fn test_undo_unnoticed_move() {
+        let mut core = new_core();
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        end_round(&mut core);
+        assert!(!core.can_undo());
+        let pos = core.state.unit(unit_id).pos;
+        let move_points = core.state.unit(unit_id).move_points.unwrap().n;
+        let command = move_command(&core, unit_id, MapPos{v: Vector2{x: 1, y: 4}});
+        core.do_command(command);
+        assert!(core.state.unit(unit_id).pos != pos);
+        assert!(core.can_undo());
+        core.do_command(Command::Undo);
+        assert!(!core.can_undo());
+        let unit = core.state.unit(unit_id);
+        assert_eq!(unit.pos, pos);
+        assert_eq!(unit.move_points.unwrap().n, move_points);
+        assert_eq!(unit.move_mode, None);
+        let undo_events_count = |core: &Core, player_id| {
+            core.players_info[&player_id].events.iter()
+                .filter_map(|event| match *event {
+                    CoreEvent::UndoMove{unit_id, ..} => Some(unit_id),
+                    _ => None,
+                })
+                .count()
+        };
+        assert_eq!(undo_events_count(&core, PlayerId{id: 0}), 1);
+        assert_eq!(undo_events_count(&core, PlayerId{id: 1}), 0);
+    }
+
+    #[test]
+    fn test_undo_restores_player_fog() {
+        let mut core = new_core();
+        let player_id = PlayerId{id: 0};
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        end_round(&mut core);
+        let visibility = |core: &Core| -> Vec<_> {
+            core.state.map().get_iter()
+                .map(|pos| core.players_info[&player_id].fow.tile_visibility(pos))
+                .collect()
+        };
+        let pre_move_visibility = visibility(&core);
+        let command = move_command(&core, unit_id, MapPos{v: Vector2{x: 1, y: 4}});
+        core.do_command(command);
+        assert!(visibility(&core) != pre_move_visibility);
+        core.do_command(Command::Undo);
+        assert_eq!(visibility(&core), pre_move_visibility);
+        let mut client_state = PartialState::new("map02", player_id);
+        for event in &core.players_info[&player_id].events {
+            client_state.apply_event(&core.db, event);
+        }
+        let game_state = &core.players_info[&player_id].game_state;
+        for pos in core.state.map().get_iter() {
+            let visibility = core.players_info[&player_id].fow.tile_visibility(pos);
+            assert_eq!(game_state.tile_visibility(pos), visibility);
+            assert_eq!(client_state.tile_visibility(pos), visibility);
+        }
+    }
+
+    #[test]
+    fn test_noticed_move_can_not_be_undone() {
+        let mut core = new_core();
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", MapPos{v: Vector2{x: 4, y: 4}});
+        core.do_core_event(&CoreEvent::SetReactionFireMode {
+            unit_id: enemy_id,
+            mode: ReactionFireMode::HoldFire,
+        });
+        end_round(&mut core);
+        let command = move_command(&core, unit_id, MapPos{v: Vector2{x: 1, y: 4}});
+        core.do_command(command);
+        assert!(!core.can_undo());
+        assert_eq!(
+            core.check_player_command(PlayerId{id: 0}, &Command::Undo),
+            Err(CommandError::NothingToUndo),
+        );
+    }
+
+    #[test]
+    fn test_return_fire_respects_reaction_fire_mode() {
+        let returns_fire = |mode: ReactionFireMode| {
+            let mut core = new_core();
+            let unit_id = place_unit(
+                &mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+            let enemy_id = place_player_unit(
+                &mut core, PlayerId{id: 1}, "medium_tank", MapPos{v: Vector2{x: 3, y: 4}});
+            core.do_core_event(&CoreEvent::SetReactionFireMode {
+                unit_id: enemy_id,
+                mode: mode,
+            });
+            end_round(&mut core);
+            core.do_command(Command::AttackUnit {
+                attacker_id: unit_id,
+                defender_id: enemy_id,
+            });
+            core.players_info[&PlayerId{id: 0}].events.iter().any(|event| {
+                match *event {
+                    CoreEvent::AttackUnit{ref attack_info} => attack_info.is_return_fire,
+                    _ => false,
+                }
+            })
+        };
+        let shooter_pos = MapPos{v: Vector2{x: 0, y: 4}};
+        let far_pos = MapPos{v: Vector2{x: 0, y: 8}};
+        assert!(returns_fire(ReactionFireMode::Normal));
+        assert!(!returns_fire(ReactionFireMode::HoldFire));
+        assert!(returns_fire(ReactionFireMode::Ambush{distance: 3}));
+        assert!(!returns_fire(ReactionFireMode::Ambush{distance: 2}));
+        assert!(returns_fire(ReactionFireMode::Overwatch{pos: shooter_pos}));
+        assert!(!returns_fire(ReactionFireMode::Overwatch{pos: far_pos}));
+    }
+
+    #[test]
+    fn test_group_move_takes_free_slots() {
+        let mut core = new_core();
+        let unit_ids: Vec<_> = (0..4).map(|i| {
+            let pos = MapPos{v: Vector2{x: i % 2, y: 4 + i % 2}};
+            place_unit(&mut core, "soldier", pos)
+        }).collect();
+        end_round(&mut core);
+        let destination = MapPos{v: Vector2{x: 2, y: 4}};
+        let command = Command::GroupMove {
+            unit_ids: unit_ids.clone(),
+            pos: destination,
+            mode: MoveMode::Fast,
+        };
+        assert_eq!(check_command(
+            core.db(), core.player_id(), &core.state, &command), Ok(()));
+        core.do_command(command);
+        let positions: Vec<_> = unit_ids.iter()
+            .map(|id| core.state.unit(*id).pos)
+            .collect();
+        for (i, pos) in positions.iter().enumerate() {
+            assert!(distance(pos.map_pos, destination) <= GROUP_MOVE_RADIUS);
+            assert!(!positions[..i].contains(pos));
+        }
+        // the first unit takes the best place
+        assert_eq!(positions[0].map_pos, destination);
+    }
+
+    #[test]
+    fn test_group_stops_when_member_is_hit() {
+        let mut core = new_core();
+        let first_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        let second_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 5}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", MapPos{v: Vector2{x: 3, y: 1}});
+        end_round(&mut core);
+        let second_pos = core.state.unit(second_id).pos;
+        core.do_command(Command::GroupMove {
+            unit_ids: vec![second_id, first_id],
+            pos: MapPos{v: Vector2{x: 3, y: 4}},
+            mode: MoveMode::Fast,
+        });
+        let is_attacked = core.players_info[&PlayerId{id: 0}].events.iter()
+            .any(|event| match *event {
+                CoreEvent::AttackUnit{ref attack_info} => {
+                    attack_info.attacker_id == Some(enemy_id)
+                        && attack_info.defender_id == first_id
+                },
+                _ => false,
+            });
+        assert!(is_attacked);
+        assert_eq!(core.state.unit(second_id).pos, second_pos);
+    }
+
+    fn observer_events(core: &mut Core, observer_id: ObserverId) -> Vec<CoreEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = core.get_observer_event(observer_id) {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn test_observers() {
+        let mut core = new_core();
+        place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        let enemy_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", MapPos{v: Vector2{x: 8, y: 11}});
+        end_round(&mut core);
+        let player_0 = PlayerId{id: 0};
+        let player_1 = PlayerId{id: 1};
+        let full_id = core.add_observer(ObserverVision::Full);
+        let player_0_id = core.add_observer(ObserverVision::Players(vec![player_0]));
+        let both_id = core.add_observer(
+            ObserverVision::Players(vec![player_0, player_1]));
+        for player_id in &[player_0, player_1] {
+            core.players_info.get_mut(player_id).unwrap().events.clear();
+        }
+        let event = move_event(&core, enemy_id, MapPos{v: Vector2{x: 8, y: 10}}, MoveMode::Fast);
+        core.do_core_event(&event);
+        core.do_command(Command::EndTurn);
+        let full_events = observer_events(&mut core, full_id);
+        assert_eq!(full_events[0], event);
+        let player_0_events: Vec<_> = core.players_info[&player_0].events
+            .iter().cloned().collect();
+        let player_1_events: Vec<_> = core.players_info[&player_1].events
+            .iter().cloned().collect();
+        assert!(!player_0_events.contains(&event));
+        assert!(player_1_events.contains(&event));
+        assert_eq!(observer_events(&mut core, player_0_id), player_0_events);
+        let both_events = observer_events(&mut core, both_id);
+        for event in player_0_events.iter().chain(&player_1_events) {
+            assert!(both_events.contains(event));
+        }
+        // the end of the turn is seen by both players but reported once
+        let end_turn_count = both_events.iter()
+            .filter_map(|event| match *event {
+                CoreEvent::EndTurn{new_id, ..} => Some(new_id),
+                _ => None,
+            })
+            .count();
+        assert_eq!(end_turn_count, 1);
+        core.remove_observer(full_id);
+        assert!(!core.observers.contains_key(&full_id));
+    }
+
+    #[test]
+    fn test_ai_wins_sector_race() {
+        let mut core = new_ai_core();
+        let human_id = PlayerId{id: 0};
+        let ai_id = PlayerId{id: 1};
+        for &(player_id, x, y) in &[
+            (human_id, 0, 4),
+            (human_id, 0, 10),
+            (ai_id, 8, 4),
+            (ai_id, 8, 10),
+        ] {
+            place_player_unit(&mut core, player_id, "soldier", MapPos{v: Vector2{x: x, y: y}});
+        }
+        // the human player just waits, the AI makes its whole turn
+        // in response to every `EndTurn`
+        for _ in 0..6 {
+            core.do_command(Command::EndTurn);
+        }
+        for sector in core.state.sectors().values() {
+            assert_eq!(sector.owner_id, Some(ai_id));
+        }
+        let score = core.state.score();
+        assert!(score[&ai_id].n > score[&human_id].n);
+    }
+
+    #[test]
+    fn test_ai_explores_with_scouts() {
+        let mut core = new_ai_core();
+        let ai_id = PlayerId{id: 1};
+        for &(x, y) in &[(4, 3), (5, 8)] {
+            place_player_unit(&mut core, ai_id, "soldier", MapPos{v: Vector2{x: x, y: y}});
+        }
+        let scout_id = place_player_unit(
+            &mut core, ai_id, "scout", MapPos{v: Vector2{x: 8, y: 1}});
+        let soldier_id = place_player_unit(
+            &mut core, ai_id, "soldier", MapPos{v: Vector2{x: 4, y: 5}});
+        let scout_pos = core.state.unit(scout_id).pos;
+        core.do_command(Command::EndTurn);
+        // the scout heads to the enemy's side of the map
+        let enemy_sector_pos = MapPos{v: Vector2{x: 0, y: 4}};
+        let new_scout_pos = core.state.unit(scout_id).pos;
+        assert!(distance(new_scout_pos.map_pos, enemy_sector_pos)
+            < distance(scout_pos.map_pos, enemy_sector_pos));
+        let soldier_pos = core.state.unit(soldier_id).pos.map_pos;
+        assert_eq!(*core.state.map().tile(soldier_pos), Terrain::Trees);
+    }
+
+    #[test]
+    fn test_ai_finishes_damaged_squads() {
+        let mut core = new_core();
+        let ai_id = PlayerId{id: 1};
+        place_player_unit(&mut core, ai_id, "soldier", MapPos{v: Vector2{x: 4, y: 4}});
+        let fresh_id = place_player_unit(
+            &mut core, PlayerId{id: 0}, "soldier", MapPos{v: Vector2{x: 2, y: 4}});
+        let damaged_id = place_player_unit(
+            &mut core, PlayerId{id: 0}, "soldier", MapPos{v: Vector2{x: 6, y: 4}});
+        core.do_core_event(&CoreEvent::AttackUnit {
+            attack_info: AttackInfo {
+                attacker_id: None,
+                defender_id: damaged_id,
+                mode: FireMode::Active,
+                killed: 3,
+                suppression: 0,
+                remove_move_points: false,
+                is_ambush: false,
+                is_inderect: false,
+                is_return_fire: false,
+            },
+        });
+        core.do_command(Command::EndTurn);
+        let mut ai = Ai::new(ai_id, "map02", AiProfile::default());
+        while let Some(event) = core.get_event() {
+            ai.apply_event(core.db(), &event);
+        }
+        assert_eq!(core.state.unit(fresh_id).count, 4);
+        assert_eq!(core.state.unit(damaged_id).count, 1);
+        match ai.try_get_attack_command(core.db()) {
+            Some(Command::AttackUnit{defender_id, ..}) => {
+                assert_eq!(defender_id, damaged_id);
+            },
+            command => panic!("unexpected command: {:?}", command),
+        }
+    }
+
+    fn new_ai_with_units(core: &mut Core, type_names: &[&str]) -> Ai {
+        let ai_id = PlayerId{id: 1};
+        for (i, type_name) in type_names.iter().enumerate() {
+            let pos = MapPos{v: Vector2{x: 7, y: 3 + i as i32}};
+            place_player_unit(core, ai_id, type_name, pos);
+        }
+        core.do_command(Command::EndTurn);
+        let mut ai = Ai::new(ai_id, "map02", AiProfile::default());
+        while let Some(event) = core.get_event() {
+            ai.apply_event(core.db(), &event);
+        }
+        ai
+    }
+
+    #[test]
+    fn test_ai_buys_anti_tank_against_armor() {
+        let mut core = new_core();
+        for _ in 0..3 {
+            end_round(&mut core);
+        }
+        place_unit(&mut core, "medium_tank", MapPos{v: Vector2{x: 4, y: 4}});
+        let ai = new_ai_with_units(&mut core, &["soldier"]);
+        let anti_tank_names = [
+            "mammoth_tank",
+            "heavy_tank",
+            "medium_tank",
+            "light_tank",
+            "light_spg",
+            "field_gun",
+        ];
+        for _ in 0..10 {
+            match ai.try_get_create_unit_command(core.db()) {
+                Some(Command::CreateUnit{type_id, ..}) => {
+                    let name = &core.db().unit_type(type_id).name;
+                    assert!(anti_tank_names.contains(&name.as_str()));
+                },
+                command => panic!("unexpected command: {:?}", command),
+            }
+        }
+    }
+
+    #[test]
+    fn test_ai_saves_points_for_expensive_units() {
+        let mut core = new_core();
+        for _ in 0..4 {
+            end_round(&mut core);
+        }
+        place_unit(&mut core, "medium_tank", MapPos{v: Vector2{x: 4, y: 4}});
+        let ai = new_ai_with_units(&mut core, &[
+            "heavy_tank", "medium_tank", "light_tank", "light_spg", "field_gun"]);
+        // the only anti-tank unit that isn't fielded yet costs 16 points
+        assert_eq!(core.state.reinforcement_points()[&PlayerId{id: 1}], 15);
+        assert!(ai.try_get_create_unit_command(core.db()).is_none());
+    }
+
+    #[test]
+    fn test_ai_spreads_reinforcements() {
+        let mut core = new_core();
+        for _ in 0..3 {
+            end_round(&mut core);
+        }
+        let ai = new_ai_with_units(&mut core, &["soldier"]);
+        let free_sector_pos = MapPos{v: Vector2{x: 8, y: 10}};
+        for _ in 0..10 {
+            match ai.try_get_create_unit_command(core.db()) {
+                Some(Command::CreateUnit{pos, ..}) => {
+                    assert_eq!(pos.map_pos, free_sector_pos);
+                },
+                command => panic!("unexpected command: {:?}", command),
+            }
+        }
+    }
+
+    #[test]
+    fn test_ai_ferries_infantry() {
+        let mut core = new_ai_core();
+        let ai_id = PlayerId{id: 1};
+        let soldier_id = place_player_unit(
+            &mut core, ai_id, "soldier", MapPos{v: Vector2{x: 8, y: 11}});
+        let truck_id = place_player_unit(
+            &mut core, ai_id, "truck", MapPos{v: Vector2{x: 8, y: 10}});
+        let observer_id = core.add_observer(ObserverVision::Full);
+        core.do_command(Command::EndTurn);
+        let events = observer_events(&mut core, observer_id);
+        let is_loaded = events.iter().any(|event| match *event {
+            CoreEvent::LoadUnit{transporter_id, passenger_id, ..} => {
+                transporter_id == Some(truck_id) && passenger_id == soldier_id
+            },
+            _ => false,
+        });
+        assert!(is_loaded);
+        assert!(core.state.unit(truck_id).passenger_id.is_none());
+        // walking alone the soldier would stop two tiles away
+        let sector_pos = MapPos{v: Vector2{x: 5, y: 8}};
+        assert!(distance(core.state.unit(soldier_id).pos.map_pos, sector_pos) <= 1);
+    }
+
+    #[test]
+    fn test_ai_covers_advance_with_smoke() {
+        let mut core = new_ai_core();
+        let ai_id = PlayerId{id: 1};
+        place_player_unit(&mut core, ai_id, "mortar", MapPos{v: Vector2{x: 7, y: 4}});
+        place_player_unit(&mut core, ai_id, "soldier", MapPos{v: Vector2{x: 5, y: 4}});
+        place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 4, y: 8}});
+        assert_eq!(smoke_count(&core), 0);
+        core.do_command(Command::EndTurn);
+        assert!(smoke_count(&core) > 0);
+    }
+
+    #[test]
+    fn test_ai_holds_fire_against_tanks() {
+        let mut core = new_ai_core();
+        let soldier_id = place_player_unit(
+            &mut core, PlayerId{id: 1}, "soldier", MapPos{v: Vector2{x: 4, y: 4}});
+        place_unit(&mut core, "medium_tank", MapPos{v: Vector2{x: 4, y: 7}});
+        core.do_command(Command::EndTurn);
+        let soldier = core.state.unit(soldier_id);
+        assert_eq!(soldier.reaction_fire_mode, ReactionFireMode::HoldFire);
+    }
+
+    fn last_move_pos(ai: &mut Ai, db: &Db) -> MapPos {
+        match ai.try_get_move_command(db) {
+            Some(Command::Move{path, ..}) => path.last().unwrap().map_pos,
+            command => panic!("unexpected command: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_ai_personality_changes_targets() {
+        let mut core = new_core();
+        let ai_id = PlayerId{id: 1};
+        let start_pos = MapPos{v: Vector2{x: 8, y: 0}};
+        place_player_unit(&mut core, ai_id, "soldier", start_pos);
+        let enemy_pos = MapPos{v: Vector2{x: 8, y: 5}};
+        place_unit(&mut core, "soldier", enemy_pos);
+        core.do_command(Command::EndTurn);
+        let mut events = Vec::new();
+        while let Some(event) = core.get_event() {
+            events.push(event);
+        }
+        let new_ai = |personality| {
+            let profile = AiProfile{personality: personality, ..AiProfile::default()};
+            let mut ai = Ai::new(ai_id, "map02", profile);
+            for event in &events {
+                ai.apply_event(core.db(), event);
+            }
+            ai
+        };
+        let defensive_pos = last_move_pos(&mut new_ai(AiPersonality::Defensive), core.db());
+        let aggressive_pos = last_move_pos(&mut new_ai(AiPersonality::Aggressive), core.db());
+        // the enemy is closer than the sector, but only
+        // the aggressive AI goes for it when it's not ahead on score
+        let sector_pos = MapPos{v: Vector2{x: 4, y: 3}};
+        assert!(distance(aggressive_pos, enemy_pos) < distance(start_pos, enemy_pos));
+        assert!(distance(defensive_pos, sector_pos) < distance(start_pos, sector_pos));
+        // hunting under the enemy's fire, the aggressive AI
+        // makes shorter steps towards it
+        assert!(distance(aggressive_pos, enemy_pos) <= distance(defensive_pos, enemy_pos));
+        assert!(distance(defensive_pos, sector_pos) < distance(aggressive_pos, sector_pos));
+    }
+
+    #[test]
+    fn test_cheating_ai_sees_everything() {
+        let ai_id = PlayerId{id: 1};
+        let mut ai_profiles = HashMap::new();
+        ai_profiles.insert(ai_id, AiProfile {
+            difficulty: AiDifficulty::Easy,
+            is_cheating: true,
+            ..AiProfile::default()
+        });
+        let mut options = Options {
+            game_type: GameType::SingleVsAi,
+            map_name: "map02".to_owned(),
+            ai_profiles: ai_profiles,
+            bots: HashMap::new(),
+            bot_commands: HashMap::new(),
+        };
+        let mut cheating_core = Core::new(&options).unwrap();
+        options.ai_profiles.clear();
+        let mut fair_core = Core::new(&options).unwrap();
+        for core in &mut [&mut cheating_core, &mut fair_core] {
+            place_unit(core, "soldier", MapPos{v: Vector2{x: 0, y: 0}});
+        }
+        let has_seen_enemy = |core: &Core| {
+            core.players_info[&ai_id].events.iter().any(|event| match *event {
+                CoreEvent::CreateUnit{ref unit_info} => unit_info.player_id != ai_id,
+                _ => false,
+            })
+        };
+        assert!(has_seen_enemy(&cheating_core));
+        assert!(!has_seen_enemy(&fair_core));
+        // hidden minefields and units are in the view too
+        let mut state = PartialState::new_full("map02", ai_id);
+        for event in &cheating_core.players_info[&ai_id].events {
+            state.apply_event(&cheating_core.db, event);
+        }
+        let full_state = &cheating_core.state;
+        assert_eq!(state.units().len(), full_state.units().len());
+        assert!(full_state.units().keys().all(|id| state.units().contains_key(id)));
+        assert_eq!(state.objects().len(), full_state.objects().len());
+        assert!(full_state.objects().keys().all(|id| state.objects().contains_key(id)));
+        assert!(state.is_tile_visible(MapPos{v: Vector2{x: 0, y: 0}}));
+    }
+
+    #[test]
+    fn test_observer_merges_players_views() {
+        let mut core = new_core();
+        let player_0 = PlayerId{id: 0};
+        let player_1 = PlayerId{id: 1};
+        let attacker_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 4}});
+        let defender_id = place_player_unit(
+            &mut core, player_1, "soldier", MapPos{v: Vector2{x: 4, y: 4}});
+        end_round(&mut core);
+        let both_id = core.add_observer(
+            ObserverVision::Players(vec![player_0, player_1]));
+        for player_id in &[player_0, player_1] {
+            core.players_info.get_mut(player_id).unwrap().events.clear();
+        }
+        core.do_core_event(&CoreEvent::AttackUnit {
+            attack_info: AttackInfo {
+                attacker_id: Some(attacker_id),
+                defender_id: defender_id,
+                mode: FireMode::Active,
+                killed: 1,
+                suppression: 0,
+                remove_move_points: false,
+                is_ambush: true,
+                is_inderect: false,
+                is_return_fire: false,
+            },
+        });
+        let attackers = |events: Vec<CoreEvent>| -> Vec<Option<UnitId>> {
+            events.into_iter()
+                .filter_map(|event| match event {
+                    CoreEvent::AttackUnit{attack_info} => Some(attack_info.attacker_id),
+                    _ => None,
+                })
+                .collect()
+        };
+        let player_events = |core: &Core, player_id| {
+            core.players_info[&player_id].events.iter().cloned().collect()
+        };
+        // the defender does not see who ambushed it
+        assert_eq!(attackers(player_events(&core, player_0)), vec![Some(attacker_id)]);
+        assert_eq!(attackers(player_events(&core, player_1)), vec![None]);
+        // but the observer sees through both players' eyes
+        assert_eq!(
+            attackers(observer_events(&mut core, both_id)),
+            vec![Some(attacker_id)],
+        );
+    }
+
+    #[test]
+    fn test_threat_map() {
+        let mut core = new_core();
+        let enemy_pos = MapPos{v: Vector2{x: 3, y: 8}};
+        let enemy_id = place_unit(&mut core, "soldier", enemy_pos);
+        let mut threat_map = ThreatMap::new(core.state.map().size());
+        threat_map.update(core.db(), &core.state, PlayerId{id: 1});
+        assert_eq!(threat_map.threat(MapPos{v: Vector2{x: 5, y: 5}}), 1);
+        // behind the trees
+        assert_eq!(threat_map.threat(MapPos{v: Vector2{x: 3, y: 5}}), 0);
+        // out of the rifle range
+        assert_eq!(threat_map.threat(MapPos{v: Vector2{x: 3, y: 4}}), 0);
+        let normal_threat_map = threat_map.clone();
+        let set_mode = |core: &mut Core, mode| core.do_command(
+            Command::SetReactionFireMode{unit_id: enemy_id, mode: mode});
+        let sector_pos = MapPos{v: Vector2{x: 5, y: 5}};
+        set_mode(&mut core, ReactionFireMode::Overwatch{pos: sector_pos});
+        threat_map.update(core.db(), &core.state, PlayerId{id: 1});
+        assert_eq!(threat_map.threat(sector_pos), 1);
+        for pos in core.state.map().get_iter() {
+            let is_in_sector = distance(pos, sector_pos) <= OVERWATCH_SECTOR_RADIUS;
+            let expected = if is_in_sector { normal_threat_map.threat(pos) } else { 0 };
+            assert_eq!(threat_map.threat(pos), expected);
+        }
+        let ambush_distance = 2;
+        set_mode(&mut core, ReactionFireMode::Ambush{distance: ambush_distance});
+        threat_map.update(core.db(), &core.state, PlayerId{id: 1});
+        assert_eq!(threat_map.threat(sector_pos), 0);
+        assert_eq!(threat_map.threat(MapPos{v: Vector2{x: 3, y: 7}}), 1);
+        for pos in core.state.map().get_iter() {
+            let is_near = distance(pos, enemy_pos) <= ambush_distance;
+            let expected = if is_near { normal_threat_map.threat(pos) } else { 0 };
+            assert_eq!(threat_map.threat(pos), expected);
+        }
+        set_mode(&mut core, ReactionFireMode::HoldFire);
+        threat_map.update(core.db(), &core.state, PlayerId{id: 1});
+        assert_eq!(threat_map.threat(sector_pos), 0);
+    }
+
+    #[test]
+    fn test_ai_hunts_under_reaction_fire() {
+        let mut core = new_core();
+        let ai_id = PlayerId{id: 1};
+        // the first sector is already taken
+        place_player_unit(&mut core, ai_id, "soldier", MapPos{v: Vector2{x: 4, y: 3}});
+        place_player_unit(&mut core, ai_id, "soldier", MapPos{v: Vector2{x: 5, y: 4}});
+        // guards the second sector
+        place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 5, y: 9}});
+        core.do_command(Command::EndTurn);
+        let mut events = Vec::new();
+        while let Some(event) = core.get_event() {
+            events.push(event);
+        }
+        let new_ai = |personality| {
+            let profile = AiProfile{personality: personality, ..AiProfile::default()};
+            let mut ai = Ai::new(ai_id, "map02", profile);
+            for event in &events {
+                ai.apply_event(core.db(), event);
+            }
+            ai
+        };
+        let move_mode = |mut ai: Ai| match ai.try_get_move_command(core.db()) {
+            Some(Command::Move{mode, ..}) => mode,
+            command => panic!("unexpected command: {:?}", command),
+        };
+        // the personality changes only the route, not the way to walk it
+        assert_eq!(move_mode(new_ai(AiPersonality::Defensive)), MoveMode::Hunt);
+        assert_eq!(move_mode(new_ai(AiPersonality::Aggressive)), MoveMode::Hunt);
+    }
+
+    /// Calls in a soldier every turn
+    #[derive(Debug)]
+    struct ReinforcingBot {
+        is_done: bool,
+    }
+
+    impl Bot for ReinforcingBot {
+        fn apply_event(&mut self, _: &Db, event: &CoreEvent) {
+            if let CoreEvent::EndTurn{..} = *event {
+                self.is_done = false;
+            }
+        }
+
+        fn get_command(&mut self, db: &Db) -> Command {
+            if self.is_done {
+                return Command::EndTurn;
+            }
+            self.is_done = true;
+            Command::CreateUnit {
+                type_id: db.unit_type_id("soldier"),
+                pos: ExactPos {
+                    map_pos: MapPos{v: Vector2{x: 8, y: 4}},
+                    slot_id: SlotId::Id(0),
+                },
+            }
+        }
+    }
+
+    fn new_reinforcing_bot(_: PlayerId, _: &Options) -> io::Result<Box<Bot>> {
+        Ok(Box::new(ReinforcingBot{is_done: false}))
+    }
+
+    fn new_core_with_bots(bots: &[(PlayerId, BotFactory)]) -> Core {
+        Core::new(&Options {
+            game_type: GameType::Hotseat,
+            map_name: "map02".to_owned(),
+            ai_profiles: HashMap::new(),
+            bots: bots.iter().cloned().collect(),
+            bot_commands: HashMap::new(),
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_custom_bot() {
+        let bot_id = PlayerId{id: 1};
+        let mut core = new_core_with_bots(&[(bot_id, new_reinforcing_bot)]);
+        core.do_command(Command::EndTurn);
+        assert_eq!(core.player_id(), PlayerId{id: 0});
+        let units_count = core.state.units().values()
+            .filter(|unit| unit.player_id == bot_id)
+            .count();
+        assert_eq!(units_count, 1);
+    }
+
+    /// Keeps attacking a unit that doesn't exist
+    #[derive(Debug)]
+    struct BrokenBot {
+        errors_count: i32,
+    }
+
+    impl Bot for BrokenBot {
+        fn apply_event(&mut self, _: &Db, _: &CoreEvent) {}
+
+        fn get_command(&mut self, _: &Db) -> Command {
+            Command::AttackUnit {
+                attacker_id: UnitId{id: 999},
+                defender_id: UnitId{id: 998},
+            }
+        }
+
+        fn report_error(&mut self, _: &Db, _: &Command, _: &CommandError) {
+            self.errors_count += 1;
+        }
+    }
+
+    fn new_broken_bot(_: PlayerId, _: &Options) -> io::Result<Box<Bot>> {
+        Ok(Box::new(BrokenBot{errors_count: 0}))
+    }
+
+    #[test]
+    fn test_broken_bot_loses_its_turn() {
+        let mut core = new_core_with_bots(&[(PlayerId{id: 1}, new_broken_bot)]);
+        core.do_command(Command::EndTurn);
+        assert_eq!(core.player_id(), PlayerId{id: 0});
+        let bot = format!("{:?}", core.bots[&PlayerId{id: 1}]);
+        assert_eq!(bot, format!("{:?}", BrokenBot{errors_count: MAX_BOT_ERRORS}));
+    }
+
+    #[test]
+    fn test_bots_play_each_other() {
+        let mut core = new_core_with_bots(&[
+            (PlayerId{id: 0}, new_default_bot),
+            (PlayerId{id: 1}, new_reinforcing_bot),
+        ]);
+        for turn in 0..4 {
+            assert_eq!(core.player_id(), PlayerId{id: turn % 2});
+            core.do_bot_turn();
+        }
+        for id in 0..2 {
+            let player_id = PlayerId{id: id};
+            assert!(core.state.units().values().any(|unit| unit.player_id == player_id));
+        }
+    }
+
+    #[test]
+    fn test_simulate_command() {
+        let mut core = new_core();
+        let player_id = core.player_id();
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 1, y: 1}});
+        end_round(&mut core);
+        let old_pos = core.state.unit(unit_id).pos;
+        let new_pos = MapPos{v: Vector2{x: 2, y: 1}};
+        let command = move_command(&core, unit_id, new_pos);
+        let view = core.players_info[&player_id].game_state.clone();
+        let (state, events) = simulate_command(
+            core.db(), &view, player_id, &command).unwrap();
+        assert_eq!(state.unit(unit_id).pos.map_pos, new_pos);
+        assert!(events.iter().any(|event| match *event {
+            CoreEvent::Move{unit_id: id, ..} => id == unit_id,
+            _ => false,
+        }));
+        assert_eq!(view.unit(unit_id).pos, old_pos);
+        assert_eq!(core.state.unit(unit_id).pos, old_pos);
+        assert_eq!(
+            simulate_command(core.db(), &view, PlayerId{id: 1}, &command).err(),
+            Some(CommandError::CanNotCommandEnemyUnits),
+        );
+    }
+
+    #[test]
+    fn test_simulated_indirect_fire_needs_spotter() {
+        let mut core = new_core();
+        let player_id = core.player_id();
+        let mortar_id = place_unit(&mut core, "mortar", MapPos{v: Vector2{x: 0, y: 6}});
+        end_round(&mut core);
+        // an enemy that is only guessed to be in the trees
+        let mut view = core.players_info[&player_id].game_state.clone();
+        let enemy_id = UnitId{id: 100};
+        view.add_guessed_unit(core.db(), &UnitInfo {
+            unit_id: enemy_id,
+            pos: ExactPos {
+                map_pos: MapPos{v: Vector2{x: 3, y: 6}},
+                slot_id: SlotId::Id(0),
+            },
+            type_id: core.db().unit_type_id("soldier"),
+            player_id: PlayerId{id: 1},
+            passenger_id: None,
+            is_entrenched: false,
+        });
+        view.guess_unknown_points(core.db());
+        let attack = Command::AttackUnit {
+            attacker_id: mortar_id,
+            defender_id: enemy_id,
+        };
+        assert_eq!(
+            simulate_command(core.db(), &view, player_id, &attack).err(),
+            Some(CommandError::NoSpotter),
+        );
+    }
+
+    #[test]
+    fn test_search_ai_captures_sectors() {
+        let mut core = new_core();
+        let ai_id = PlayerId{id: 1};
+        let unit_id = place_player_unit(
+            &mut core, ai_id, "soldier", MapPos{v: Vector2{x: 4, y: 4}});
+        core.do_command(Command::EndTurn);
+        let mut ai = SearchAi::new(ai_id, "map02", Budget::Rollouts(200));
+        while let Some(event) = core.get_event() {
+            ai.apply_event(core.db(), &event);
+        }
+        match ai.search(core.db()) {
+            Command::Move{unit_id: id, ref path, ..} => {
+                assert_eq!(id, unit_id);
+                assert_eq!(path.last().unwrap().map_pos, MapPos{v: Vector2{x: 4, y: 3}});
+            },
+            command => panic!("unexpected command: {:?}", command),
+        }
+    }
+
+    /// Sends a bad line and bad commands first, places a soldier
+    /// only after all the errors came back and then tries
+    /// to move it off the map
+    const SCRIPTED_BOT: &str = "
+        errors=0
+        unit=
+        while read -r line; do
+            case \"$line\" in
+                error*) errors=$((errors + 1));;
+                'event create_unit '*)
+                    set -- $line
+                    unit=$3;;
+                turn)
+                    case $errors in
+                        0) echo 'fly away';;
+                        1) echo 'attack 100 101';;
+                        2) echo 'move 999 fast 0,0,0 1,0,0';;
+                        3) echo 'create_unit soldier 8,4,0'; errors=4;;
+                        4) echo \"move $unit fast 8,4,0 8,-1,0\";;
+                        *) echo 'end_turn';;
+                    esac;;
+            esac
+        done
+    ";
+
+    #[cfg(unix)]
+    #[test]
+    fn test_external_bot() {
+        let bot_id = PlayerId{id: 1};
+        let mut bot_commands = HashMap::new();
+        let command_line = ["sh", "-c", SCRIPTED_BOT];
+        bot_commands.insert(bot_id, command_line.iter().map(|s| s.to_string()).collect());
+        let mut bots = HashMap::new();
+        bots.insert(bot_id, new_external_bot as BotFactory);
+        let mut core = Core::new(&Options {
+            game_type: GameType::Hotseat,
+            map_name: "map02".to_owned(),
+            ai_profiles: HashMap::new(),
+            bots: bots,
+            bot_commands: bot_commands,
+        }).unwrap();
+        core.do_command(Command::EndTurn);
+        assert_eq!(core.player_id(), PlayerId{id: 0});
+        let positions: Vec<_> = core.state.units().values()
+            .filter(|unit| unit.player_id == bot_id)
+            .map(|unit| unit.pos.map_pos)
+            .collect();
+        assert_eq!(positions, vec![MapPos{v: Vector2{x: 8, y: 4}}]);
+    }
+
+    #[test]
+    fn test_parse_bot_command() {
+        let db = Db::new();
+        assert_eq!(
+            parse_command(&db, "move 3 hunt 1,2,0 2,2,whole\n"),
+            Ok(Command::Move {
+                unit_id: UnitId{id: 3},
+                path: vec![
+                    ExactPos{map_pos: MapPos{v: Vector2{x: 1, y: 2}}, slot_id: SlotId::Id(0)},
+                    ExactPos{map_pos: MapPos{v: Vector2{x: 2, y: 2}}, slot_id: SlotId::WholeTile},
+                ],
+                mode: MoveMode::Hunt,
+            }),
+        );
+        assert_eq!(
+            parse_command(&db, "reaction_fire 1 overwatch 4,5"),
+            Ok(Command::SetReactionFireMode {
+                unit_id: UnitId{id: 1},
+                mode: ReactionFireMode::Overwatch{pos: MapPos{v: Vector2{x: 4, y: 5}}},
+            }),
+        );
+        assert!(parse_command(&db, "").is_err());
+        assert!(parse_command(&db, "attack 1").is_err());
+        assert!(parse_command(&db, "dig_in 1 2").is_err());
+        assert!(parse_command(&db, "create_unit dragon 0,0,0").is_err());
+    }
+
+    #[test]
+    fn test_external_bot_gives_up_on_bad_lines() {
+        let db = Db::new();
+        let mut lines = "fly away\n".repeat(MAX_BOT_ERRORS as usize);
+        lines.push_str("dig_in 1\n");
+        let mut bot = ExternalBot::new(
+            PlayerId{id: 1},
+            "map02",
+            Box::new(io::Cursor::new(lines.into_bytes())),
+            Box::new(io::sink()),
+        );
+        assert_eq!(bot.get_command(&db), Command::EndTurn);
+        assert_eq!(bot.get_command(&db), Command::DigIn{unit_id: UnitId{id: 1}});
+    }
+
+    #[test]
+    fn test_format_bot_event() {
+        let db = Db::new();
+        let from = ExactPos{map_pos: MapPos{v: Vector2{x: 1, y: 2}}, slot_id: SlotId::Id(0)};
+        let to = ExactPos{map_pos: MapPos{v: Vector2{x: 2, y: 2}}, slot_id: SlotId::WholeTile};
+        let event = CoreEvent::Move {
+            unit_id: UnitId{id: 3},
+            from: from,
+            to: to,
+            mode: MoveMode::Hunt,
+            cost: MovePoints{n: 4},
+        };
+        assert_eq!(format_event(&db, &event), "move 3 1,2,0 2,2,whole hunt 4");
+        let event = CoreEvent::CreateUnit {
+            unit_info: UnitInfo {
+                unit_id: UnitId{id: 5},
+                pos: from,
+                type_id: db.unit_type_id("soldier"),
+                player_id: PlayerId{id: 1},
+                passenger_id: None,
+                is_entrenched: false,
+            },
+        };
+        assert_eq!(format_event(&db, &event), "create_unit 5 soldier 1 1,2,0 - false");
+        let event = CoreEvent::AttackUnit {
+            attack_info: AttackInfo {
+                attacker_id: None,
+                defender_id: UnitId{id: 5},
+                mode: FireMode::Reactive,
+                killed: 1,
+                suppression: 30,
+                remove_move_points: true,
+                is_ambush: false,
+                is_inderect: true,
+                is_return_fire: false,
+            },
+        };
+        assert_eq!(
+            format_event(&db, &event),
+            "attack - 5 reactive 1 30 true false true false",
+        );
+        let event = CoreEvent::SetReactionFireMode {
+            unit_id: UnitId{id: 3},
+            mode: ReactionFireMode::Overwatch{pos: MapPos{v: Vector2{x: 4, y: 5}}},
+        };
+        assert_eq!(format_event(&db, &event), "reaction_fire 3 overwatch 4,5");
+    }
+
+    #[test]
+    fn test_check_move_path() {
+        let mut core = new_core();
+        let player_id = core.player_id();
+        let unit_id = place_unit(&mut core, "soldier", MapPos{v: Vector2{x: 0, y: 1}});
+        end_round(&mut core);
+        let from = core.state.unit(unit_id).pos;
+        let exact_pos = |x, y, slot| {
+            ExactPos{map_pos: MapPos{v: Vector2{x: x, y: y}}, slot_id: SlotId::Id(slot)}
+        };
+        let check = |unit_id, path: Vec<ExactPos>| {
+            let command = Command::Move{unit_id: unit_id, path: path, mode: MoveMode::Fast};
+            check_command(&core.db, player_id, &core.state, &command)
+        };
+        assert_eq!(
+            check(UnitId{id: 999}, vec![from, exact_pos(1, 1, 0)]),
+            Err(CommandError::BadUnitId),
+        );
+        // off the map
+        assert_eq!(check(unit_id, vec![from, exact_pos(-1, 1, 0)]), Err(CommandError::BadPath));
+        // not from the unit's position
+        assert_eq!(
+            check(unit_id, vec![exact_pos(1, 1, 0), exact_pos(2, 1, 0)]),
+            Err(CommandError::BadPath),
+        );
+        // not a neighbouring tile
+        assert_eq!(check(unit_id, vec![from, exact_pos(2, 1, 0)]), Err(CommandError::BadPath));
+        // no such slot
+        assert_eq!(check(unit_id, vec![from, exact_pos(1, 1, 7)]), Err(CommandError::BadPath));
+        let type_id = core.state.unit(unit_id).type_id;
+        let to = get_free_exact_pos(
+            &core.db, &core.state, type_id, MapPos{v: Vector2{x: 1, y: 1}}).unwrap();
+        assert_eq!(check(unit_id, vec![from, to]), Ok(()));
+    }
+
+    #[test]
+    fn test_hidden_obstacles_are_not_named() {
+        let mut core = new_core();
+        let bot_id = PlayerId{id: 1};
+        let pos = MapPos{v: Vector2{x: 8, y: 4}};
+        place_unit(&mut core, "soldier", pos);
+        let command = Command::CreateUnit {
+            type_id: core.db.unit_type_id("soldier"),
+            pos: ExactPos{map_pos: pos, slot_id: SlotId::Id(0)},
+        };
+        assert_eq!(
+            core.check_player_command(bot_id, &command),
+            Err(CommandError::UnseenObstacle),
+        );
+        core.cheating_bot_ids.insert(bot_id);
+        assert_eq!(
+            core.check_player_command(bot_id, &command),
+            Err(CommandError::TileIsOccupied),
+        );
+    }
 }
@@ -4,27 +4,109 @@ use db::{Db};
 use map::{Map, Terrain};
 use internal_state::{InternalState};
 use game_state::{GameState, GameStateMut};
-use fow::{Fow};
-use ::{CoreEvent, PlayerId, UnitId, ObjectId, Object, MapPos, Score, Sector, SectorId};
+use fow::{Fow, TileVisibility};
+use filter::{get_visible_enemies};
+use ::{
+    CoreEvent,
+    PlayerId,
+    UnitId,
+    ObjectId,
+    Object,
+    MapPos,
+    Score,
+    Sector,
+    SectorId,
+    SupportCall,
+    UnitInfo,
+};
 
 #[derive(Clone, Debug)]
 pub struct PartialState {
     state: InternalState,
     fow: Fow,
+    sees_everything: bool,
 }
 
 impl PartialState {
     pub fn new(map_name: &str, player_id: PlayerId) -> PartialState {
+        let mut state = InternalState::new(map_name);
+        let fow = Fow::new(state.map().size(), player_id);
+        state.remove_hidden_objects(&fow);
+        PartialState {
+            state: state,
+            fow: fow,
+            sees_everything: false,
+        }
+    }
+
+    /// The full state without any fog, for bots
+    /// that get all events unfiltered
+    pub fn new_full(map_name: &str, player_id: PlayerId) -> PartialState {
         let state = InternalState::new(map_name);
         let map_size = state.map().size();
         PartialState {
             state: state,
             fow: Fow::new(map_size, player_id),
+            sees_everything: true,
+        }
+    }
+
+    /// What the player can see of the full state right now
+    pub fn from_state(db: &Db, state: &InternalState, player_id: PlayerId) -> PartialState {
+        PartialState::from_state_shared(db, state, &[player_id])
+    }
+
+    /// What the players can see of the full state right now together
+    pub fn from_state_shared(
+        db: &Db,
+        state: &InternalState,
+        player_ids: &[PlayerId],
+    ) -> PartialState {
+        let mut fow = Fow::new_shared(state.map().size(), player_ids);
+        fow.reset(db, state);
+        let visible_enemies = get_visible_enemies(db, state, &fow);
+        let hidden_unit_ids: Vec<_> = state.units().values()
+            .filter(|unit| !fow.is_own(unit.player_id)
+                && !visible_enemies.contains(&unit.id))
+            .map(|unit| unit.id)
+            .collect();
+        let mut state = state.clone();
+        state.remove_hidden_objects(&fow);
+        for &unit_id in &hidden_unit_ids {
+            state.apply_event(db, &CoreEvent::HideUnit{unit_id: unit_id});
+        }
+        PartialState {
+            state: state,
+            fow: fow,
+            sees_everything: false,
         }
     }
 
     pub fn is_tile_visible(&self, pos: MapPos) -> bool {
-        self.fow.is_tile_visible(pos)
+        self.sees_everything || self.fow.is_tile_visible(pos)
+    }
+
+    pub fn tile_visibility(&self, pos: MapPos) -> TileVisibility {
+        if self.sees_everything {
+            TileVisibility::Excellent
+        } else {
+            self.fow.tile_visibility(pos)
+        }
+    }
+
+    /// Everything the player knows, without the fog
+    pub fn internal_state(&self) -> &InternalState {
+        &self.state
+    }
+
+    /// Puts an enemy unit where it's only guessed to be, for free
+    pub fn add_guessed_unit(&mut self, db: &Db, unit_info: &UnitInfo) {
+        self.state.add_guessed_unit(db, unit_info);
+    }
+
+    /// Assumes that enemy units we know only partially have all their points
+    pub fn guess_unknown_points(&mut self, db: &Db) {
+        self.state.guess_unknown_points(db);
     }
 }
 
@@ -52,6 +134,20 @@ impl GameState for PartialState {
     fn reinforcement_points(&self) -> &HashMap<PlayerId, i32> {
         self.state.reinforcement_points()
     }
+
+    fn support_points(&self) -> &HashMap<PlayerId, i32> {
+        self.state.support_points()
+    }
+
+    fn support_calls(&self) -> &[SupportCall] {
+        self.state.support_calls()
+    }
+
+    fn is_unit_visible(&self, db: &Db, unit: &Unit) -> bool {
+        self.sees_everything
+            || self.fow.is_own(unit.player_id)
+            || self.fow.is_visible(db, &self.state, unit, unit.pos)
+    }
 }
 
 impl GameStateMut for PartialState {
@@ -3,10 +3,19 @@ use types::{Size2};
 use db::{Db};
 use unit::{Unit, UnitClass};
 use map::{Map, Terrain};
-use partial_state::{PartialState};
 use game_state::{GameState};
 use dir::{Dir, dirs};
-use ::{MovePoints, ExactPos, SlotId, ObjectClass, get_free_exact_pos};
+use ::{
+    MovePoints,
+    MoveMode,
+    MapPos,
+    ExactPos,
+    SlotId,
+    ObjectClass,
+    get_free_exact_pos,
+    is_blocked_by_tank_traps,
+    move_cost_modifier,
+};
 
 #[derive(Clone, Debug)]
 pub struct Tile {
@@ -31,7 +40,13 @@ impl Default for Tile {
     }
 }
 
-pub fn truncate_path(db: &Db, state: &PartialState, path: &[ExactPos], unit: &Unit) -> Option<Vec<ExactPos>> {
+pub fn truncate_path<S: GameState>(
+    db: &Db,
+    state: &S,
+    path: &[ExactPos],
+    unit: &Unit,
+    mode: MoveMode,
+) -> Option<Vec<ExactPos>> {
     let mut new_path = Vec::new();
     let mut cost = MovePoints{n: 0};
     new_path.push(path[0]);
@@ -39,7 +54,7 @@ pub fn truncate_path(db: &Db, state: &PartialState, path: &[ExactPos], unit: &Un
     for window in path.windows(2) {
         let from = window[0];
         let to = window[1];
-        cost.n += tile_cost(db, state, unit, from, to).n;
+        cost.n += tile_cost(db, state, unit, from, to).n * move_cost_modifier(mode);
         if cost.n > move_points.n {
             break;
         }
@@ -77,7 +92,6 @@ pub fn tile_cost<S: GameState>(db: &Db, state: &S, unit: &Unit, from: ExactPos,
     let objects = state.objects_at(map_pos);
     let units = state.units_at(map_pos);
     let mut unit_cost = 0;
-    let mut object_cost = 0;
     let unit_type = db.unit_type(unit.type_id);
     if unit_type.is_air {
         return MovePoints{n: 2};
@@ -98,19 +112,7 @@ pub fn tile_cost<S: GameState>(db: &Db, state: &S, unit: &Unit, from: ExactPos,
         }
         unit_cost += 1;
     }
-    let tile = state.map().tile(pos);
-    let mut terrain_cost = match unit_type.class {
-        UnitClass::Infantry => match *tile {
-            Terrain::Plain | Terrain::City => 4,
-            Terrain::Trees => 5,
-            Terrain::Water => 99,
-        },
-        UnitClass::Vehicle => match *tile {
-            Terrain::Plain | Terrain::City => 4,
-            Terrain::Trees => 8,
-            Terrain::Water => 99,
-        },
-    };
+    let mut terrain_cost = base_terrain_cost(state, unit_type.class, map_pos);
     for object in &objects {
         if object.class != ObjectClass::Road {
             continue;
@@ -129,24 +131,74 @@ pub fn tile_cost<S: GameState>(db: &Db, state: &S, unit: &Unit, from: ExactPos,
             };
         }
     }
-    for object in &objects {
-        let cost = match unit_type.class {
+    let object_cost = objects_cost(state, unit_type.class, map_pos);
+    MovePoints{n: terrain_cost + object_cost + unit_cost}
+}
+
+fn base_terrain_cost<S: GameState>(state: &S, class: UnitClass, pos: MapPos) -> i32 {
+    let tile = state.map().tile(pos);
+    let mut terrain_cost = match class {
+        UnitClass::Infantry => match *tile {
+            Terrain::Plain | Terrain::City => 4,
+            Terrain::Trees => 5,
+            Terrain::Water => 99,
+        },
+        UnitClass::Vehicle => match *tile {
+            Terrain::Plain | Terrain::City => 4,
+            Terrain::Trees => 8,
+            Terrain::Water => 99,
+        },
+    };
+    for object in state.objects_at(pos) {
+        if object.class == ObjectClass::Bridge && object.is_complete() {
+            terrain_cost = 4;
+        }
+    }
+    terrain_cost
+}
+
+fn objects_cost<S: GameState>(state: &S, class: UnitClass, pos: MapPos) -> i32 {
+    let mut object_cost = 0;
+    for object in state.objects_at(pos) {
+        let cost = match class {
             UnitClass::Infantry => match object.class {
-                ObjectClass::Building => 1,
+                ObjectClass::Building | ObjectClass::TankTraps => 1,
+                ObjectClass::BarbedWire => 4,
                 ObjectClass::ReinforcementSector |
+                ObjectClass::Bridge |
+                ObjectClass::Entrenchment |
                 ObjectClass::Road |
+                ObjectClass::Minefield |
                 ObjectClass::Smoke => 0,
             },
             UnitClass::Vehicle => match object.class {
                 ObjectClass::Building => 2,
+                ObjectClass::BarbedWire => 1,
+                ObjectClass::Entrenchment => 2,
                 ObjectClass::ReinforcementSector |
+                ObjectClass::TankTraps |
+                ObjectClass::Bridge |
                 ObjectClass::Road |
+                ObjectClass::Minefield |
                 ObjectClass::Smoke => 0,
             },
         };
         object_cost += cost;
     }
-    MovePoints{n: terrain_cost + object_cost + unit_cost}
+    object_cost
+}
+
+/// Cost of entering the tile by a unit of the given class
+/// or `None` if the tile can't be entered at all,
+/// roads and other units are not taken into account
+pub fn terrain_cost<S: GameState>(state: &S, class: UnitClass, pos: MapPos)
+    -> Option<MovePoints>
+{
+    if is_blocked_by_tank_traps(state, class, pos) {
+        return None;
+    }
+    let n = base_terrain_cost(state, class, pos) + objects_cost(state, class, pos);
+    Some(MovePoints{n: n})
 }
 
 #[derive(Clone, Debug)]
@@ -167,18 +219,20 @@ impl Pathfinder {
         &self.map
     }
 
-    fn process_neighbour_pos(
+    fn process_neighbour_pos<S: GameState, F: Fn(MapPos) -> i32>(
         &mut self,
         db: &Db,
-        state: &PartialState,
+        state: &S,
         unit: &Unit,
+        extra_cost: &F,
         original_pos: ExactPos,
         neighbour_pos: ExactPos
     ) {
         let old_cost = self.map.tile(original_pos).cost;
-        let tile_cost = tile_cost(db, state, unit, original_pos, neighbour_pos);
+        let tile_cost = tile_cost(db, state, unit, original_pos, neighbour_pos).n
+            + extra_cost(neighbour_pos.map_pos);
         let tile = self.map.tile_mut(neighbour_pos);
-        let new_cost = MovePoints{n: old_cost.n + tile_cost.n};
+        let new_cost = MovePoints{n: old_cost.n + tile_cost};
         if tile.cost.n > new_cost.n {
             tile.cost = new_cost;
             tile.parent = Some(Dir::get_dir_from_to(
@@ -197,11 +251,12 @@ impl Pathfinder {
         }
     }
 
-    fn try_to_push_neighbours(
+    fn try_to_push_neighbours<S: GameState, F: Fn(MapPos) -> i32>(
         &mut self,
         db: &Db,
-        state: &PartialState,
+        state: &S,
         unit: &Unit,
+        extra_cost: &F,
         pos: ExactPos,
     ) {
         assert!(self.map.is_inboard(pos));
@@ -215,7 +270,7 @@ impl Pathfinder {
                     None => continue,
                 };
                 self.process_neighbour_pos(
-                    db, state, unit, pos, exact_neighbour_pos);
+                    db, state, unit, extra_cost, pos, exact_neighbour_pos);
             }
         }
     }
@@ -228,13 +283,25 @@ impl Pathfinder {
         self.queue.push(start_pos);
     }
 
-    pub fn fill_map(&mut self, db: &Db, state: &PartialState, unit: &Unit) {
+    pub fn fill_map<S: GameState>(&mut self, db: &Db, state: &S, unit: &Unit) {
+        self.fill_map_with_extra_cost(db, state, unit, &|_| 0);
+    }
+
+    /// Entering a tile costs more by `extra_cost(pos)`, so the costs
+    /// in the map are not exactly move points anymore
+    pub fn fill_map_with_extra_cost<S: GameState, F: Fn(MapPos) -> i32>(
+        &mut self,
+        db: &Db,
+        state: &S,
+        unit: &Unit,
+        extra_cost: &F,
+    ) {
         assert!(self.queue.len() == 0);
         self.clean_map();
         self.push_start_pos_to_queue(unit.pos);
         while !self.queue.is_empty() {
             let pos = self.queue.remove(0);
-            self.try_to_push_neighbours(db, state, unit, pos);
+            self.try_to_push_neighbours(db, state, unit, extra_cost, pos);
         }
     }
 
@@ -0,0 +1,387 @@
+//! AI that picks commands by playing them out on copies of the state
+
+use std::cmp;
+use std::collections::{HashMap};
+use std::time::{Duration, Instant};
+use rand::{thread_rng, Rng};
+use game_state::{GameState, GameStateMut};
+use partial_state::{PartialState};
+use pathfinder::{Pathfinder};
+use map::{distance, spiral_iter};
+use unit::{Unit, UnitTypeId};
+use db::{Db};
+use check::{check_command};
+use ::{
+    Bot,
+    Command,
+    CoreEvent,
+    MapPos,
+    MoveMode,
+    ObjectClass,
+    PlayerId,
+    UnitId,
+    UnitInfo,
+    get_free_exact_pos,
+    simulate_command,
+};
+
+/// How long the AI thinks about every command by default
+pub const DEFAULT_TIME_BUDGET_MS: u64 = 200;
+
+/// When the search stops
+#[derive(Clone, Copy, Debug)]
+pub enum Budget {
+    Time(Duration),
+
+    /// Fixed amount of work that doesn't depend on the machine's speed
+    Rollouts(usize),
+}
+
+impl Budget {
+    fn is_exceeded(&self, start_time: Instant, rollouts_count: usize) -> bool {
+        match *self {
+            Budget::Time(duration) => start_time.elapsed() >= duration,
+            Budget::Rollouts(count) => rollouts_count >= count,
+        }
+    }
+}
+
+/// Every unit considers moving to this many tiles closest to its objective
+const MOVE_CANDIDATES: usize = 3;
+
+/// Random attacks played after the candidate command in every rollout
+const ROLLOUT_DEPTH: usize = 2;
+
+/// Bigger values make the search try less promising commands more often
+const EXPLORATION_WEIGHT: f32 = 2.0;
+
+/// Evaluation points for one victory point
+const SCORE_WEIGHT: f32 = 10.0;
+
+/// Evaluation points for owning a sector
+const SECTOR_WEIGHT: f32 = 5.0;
+
+/// Evaluation points lost for every tile between a unit and its objective
+const DISTANCE_WEIGHT: f32 = 0.1;
+
+/// Reinforcement points spent on what's left of the unit,
+/// suppressed units are worth half as much
+fn unit_value(db: &Db, unit: &Unit) -> f32 {
+    let unit_type = db.unit_type(unit.type_id);
+    let value = unit_type.cost as f32 * unit.count as f32 / unit_type.count as f32;
+    let morale = cmp::max(unit.morale, 0) as f32 / 100.0;
+    value * (0.5 + 0.5 * morale)
+}
+
+/// Sectors that the player doesn't own and enemy positions
+fn objectives<S: GameState>(state: &S, player_id: PlayerId) -> Vec<MapPos> {
+    let sectors = state.sectors().values()
+        .filter(|sector| sector.owner_id != Some(player_id))
+        .map(|sector| sector.center());
+    let enemies = state.units().values()
+        .filter(|unit| unit.player_id != player_id)
+        .map(|unit| unit.pos.map_pos);
+    sectors.chain(enemies).collect()
+}
+
+fn distance_to_objective(objectives: &[MapPos], pos: MapPos) -> i32 {
+    objectives.iter()
+        .map(|&objective| distance(objective, pos))
+        .min()
+        .unwrap_or(0)
+}
+
+/// Higher is better for the player
+fn evaluate<S: GameState>(db: &Db, state: &S, player_id: PlayerId) -> f32 {
+    let objectives = objectives(state, player_id);
+    let mut value = 0.0;
+    for unit in state.units().values() {
+        if unit.player_id == player_id {
+            value += unit_value(db, unit);
+            if !db.unit_type(unit.type_id).is_air {
+                let distance = distance_to_objective(&objectives, unit.pos.map_pos);
+                value -= DISTANCE_WEIGHT * distance as f32;
+            }
+        } else {
+            value -= unit_value(db, unit);
+        }
+    }
+    // points are worth what they can buy, so buying a unit alone isn't a gain
+    if let Some(&points) = state.reinforcement_points().get(&player_id) {
+        value += points as f32;
+    }
+    for (&id, score) in state.score() {
+        if id == player_id {
+            value += SCORE_WEIGHT * score.n as f32;
+        } else {
+            value -= SCORE_WEIGHT * score.n as f32;
+        }
+    }
+    for sector in state.sectors().values() {
+        match sector.owner_id {
+            Some(id) if id == player_id => value += SECTOR_WEIGHT,
+            Some(_) => value -= SECTOR_WEIGHT,
+            None => {},
+        }
+    }
+    value
+}
+
+fn attack_commands<S: GameState>(db: &Db, state: &S, player_id: PlayerId) -> Vec<Command> {
+    let mut commands = Vec::new();
+    for unit in state.units().values() {
+        if unit.player_id != player_id {
+            continue;
+        }
+        for enemy in state.units().values() {
+            if enemy.player_id == player_id {
+                continue;
+            }
+            let command = Command::AttackUnit {
+                attacker_id: unit.id,
+                defender_id: enemy.id,
+            };
+            if check_command(db, player_id, state, &command).is_ok() {
+                commands.push(command);
+            }
+        }
+    }
+    commands
+}
+
+fn move_commands<S: GameState>(
+    db: &Db,
+    state: &S,
+    pathfinder: &mut Pathfinder,
+    player_id: PlayerId,
+) -> Vec<Command> {
+    let objectives = objectives(state, player_id);
+    let mut commands = Vec::new();
+    for unit in state.units().values() {
+        if unit.player_id != player_id {
+            continue;
+        }
+        let move_points = match unit.move_points {
+            Some(move_points) if move_points.n > 0 => move_points,
+            _ => continue,
+        };
+        pathfinder.fill_map(db, state, unit);
+        let mut destinations = Vec::new();
+        for pos in state.map().get_iter() {
+            let exact_pos = match get_free_exact_pos(db, state, unit.type_id, pos) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let cost = pathfinder.get_map().tile(exact_pos).cost();
+            if cost.n == 0 || cost.n > move_points.n {
+                continue;
+            }
+            destinations.push((distance_to_objective(&objectives, pos), cost.n, exact_pos));
+        }
+        destinations.sort_by_key(|&(distance, cost, _)| (distance, cost));
+        for &(_, _, destination) in destinations.iter().take(MOVE_CANDIDATES) {
+            let path = match pathfinder.get_path(destination) {
+                Some(path) => path,
+                None => continue,
+            };
+            let command = Command::Move {
+                unit_id: unit.id,
+                path: path,
+                mode: MoveMode::Fast,
+            };
+            if check_command(db, player_id, state, &command).is_ok() {
+                commands.push(command);
+            }
+        }
+    }
+    commands
+}
+
+/// Every affordable unit type in the first free reinforcement sector
+fn create_unit_commands<S: GameState>(db: &Db, state: &S, player_id: PlayerId) -> Vec<Command> {
+    let mut commands = Vec::new();
+    for type_index in 0..db.unit_types().len() {
+        let type_id = UnitTypeId{id: type_index as i32};
+        let sector_positions = state.objects().values()
+            .filter(|object| object.class == ObjectClass::ReinforcementSector)
+            .filter(|object| object.owner_id == Some(player_id))
+            .map(|object| object.pos.map_pos);
+        for pos in sector_positions {
+            let exact_pos = match get_free_exact_pos(db, state, type_id, pos) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let command = Command::CreateUnit {
+                type_id: type_id,
+                pos: exact_pos,
+            };
+            if check_command(db, player_id, state, &command).is_ok() {
+                commands.push(command);
+                break;
+            }
+        }
+    }
+    commands
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchAi {
+    id: PlayerId,
+    state: PartialState,
+    pathfinder: Pathfinder,
+    /// Enemies that we have seen, at the last seen positions
+    known_enemies: HashMap<UnitId, UnitInfo>,
+    budget: Budget,
+}
+
+impl SearchAi {
+    pub fn new(id: PlayerId, map_name: &str, budget: Budget) -> SearchAi {
+        let state = PartialState::new(map_name, id);
+        let map_size = state.map().size();
+        SearchAi {
+            id: id,
+            state: state,
+            pathfinder: Pathfinder::new(map_size),
+            known_enemies: HashMap::new(),
+            budget: budget,
+        }
+    }
+
+    /// Known state where hidden enemies stay near the last seen
+    /// positions, but out of sight, and have all their points
+    fn sample_state(&self, db: &Db) -> PartialState {
+        let mut state = self.state.clone();
+        let mut rng = thread_rng();
+        for (id, unit_info) in &self.known_enemies {
+            if state.units().contains_key(id) {
+                continue;
+            }
+            let last_pos = unit_info.pos.map_pos;
+            let mut positions: Vec<_> = spiral_iter(last_pos, 1)
+                .filter(|&pos| state.map().is_inboard(pos))
+                .collect();
+            rng.shuffle(&mut positions);
+            positions.insert(0, last_pos);
+            let exact_pos = positions.into_iter()
+                .filter(|&pos| !self.state.is_tile_visible(pos))
+                .filter_map(|pos| get_free_exact_pos(db, &state, unit_info.type_id, pos))
+                .next();
+            if let Some(exact_pos) = exact_pos {
+                state.add_guessed_unit(db, &UnitInfo {
+                    pos: exact_pos,
+                    .. unit_info.clone()
+                });
+            }
+        }
+        state.guess_unknown_points(db);
+        state
+    }
+
+    fn candidate_commands(&mut self, db: &Db, state: &PartialState) -> Vec<Command> {
+        let mut commands = vec![Command::EndTurn];
+        commands.extend(attack_commands(db, state, self.id));
+        commands.extend(move_commands(db, state, &mut self.pathfinder, self.id));
+        commands.extend(create_unit_commands(db, state, self.id));
+        commands
+    }
+
+    /// Plays the command and a few random attacks after it
+    /// on a sampled state and evaluates the result
+    fn rollout(&self, db: &Db, command: &Command) -> f32 {
+        let mut state = self.sample_state(db);
+        if *command == Command::EndTurn {
+            return evaluate(db, &state, self.id);
+        }
+        state = match simulate_command(db, &state, self.id, command) {
+            Ok((state, _)) => state,
+            // a guessed enemy got in the way
+            Err(_) => return evaluate(db, &state, self.id),
+        };
+        let mut rng = thread_rng();
+        for _ in 0..ROLLOUT_DEPTH {
+            let attacks = attack_commands(db, &state, self.id);
+            let attack = match rng.choose(&attacks) {
+                Some(attack) => attack,
+                None => break,
+            };
+            if let Ok((new_state, _)) = simulate_command(db, &state, self.id, attack) {
+                state = new_state;
+            }
+        }
+        evaluate(db, &state, self.id)
+    }
+
+    /// Flat Monte-Carlo search: every candidate gets rollouts
+    /// picked by UCB1 until the budget is spent
+    pub fn search(&mut self, db: &Db) -> Command {
+        let mut state = self.state.clone();
+        state.guess_unknown_points(db);
+        let commands = self.candidate_commands(db, &state);
+        let mut visits = vec![0; commands.len()];
+        let mut totals = vec![0.0; commands.len()];
+        let base_value = evaluate(db, &state, self.id);
+        let start_time = Instant::now();
+        let mut rollouts_count = 0;
+        while rollouts_count < commands.len()
+            || !self.budget.is_exceeded(start_time, rollouts_count)
+        {
+            let index = if rollouts_count < commands.len() {
+                rollouts_count
+            } else {
+                let log_count = (rollouts_count as f32).ln();
+                let ucb = |i: usize| {
+                    let mean = totals[i] / visits[i] as f32;
+                    mean + EXPLORATION_WEIGHT * (log_count / visits[i] as f32).sqrt()
+                };
+                (0..commands.len())
+                    .max_by(|&a, &b| ucb(a).partial_cmp(&ucb(b)).unwrap())
+                    .unwrap()
+            };
+            totals[index] += self.rollout(db, &commands[index]) - base_value;
+            visits[index] += 1;
+            rollouts_count += 1;
+        }
+        let mean = |i: usize| totals[i] / visits[i] as f32;
+        let best_index = (0..commands.len())
+            .max_by(|&a, &b| mean(a).partial_cmp(&mean(b)).unwrap())
+            .unwrap();
+        commands[best_index].clone()
+    }
+}
+
+impl Bot for SearchAi {
+    fn apply_event(&mut self, db: &Db, event: &CoreEvent) {
+        let units_count = self.state.units().len();
+        self.state.apply_event(db, event);
+        match *event {
+            CoreEvent::CreateUnit{ref unit_info}
+            | CoreEvent::ShowUnit{ref unit_info}
+            | CoreEvent::UnloadUnit{ref unit_info, ..}
+                if unit_info.player_id != self.id =>
+            {
+                self.known_enemies.insert(unit_info.unit_id, unit_info.clone());
+            },
+            CoreEvent::Move{unit_id, to, ..} => {
+                if let Some(unit_info) = self.known_enemies.get_mut(&unit_id) {
+                    unit_info.pos = to;
+                }
+            },
+            CoreEvent::AttackUnit{ref attack_info}
+                if self.state.units().len() < units_count =>
+            {
+                self.known_enemies.remove(&attack_info.defender_id);
+            },
+            _ => {},
+        }
+        // an enemy that isn't on the tile where it was seen
+        // last time is somewhere else and can't be guessed
+        let state = &self.state;
+        self.known_enemies.retain(|id, unit_info| {
+            state.units().contains_key(id) || !state.is_tile_visible(unit_info.pos.map_pos)
+        });
+    }
+
+    fn get_command(&mut self, db: &Db) -> Command {
+        self.search(db)
+    }
+}
@@ -0,0 +1,67 @@
+//! Tiles covered by enemy reaction fire
+
+use std::cmp;
+use types::{Size2};
+use db::{Db};
+use map::{Map};
+use fov::{fov, simple_fov};
+use game_state::{GameState};
+use ::{PlayerId, MapPos, ReactionFireMode};
+
+#[derive(Clone, Debug)]
+pub struct ThreatMap {
+    map: Map<i32>,
+}
+
+impl ThreatMap {
+    pub fn new(map_size: Size2) -> ThreatMap {
+        ThreatMap {
+            map: Map::new(map_size),
+        }
+    }
+
+    /// Number of known enemies that can see the tile
+    /// and open reaction fire at it
+    pub fn threat(&self, pos: MapPos) -> i32 {
+        *self.map.tile(pos)
+    }
+
+    pub fn update<S: GameState>(&mut self, db: &Db, state: &S, player_id: PlayerId) {
+        for pos in self.map.get_iter() {
+            *self.map.tile_mut(pos) = 0;
+        }
+        for enemy in state.units().values() {
+            if enemy.player_id == player_id {
+                continue;
+            }
+            if enemy.reaction_fire_mode == ReactionFireMode::HoldFire {
+                continue;
+            }
+            let is_passenger = state.units().values()
+                .any(|unit| unit.passenger_id == Some(enemy.id));
+            if is_passenger {
+                continue;
+            }
+            let enemy_type = db.unit_type(enemy.type_id);
+            let weapon_type = db.weapon_type(enemy_type.weapon_type_id);
+            if !weapon_type.reaction_fire || enemy_type.reactive_attack_points.n <= 0 {
+                continue;
+            }
+            let range = cmp::min(enemy_type.los_range, weapon_type.max_distance);
+            let f = if enemy_type.is_air {
+                simple_fov
+            } else {
+                fov
+            };
+            let map = &mut self.map;
+            let mode = enemy.reaction_fire_mode;
+            let origin = enemy.pos.map_pos;
+            // overwatch covers only its sector and ambush only the nearby tiles
+            f(state, origin, range, &mut |pos| {
+                if mode.is_target_allowed(origin, pos) {
+                    *map.tile_mut(pos) += 1;
+                }
+            });
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use ::{ReactionFireMode, MovePoints, AttackPoints, UnitId, PlayerId, ExactPos};
+use ::{ReactionFireMode, MoveMode, MovePoints, AttackPoints, UnitId, PlayerId, ExactPos};
 
 #[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub struct UnitTypeId{pub id: i32}
@@ -22,6 +22,11 @@ pub struct Unit {
     pub count: i32,
     pub morale: i32,
     pub passenger_id: Option<UnitId>,
+    pub is_entrenched: bool,
+    /// Mode of the last move made during this turn
+    pub move_mode: Option<MoveMode>,
+    /// Gets entrenched when its player's next turn begins
+    pub is_digging_in: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -59,5 +64,6 @@ pub struct UnitType {
     pub is_transporter: bool,
     pub is_big: bool,
     pub is_air: bool,
+    pub is_engineer: bool,
     pub cost: i32,
 }
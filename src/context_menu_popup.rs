@@ -2,11 +2,12 @@ use std::sync::mpsc::{Sender};
 use std::collections::{HashMap};
 use glutin::{self, Event, MouseButton, VirtualKeyCode};
 use glutin::ElementState::{Released};
-use core::{self, ObjectClass, UnitId, MapPos, ExactPos};
+use core::{self, ObjectClass, UnitId, MapPos, ExactPos, SupportType};
 use core::partial_state::{PartialState};
 use core::game_state::{GameState};
 use core::db::{Db};
 use core::check::{check_command};
+use core::hit_chance::{hit_chance, HitChance};
 use types::{Time, ScreenPos};
 use screen::{Screen, ScreenCommand, EventStatus};
 use context::{Context};
@@ -73,6 +74,16 @@ pub fn get_options(
         }
         options.reinforcements_pos = Some(pos);
     }
+    for &support_type in &[SupportType::Artillery, SupportType::AirStrike] {
+        let command = core::Command::CallSupport {
+            pos: pos,
+            support_type: support_type,
+        };
+        let is_ok = check_command(db, player_id, state, &command).is_ok();
+        if is_ok && state.is_tile_visible(pos) {
+            options.supports.push((pos, support_type));
+        }
+    }
     let selected_unit_id = match selected_unit_id {
         Some(id) => id,
         None => {
@@ -93,11 +104,27 @@ pub fn get_options(
                 if unit_type.attack_points.n != 0
                     || unit_type.reactive_attack_points.n != 0
                 {
-                    if unit.reaction_fire_mode == core::ReactionFireMode::HoldFire {
+                    let mode = unit.reaction_fire_mode;
+                    if mode != core::ReactionFireMode::Normal {
                         options.enable_reaction_fire = Some(selected_unit_id);
-                    } else {
+                    }
+                    if mode != core::ReactionFireMode::HoldFire {
                         options.disable_reaction_fire = Some(selected_unit_id);
                     }
+                    let ambush_distance = 2;
+                    let ambush_command = core::Command::SetReactionFireMode {
+                        unit_id: unit_id,
+                        mode: core::ReactionFireMode::Ambush {
+                            distance: ambush_distance,
+                        },
+                    };
+                    if check_command(db, player_id, state, &ambush_command).is_ok() {
+                        options.ambush = Some((unit_id, ambush_distance));
+                    }
+                }
+                let dig_in_command = core::Command::DigIn{unit_id: unit_id};
+                if check_command(db, player_id, state, &dig_in_command).is_ok() {
+                    options.dig_in = Some(unit_id);
                 }
             } else {
                 options.selects.push(unit_id);
@@ -112,7 +139,7 @@ pub fn get_options(
         } else {
             let attacker = state.unit(selected_unit_id);
             let defender = state.unit(unit_id);
-            let hit_chance = core.hit_chance(attacker, defender);
+            let hit_chance = hit_chance(db, state, attacker, defender);
             let attack_command = core::Command::AttackUnit {
                 attacker_id: attacker.id,
                 defender_id: defender.id,
@@ -122,12 +149,55 @@ pub fn get_options(
             }
         }
     }
+    if check_command(db, player_id, state, &core::Command::AreaFire {
+        unit_id: selected_unit_id,
+        pos: pos,
+    }).is_ok() {
+        options.area_fire_pos = Some(pos);
+    }
+    let selected_unit = state.unit(selected_unit_id);
+    let selected_unit_type = db.unit_type(selected_unit.type_id);
+    let overwatch_command = core::Command::SetReactionFireMode {
+        unit_id: selected_unit_id,
+        mode: core::ReactionFireMode::Overwatch{pos: pos},
+    };
+    if selected_unit.player_id == player_id
+        && selected_unit.pos.map_pos != pos
+        && selected_unit_type.reactive_attack_points.n != 0
+        && check_command(db, player_id, state, &overwatch_command).is_ok()
+    {
+        options.overwatch_pos = Some(pos);
+    }
     if check_command(db, player_id, state, &core::Command::Smoke {
         unit_id: selected_unit_id,
         pos: pos,
     }).is_ok() {
         options.smoke_pos = Some(pos);
     }
+    if check_command(db, player_id, state, &core::Command::BuildBridge {
+        unit_id: selected_unit_id,
+        pos: pos,
+    }).is_ok() {
+        options.build_bridge_pos = Some(pos);
+    }
+    if check_command(db, player_id, state, &core::Command::BuildEntrenchment {
+        unit_id: selected_unit_id,
+        pos: pos,
+    }).is_ok() {
+        options.build_entrenchment_pos = Some(pos);
+    }
+    if check_command(db, player_id, state, &core::Command::PlaceMinefield {
+        unit_id: selected_unit_id,
+        pos: pos,
+    }).is_ok() {
+        options.place_minefield_pos = Some(pos);
+    }
+    if check_command(db, player_id, state, &core::Command::ClearMinefield {
+        unit_id: selected_unit_id,
+        pos: pos,
+    }).is_ok() {
+        options.clear_minefield_pos = Some(pos);
+    }
     if let Some(pos) = can_unload_unit(db, state, selected_unit_id, pos) {
         options.unload_pos = Some(pos);
     }
@@ -165,26 +235,44 @@ pub enum Command {
     Move{pos: ExactPos},
     Hunt{pos: ExactPos},
     Attack{id: UnitId},
+    AreaFire{pos: MapPos},
     LoadUnit{passenger_id: UnitId},
     UnloadUnit{pos: ExactPos},
     EnableReactionFire{id: UnitId},
     DisableReactionFire{id: UnitId},
+    Overwatch{pos: MapPos},
+    Ambush{id: UnitId, distance: i32},
+    DigIn{id: UnitId},
     Smoke{pos: MapPos},
+    BuildBridge{pos: MapPos},
+    BuildEntrenchment{pos: MapPos},
+    PlaceMinefield{pos: MapPos},
+    ClearMinefield{pos: MapPos},
     CallReiforcements{pos: MapPos},
+    CallSupport{pos: MapPos, support_type: SupportType},
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct Options {
     selects: Vec<UnitId>,
-    attacks: Vec<(UnitId, i32)>,
+    attacks: Vec<(UnitId, HitChance)>,
     loads: Vec<UnitId>,
     move_pos: Option<ExactPos>,
     hunt_pos: Option<ExactPos>,
+    area_fire_pos: Option<MapPos>,
     unload_pos: Option<ExactPos>,
     smoke_pos: Option<MapPos>,
+    build_bridge_pos: Option<MapPos>,
+    build_entrenchment_pos: Option<MapPos>,
+    place_minefield_pos: Option<MapPos>,
+    clear_minefield_pos: Option<MapPos>,
     enable_reaction_fire: Option<UnitId>,
     disable_reaction_fire: Option<UnitId>,
+    overwatch_pos: Option<MapPos>,
+    ambush: Option<(UnitId, i32)>,
+    dig_in: Option<UnitId>,
     reinforcements_pos: Option<MapPos>,
+    supports: Vec<(MapPos, SupportType)>,
 }
 
 impl Options {
@@ -195,11 +283,20 @@ impl Options {
             loads: Vec::new(),
             move_pos: None,
             hunt_pos: None,
+            area_fire_pos: None,
             unload_pos: None,
             smoke_pos: None,
+            build_bridge_pos: None,
+            build_entrenchment_pos: None,
+            place_minefield_pos: None,
+            clear_minefield_pos: None,
             enable_reaction_fire: None,
             disable_reaction_fire: None,
+            overwatch_pos: None,
+            ambush: None,
+            dig_in: None,
             reinforcements_pos: None,
+            supports: Vec::new(),
         }
     }
 }
@@ -214,11 +311,20 @@ pub struct ContextMenuPopup {
     load_button_ids: HashMap<ButtonId, UnitId>,
     move_button_id: Option<ButtonId>,
     hunt_button_id: Option<ButtonId>,
+    area_fire_button_id: Option<ButtonId>,
     unload_unit_button_id: Option<ButtonId>,
     smoke_button_id: Option<ButtonId>,
+    build_bridge_button_id: Option<ButtonId>,
+    build_entrenchment_button_id: Option<ButtonId>,
+    place_minefield_button_id: Option<ButtonId>,
+    clear_minefield_button_id: Option<ButtonId>,
     enable_reaction_fire_button_id: Option<ButtonId>,
     disable_reaction_fire_button_id: Option<ButtonId>,
+    overwatch_button_id: Option<ButtonId>,
+    ambush_button_id: Option<ButtonId>,
+    dig_in_button_id: Option<ButtonId>,
     call_reinforcements_button_id: Option<ButtonId>,
+    support_button_ids: HashMap<ButtonId, (MapPos, SupportType)>,
 }
 
 impl ContextMenuPopup {
@@ -236,11 +342,20 @@ impl ContextMenuPopup {
         let mut load_button_ids = HashMap::new();
         let mut move_button_id = None;
         let mut hunt_button_id = None;
+        let mut area_fire_button_id = None;
         let mut unload_unit_button_id = None;
         let mut smoke_button_id = None;
+        let mut build_bridge_button_id = None;
+        let mut build_entrenchment_button_id = None;
+        let mut place_minefield_button_id = None;
+        let mut clear_minefield_button_id = None;
         let mut enable_reaction_fire_button_id = None;
         let mut disable_reaction_fire_button_id = None;
+        let mut overwatch_button_id = None;
+        let mut ambush_button_id = None;
+        let mut dig_in_button_id = None;
         let mut call_reinforcements_button_id = None;
+        let mut support_button_ids = HashMap::new();
         let mut pos = pos;
         let text_size = basic_text_size(context);
         pos.v.y -= text_size as i32 / 2;
@@ -255,7 +370,12 @@ impl ContextMenuPopup {
         }
         for &(unit_id, hit_chance) in &options.attacks {
             let unit_type = db.unit_type(state.unit(unit_id).type_id);
-            let text = format!("attack <{}> ({}%)", unit_type.name, hit_chance);
+            let text = format!(
+                "attack <{}> ({}%, ~{:.1} killed)",
+                unit_type.name,
+                hit_chance.total,
+                hit_chance.expected_kills,
+            );
             let button_id = button_manager.add_button(
                 Button::new(context, &text, pos));
             attack_button_ids.insert(button_id, unit_id);
@@ -278,6 +398,11 @@ impl ContextMenuPopup {
                 Button::new(context, "hunt", pos)));
             pos.v.y -= vstep;
         }
+        if options.area_fire_pos.is_some() {
+            area_fire_button_id = Some(button_manager.add_button(
+                Button::new(context, "area fire", pos)));
+            pos.v.y -= vstep;
+        }
         if options.enable_reaction_fire.is_some() {
             enable_reaction_fire_button_id = Some(button_manager.add_button(
                 Button::new(context, "enable reaction fire", pos)));
@@ -288,11 +413,37 @@ impl ContextMenuPopup {
                 Button::new(context, "disable reaction fire", pos)));
             pos.v.y -= vstep;
         }
+        if options.overwatch_pos.is_some() {
+            overwatch_button_id = Some(button_manager.add_button(
+                Button::new(context, "overwatch", pos)));
+            pos.v.y -= vstep;
+        }
+        if options.ambush.is_some() {
+            ambush_button_id = Some(button_manager.add_button(
+                Button::new(context, "ambush", pos)));
+            pos.v.y -= vstep;
+        }
+        if options.dig_in.is_some() {
+            dig_in_button_id = Some(button_manager.add_button(
+                Button::new(context, "dig in", pos)));
+            pos.v.y -= vstep;
+        }
         if options.reinforcements_pos.is_some() {
             call_reinforcements_button_id = Some(button_manager.add_button(
                 Button::new(context, "reinforcements", pos)));
             pos.v.y -= vstep;
         }
+        for &(support_pos, support_type) in &options.supports {
+            let text = match support_type {
+                SupportType::Artillery => "call artillery",
+                SupportType::AirStrike => "call air strike",
+            };
+            let text = format!("{} ({})", text, support_type.cost());
+            let button_id = button_manager.add_button(
+                Button::new(context, &text, pos));
+            support_button_ids.insert(button_id, (support_pos, support_type));
+            pos.v.y -= vstep;
+        }
         if options.unload_pos.is_some() {
             unload_unit_button_id = Some(button_manager.add_button(
                 Button::new(context, "unload", pos)));
@@ -303,6 +454,26 @@ impl ContextMenuPopup {
                 Button::new(context, "smoke", pos)));
             pos.v.y -= vstep;
         }
+        if options.build_bridge_pos.is_some() {
+            build_bridge_button_id = Some(button_manager.add_button(
+                Button::new(context, "build bridge", pos)));
+            pos.v.y -= vstep;
+        }
+        if options.build_entrenchment_pos.is_some() {
+            build_entrenchment_button_id = Some(button_manager.add_button(
+                Button::new(context, "build entrenchment", pos)));
+            pos.v.y -= vstep;
+        }
+        if options.place_minefield_pos.is_some() {
+            place_minefield_button_id = Some(button_manager.add_button(
+                Button::new(context, "place mines", pos)));
+            pos.v.y -= vstep;
+        }
+        if options.clear_minefield_pos.is_some() {
+            clear_minefield_button_id = Some(button_manager.add_button(
+                Button::new(context, "clear mines", pos)));
+            pos.v.y -= vstep;
+        }
         ContextMenuPopup {
             game_screen_tx: tx,
             button_manager: button_manager,
@@ -311,11 +482,20 @@ impl ContextMenuPopup {
             load_button_ids: load_button_ids,
             move_button_id: move_button_id,
             hunt_button_id: hunt_button_id,
+            area_fire_button_id: area_fire_button_id,
             unload_unit_button_id: unload_unit_button_id,
             smoke_button_id: smoke_button_id,
+            build_bridge_button_id: build_bridge_button_id,
+            build_entrenchment_button_id: build_entrenchment_button_id,
+            place_minefield_button_id: place_minefield_button_id,
+            clear_minefield_button_id: clear_minefield_button_id,
             enable_reaction_fire_button_id: enable_reaction_fire_button_id,
             disable_reaction_fire_button_id: disable_reaction_fire_button_id,
+            overwatch_button_id: overwatch_button_id,
+            ambush_button_id: ambush_button_id,
+            dig_in_button_id: dig_in_button_id,
             call_reinforcements_button_id: call_reinforcements_button_id,
+            support_button_ids: support_button_ids,
             options: options,
         }
     }
@@ -353,6 +533,13 @@ impl ContextMenuPopup {
             });
             return;
         }
+        if let Some(&(pos, support_type)) = self.support_button_ids.get(&button_id) {
+            self.return_command(context, Command::CallSupport {
+                pos: pos,
+                support_type: support_type,
+            });
+            return;
+        }
         if let Some(&unit_id) = self.load_button_ids.get(&button_id) {
             self.return_command(context, Command::LoadUnit {
                 passenger_id: unit_id,
@@ -368,6 +555,10 @@ impl ContextMenuPopup {
             self.return_command(context, Command::Hunt {
                 pos: self.options.move_pos.unwrap(),
             });
+        } else if id == self.area_fire_button_id {
+            self.return_command(context, Command::AreaFire {
+                pos: self.options.area_fire_pos.unwrap(),
+            });
         } else if id == self.unload_unit_button_id {
             self.return_command(context, Command::UnloadUnit {
                 pos: self.options.unload_pos.unwrap(),
@@ -376,6 +567,22 @@ impl ContextMenuPopup {
             self.return_command(context, Command::Smoke {
                 pos: self.options.smoke_pos.unwrap(),
             });
+        } else if id == self.build_bridge_button_id {
+            self.return_command(context, Command::BuildBridge {
+                pos: self.options.build_bridge_pos.unwrap(),
+            });
+        } else if id == self.build_entrenchment_button_id {
+            self.return_command(context, Command::BuildEntrenchment {
+                pos: self.options.build_entrenchment_pos.unwrap(),
+            });
+        } else if id == self.place_minefield_button_id {
+            self.return_command(context, Command::PlaceMinefield {
+                pos: self.options.place_minefield_pos.unwrap(),
+            });
+        } else if id == self.clear_minefield_button_id {
+            self.return_command(context, Command::ClearMinefield {
+                pos: self.options.clear_minefield_pos.unwrap(),
+            });
         } else if id == self.enable_reaction_fire_button_id {
             self.return_command(context, Command::EnableReactionFire {
                 id: self.options.enable_reaction_fire.unwrap(),
@@ -384,6 +591,20 @@ impl ContextMenuPopup {
             self.return_command(context, Command::DisableReactionFire {
                 id: self.options.disable_reaction_fire.unwrap(),
             });
+        } else if id == self.overwatch_button_id {
+            self.return_command(context, Command::Overwatch {
+                pos: self.options.overwatch_pos.unwrap(),
+            });
+        } else if id == self.ambush_button_id {
+            let (unit_id, distance) = self.options.ambush.unwrap();
+            self.return_command(context, Command::Ambush {
+                id: unit_id,
+                distance: distance,
+            });
+        } else if id == self.dig_in_button_id {
+            self.return_command(context, Command::DigIn {
+                id: self.options.dig_in.unwrap(),
+            });
         } else if id == self.call_reinforcements_button_id {
             self.return_command(context, Command::CallReiforcements {
                 pos: self.options.reinforcements_pos.unwrap(),
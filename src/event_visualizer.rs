@@ -3,7 +3,7 @@ use rand::{thread_rng, Rng};
 use cgmath::{Vector3, Rad};
 use core::partial_state::{PartialState};
 use core::game_state::{GameState};
-use core::{self, UnitInfo, AttackInfo, ReactionFireMode, UnitId, ExactPos, PlayerId, SectorId, MapPos, ObjectId};
+use core::{self, UnitInfo, AttackInfo, ReactionFireMode, UnitId, ExactPos, PlayerId, SectorId, MapPos, ObjectId, Object, ObjectClass, SupportCall, SupportType};
 use core::unit::{UnitTypeId};
 use core::db::{Db};
 use types::{WorldPos, Time};
@@ -219,7 +219,9 @@ impl EventAttackUnitVisualizer {
             let attacker_node_id = scene.unit_id_to_node_id(attacker_id);
             let attacker_pos = scene.node(attacker_node_id).pos;
             let attacker_map_pos = state.unit(attacker_id).pos.map_pos;
-            if attack_info.mode == core::FireMode::Reactive {
+            if attack_info.is_return_fire {
+                map_text.add_text(attacker_map_pos, "return fire");
+            } else if attack_info.mode == core::FireMode::Reactive {
                 map_text.add_text(attacker_map_pos, "reaction fire");
             }
             shell_node_id = Some(scene.add_node(SceneNode {
@@ -325,6 +327,61 @@ impl EventVisualizer for EventAttackUnitVisualizer {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct EventAreaFireVisualizer {
+    shell_move: MoveHelper,
+    shell_node_id: NodeId,
+}
+
+impl EventAreaFireVisualizer {
+    pub fn new(
+        scene: &mut Scene,
+        attacker_id: Option<UnitId>,
+        pos: MapPos,
+        shell_mesh_id: MeshId,
+        map_text: &mut MapTextManager,
+    ) -> Box<EventVisualizer> {
+        let to = geom::map_pos_to_world_pos(pos);
+        let from = match attacker_id {
+            Some(attacker_id) => {
+                let attacker_node_id = scene.unit_id_to_node_id(attacker_id);
+                scene.node(attacker_node_id).pos
+            },
+            // shells of an unseen battery fall from the sky
+            None => WorldPos{v: to.v + vec3_z(geom::HEX_EX_RADIUS * 4.0)},
+        };
+        map_text.add_text(pos, "barrage");
+        let shell_node_id = scene.add_node(SceneNode {
+            pos: from,
+            rot: geom::get_rot_angle(from, to),
+            mesh_id: Some(shell_mesh_id),
+            color: [1.0, 1.0, 1.0, 1.0],
+            children: Vec::new(),
+        });
+        let shell_speed = 10.0;
+        Box::new(EventAreaFireVisualizer {
+            shell_move: MoveHelper::new(from, to, shell_speed),
+            shell_node_id: shell_node_id,
+        })
+    }
+}
+
+impl EventVisualizer for EventAreaFireVisualizer {
+    fn is_finished(&self) -> bool {
+        self.shell_move.is_finished()
+    }
+
+    fn draw(&mut self, scene: &mut Scene, dtime: Time) {
+        let mut pos = self.shell_move.step(dtime);
+        pos.v.z += (self.shell_move.progress() * PI).sin() * 5.0;
+        scene.node_mut(self.shell_node_id).pos = pos;
+    }
+
+    fn end(&mut self, scene: &mut Scene, _: &PartialState) {
+        scene.remove_node(self.shell_node_id);
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct EventShowUnitVisualizer;
 
@@ -492,6 +549,13 @@ impl EventSetReactionFireModeVisualizer {
             ReactionFireMode::HoldFire => {
                 map_text.add_text(unit_pos, "Hold fire");
             },
+            ReactionFireMode::Overwatch{pos} => {
+                map_text.add_text(unit_pos, "Overwatch");
+                map_text.add_text(pos, "Overwatch sector");
+            },
+            ReactionFireMode::Ambush{..} => {
+                map_text.add_text(unit_pos, "Ambush");
+            },
         }
         Box::new(EventSetReactionFireModeVisualizer)
     }
@@ -507,6 +571,90 @@ impl EventVisualizer for EventSetReactionFireModeVisualizer {
     fn end(&mut self, _: &mut Scene, _: &PartialState) {}
 }
 
+#[derive(Clone, Debug)]
+pub struct EventCallSupportVisualizer;
+
+impl EventCallSupportVisualizer {
+    pub fn new(
+        call: &SupportCall,
+        map_text: &mut MapTextManager,
+    ) -> Box<EventVisualizer> {
+        map_text.add_text(call.pos, "support called");
+        Box::new(EventCallSupportVisualizer)
+    }
+}
+
+impl EventVisualizer for EventCallSupportVisualizer {
+    fn is_finished(&self) -> bool {
+        true
+    }
+
+    fn draw(&mut self, _: &mut Scene, _: Time) {}
+
+    fn end(&mut self, _: &mut Scene, _: &PartialState) {}
+}
+
+#[derive(Clone, Debug)]
+pub struct EventSupportStrikeVisualizer {
+    time: Time,
+    duration: Time,
+}
+
+impl EventSupportStrikeVisualizer {
+    pub fn new(
+        call: &SupportCall,
+        map_text: &mut MapTextManager,
+    ) -> Box<EventVisualizer> {
+        let text = match call.support_type {
+            SupportType::Artillery => "artillery barrage",
+            SupportType::AirStrike => "air strike",
+        };
+        map_text.add_text(call.pos, text);
+        Box::new(EventSupportStrikeVisualizer {
+            time: Time{n: 0.0},
+            duration: Time{n: 1.0},
+        })
+    }
+}
+
+impl EventVisualizer for EventSupportStrikeVisualizer {
+    fn is_finished(&self) -> bool {
+        self.time.n >= self.duration.n
+    }
+
+    fn draw(&mut self, _: &mut Scene, dt: Time) {
+        self.time.n += dt.n;
+    }
+
+    fn end(&mut self, _: &mut Scene, _: &PartialState) {}
+}
+
+#[derive(Clone, Debug)]
+pub struct EventDigInVisualizer;
+
+impl EventDigInVisualizer {
+    pub fn new(
+        state: &PartialState,
+        unit_id: UnitId,
+        text: &str,
+        map_text: &mut MapTextManager,
+    ) -> Box<EventVisualizer> {
+        let unit_pos = state.unit(unit_id).pos.map_pos;
+        map_text.add_text(unit_pos, text);
+        Box::new(EventDigInVisualizer)
+    }
+}
+
+impl EventVisualizer for EventDigInVisualizer {
+    fn is_finished(&self) -> bool {
+        true
+    }
+
+    fn draw(&mut self, _: &mut Scene, _: Time) {}
+
+    fn end(&mut self, _: &mut Scene, _: &PartialState) {}
+}
+
 #[derive(Clone, Debug)]
 pub struct EventSectorOwnerChangedVisualizer;
 
@@ -596,6 +744,7 @@ pub struct EventSmokeVisualizer {
 
 impl EventSmokeVisualizer {
     pub fn new(
+        state: &PartialState,
         scene: &mut Scene,
         pos: MapPos,
         _: Option<UnitId>, // TODO
@@ -606,6 +755,10 @@ impl EventSmokeVisualizer {
         // println!("unit_id: {:?}", unit_id); // TODO
         // TODO: show shell animation
         map_text.add_text(pos, "smoke");
+        if state.objects().contains_key(&object_id) {
+            // smoke is refreshed: replace old clouds with new ones
+            scene.remove_object(object_id);
+        }
         let z_step = 0.45; // TODO: magic
         let mut node = SceneNode {
             pos: geom::map_pos_to_world_pos(pos),
@@ -686,3 +839,145 @@ impl EventVisualizer for EventRemoveSmokeVisualizer {
         scene.remove_object(self.object_id);
     }
 }
+
+#[derive(Clone, Debug)]
+pub struct EventRevealObjectVisualizer {
+    time: Time,
+    duration: Time,
+}
+
+impl EventRevealObjectVisualizer {
+    pub fn new(
+        state: &PartialState,
+        scene: &mut Scene,
+        object_id: ObjectId,
+        object: &Object,
+        obstacle_mesh_id: MeshId,
+        map_text: &mut MapTextManager,
+    ) -> Box<EventVisualizer> {
+        let pos = object.pos.map_pos;
+        let text = match object.class {
+            ObjectClass::Minefield => "mines!",
+            _ => "obstacle",
+        };
+        map_text.add_text(pos, text);
+        if !state.objects().contains_key(&object_id) {
+            let mut world_pos = geom::map_pos_to_world_pos(pos);
+            world_pos.v.z += 0.03; // TODO: layers
+            scene.add_object(object_id, SceneNode {
+                pos: world_pos,
+                rot: Rad(0.0),
+                mesh_id: Some(obstacle_mesh_id),
+                color: gen::get_obstacle_color(object.class),
+                children: Vec::new(),
+            });
+        }
+        Box::new(EventRevealObjectVisualizer {
+            time: Time{n: 0.0},
+            duration: Time{n: 1.0},
+        })
+    }
+}
+
+impl EventVisualizer for EventRevealObjectVisualizer {
+    fn is_finished(&self) -> bool {
+        self.time.n >= self.duration.n
+    }
+
+    fn draw(&mut self, _: &mut Scene, dt: Time) {
+        self.time.n += dt.n;
+    }
+
+    fn end(&mut self, _: &mut Scene, _: &PartialState) {}
+}
+
+#[derive(Clone, Debug)]
+pub struct EventConstructVisualizer {
+    time: Time,
+    duration: Time,
+}
+
+impl EventConstructVisualizer {
+    pub fn new(
+        state: &PartialState,
+        scene: &mut Scene,
+        object_id: ObjectId,
+        pos: MapPos,
+        class: ObjectClass,
+        progress: Option<i32>,
+        obstacle_mesh_id: MeshId,
+        map_text: &mut MapTextManager,
+    ) -> Box<EventVisualizer> {
+        if state.objects().get(&object_id).is_none() {
+            let mut world_pos = geom::map_pos_to_world_pos(pos);
+            world_pos.v.z += 0.03; // TODO: layers
+            scene.add_object(object_id, SceneNode {
+                pos: world_pos,
+                rot: Rad(0.0),
+                mesh_id: Some(obstacle_mesh_id),
+                color: gen::get_obstacle_color(class),
+                children: Vec::new(),
+            });
+        }
+        match progress {
+            Some(progress) => {
+                let time = core::construction_time(class);
+                map_text.add_text(pos, &format!("construction {}/{}", progress, time));
+            },
+            None => map_text.add_text(pos, "construction finished"),
+        }
+        Box::new(EventConstructVisualizer {
+            time: Time{n: 0.0},
+            duration: Time{n: 1.0},
+        })
+    }
+}
+
+impl EventVisualizer for EventConstructVisualizer {
+    fn is_finished(&self) -> bool {
+        self.time.n >= self.duration.n
+    }
+
+    fn draw(&mut self, _: &mut Scene, dt: Time) {
+        self.time.n += dt.n;
+    }
+
+    fn end(&mut self, _: &mut Scene, _: &PartialState) {}
+}
+
+#[derive(Clone, Debug)]
+pub struct EventClearMinefieldVisualizer {
+    time: Time,
+    duration: Time,
+    object_id: ObjectId,
+}
+
+impl EventClearMinefieldVisualizer {
+    pub fn new(
+        state: &PartialState,
+        object_id: ObjectId,
+        map_text: &mut MapTextManager,
+    ) -> Box<EventVisualizer> {
+        let pos = state.objects()[&object_id].pos.map_pos;
+        map_text.add_text(pos, "mines cleared");
+        Box::new(EventClearMinefieldVisualizer {
+            time: Time{n: 0.0},
+            duration: Time{n: 1.0},
+            object_id: object_id,
+        })
+    }
+}
+
+impl EventVisualizer for EventClearMinefieldVisualizer {
+    fn is_finished(&self) -> bool {
+        self.time.n >= self.duration.n
+    }
+
+    fn draw(&mut self, _: &mut Scene, dt: Time) {
+        self.time.n += dt.n;
+    }
+
+    fn end(&mut self, scene: &mut Scene, _: &PartialState) {
+        scene.remove_object(self.object_id);
+    }
+}
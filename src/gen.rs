@@ -1,6 +1,6 @@
 use std::path::{Path};
 use cgmath::{Vector2, Array};
-use core::{MapPos, Sector, MovePoints, ExactPos, Command, UnitId, PlayerId};
+use core::{MapPos, Sector, MovePoints, ExactPos, Command, UnitId, PlayerId, ObjectClass};
 use core::db::{Db};
 use core::pathfinder::{Pathfinder};
 use core::map::{Terrain};
@@ -23,6 +23,20 @@ pub fn get_player_color(player_id: PlayerId) -> [f32; 4] {
     }
 }
 
+pub fn get_obstacle_color(class: ObjectClass) -> [f32; 4] {
+    match class {
+        ObjectClass::Minefield => [0.8, 0.1, 0.1, 0.6],
+        ObjectClass::BarbedWire => [0.4, 0.4, 0.4, 0.6],
+        ObjectClass::TankTraps => [0.1, 0.1, 0.1, 0.6],
+        ObjectClass::Bridge => [0.5, 0.35, 0.2, 0.8],
+        ObjectClass::Entrenchment => [0.45, 0.4, 0.25, 0.6],
+        ObjectClass::Building |
+        ObjectClass::Road |
+        ObjectClass::Smoke |
+        ObjectClass::ReinforcementSector => panic!("Not an obstacle: {:?}", class),
+    }
+}
+
 pub fn generate_tiles_mesh<I: IntoIterator<Item=MapPos>>(
     context: &mut Context,
     tex: Texture,
@@ -1,3 +1,4 @@
+use std::collections::{HashMap};
 use cgmath::{Vector2};
 use glutin::{self, Event, MouseButton, VirtualKeyCode};
 use glutin::ElementState::{Released};
@@ -74,6 +75,9 @@ impl MainMenuScreen {
             let core_options = core::Options {
                 game_type: core::GameType::Hotseat,
                 map_name: map_name.to_string(),
+                ai_profiles: HashMap::new(),
+                bots: HashMap::new(),
+                bot_commands: HashMap::new(),
             };
             let tactical_screen = Box::new(
                 TacticalScreen::new(context, &core_options));
@@ -82,6 +86,9 @@ impl MainMenuScreen {
             let core_options = core::Options {
                 game_type: core::GameType::SingleVsAi,
                 map_name: map_name.to_string(),
+                ai_profiles: HashMap::new(),
+                bots: HashMap::new(),
+                bot_commands: HashMap::new(),
             };
             let tactical_screen = Box::new(
                 TacticalScreen::new(context, &core_options));
@@ -7,7 +7,7 @@ use core::game_state::{GameState};
 use context::{Context};
 use geom;
 use camera::Camera;
-use types::{WorldPos};
+use types::{WorldPos, ScreenPos};
 
 pub fn pick_world_pos(context: &Context, camera: &Camera) -> WorldPos {
     let im = camera.mat().invert()
@@ -29,6 +29,22 @@ pub fn pick_world_pos(context: &Context, camera: &Camera) -> WorldPos {
     WorldPos{v: intersection_pos.to_vec()}
 }
 
+/// Projects a world position onto the screen, uses the mouse coordinates
+pub fn world_pos_to_screen_pos(
+    context: &Context,
+    camera: &Camera,
+    pos: WorldPos,
+) -> ScreenPos {
+    let p_raw = camera.mat() * pos.v.extend(1.0);
+    let p = p_raw.truncate() / p_raw.w;
+    let w = context.win_size.w as f32;
+    let h = context.win_size.h as f32;
+    ScreenPos{v: cgmath::Vector2 {
+        x: ((p.x + 1.0) * w / 2.0) as i32,
+        y: ((1.0 - p.y) * h / 2.0) as i32,
+    }}
+}
+
 pub fn pick_tile(
     context: &Context,
     state: &PartialState,
@@ -13,11 +13,19 @@ use mesh::{MeshId};
 use mesh::{Mesh};
 use pipeline::{Vertex};
 
+const GROUP_MARKER_COLOR: [f32; 4] = [0.4, 1.0, 0.4, 1.0];
+
+fn marker_pos(state: &PartialState, unit_id: UnitId) -> WorldPos {
+    let map_pos = state.units()[&unit_id].pos;
+    WorldPos{v: geom::lift(geom::exact_pos_to_world_pos(state, map_pos).v)}
+}
+
 #[derive(Clone, Debug)]
 pub struct SelectionManager {
     unit_id: Option<UnitId>,
     mesh_id: MeshId,
     selection_marker_node_id: Option<NodeId>,
+    group_marker_node_ids: Vec<NodeId>,
 }
 
 impl SelectionManager {
@@ -26,14 +34,14 @@ impl SelectionManager {
             unit_id: None,
             mesh_id: mesh_id,
             selection_marker_node_id: None,
+            group_marker_node_ids: Vec::new(),
         }
     }
 
     fn get_pos(&self, state: &PartialState) -> WorldPos {
         let unit_id = self.unit_id
             .expect("Can`t get pos if no unit is selected");
-        let map_pos = state.units()[&unit_id].pos;
-        WorldPos{v: geom::lift(geom::exact_pos_to_world_pos(state, map_pos).v)}
+        marker_pos(state, unit_id)
     }
 
     pub fn create_selection_marker(
@@ -65,6 +73,35 @@ impl SelectionManager {
         }
         self.selection_marker_node_id = None;
     }
+
+    /// Group markers are lifted above the selection marker,
+    /// so a selected member of the group shows both
+    pub fn mark_group(
+        &mut self,
+        state: &PartialState,
+        scene: &mut Scene,
+        unit_ids: &[UnitId],
+    ) {
+        self.unmark_group(scene);
+        for &unit_id in unit_ids {
+            let node = SceneNode {
+                pos: WorldPos{v: geom::lift(marker_pos(state, unit_id).v)},
+                rot: Rad(0.0),
+                mesh_id: Some(self.mesh_id),
+                color: GROUP_MARKER_COLOR,
+                children: Vec::new(),
+            };
+            self.group_marker_node_ids.push(scene.add_node(node));
+        }
+    }
+
+    pub fn unmark_group(&mut self, scene: &mut Scene) {
+        for node_id in self.group_marker_node_ids.drain(..) {
+            if scene.nodes().get(&node_id).is_some() {
+                scene.remove_node(node_id);
+            }
+        }
+    }
 }
 
 pub fn get_selection_mesh(context: &mut Context) -> Mesh {
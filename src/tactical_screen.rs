@@ -12,6 +12,7 @@ use core::partial_state::{PartialState};
 use core::game_state::{GameState, GameStateMut};
 use core::{self, CoreEvent, Command, UnitId, PlayerId, MapPos, ExactPos, SlotId};
 use core::db::{Db};
+use core::info;
 use core::unit::{UnitTypeId};
 use obj;
 use gui::{ButtonManager, Button, ButtonId, is_tap};
@@ -66,7 +67,8 @@ fn score_text(state: &PartialState) -> String {
 fn reinforcement_points_text(state: &PartialState, player_id: PlayerId) -> String {
     let rp = state.reinforcement_points()[&player_id];
     let rp_per_turn = 10; // TODO: magic num
-    format!("reinforcements: {} (+{})", rp, rp_per_turn)
+    let sp = state.support_points()[&player_id];
+    format!("reinforcements: {} (+{}), support: {}", rp, rp_per_turn, sp)
 }
 
 fn load_object_mesh(context: &mut Context, name: &str) -> Mesh {
@@ -127,6 +129,7 @@ struct MeshIdManager {
     smoke_mesh_id: MeshId,
     fow_tile_mesh_id: MeshId,
     reinforcement_sector_tile_mesh_id: MeshId,
+    obstacle_tile_mesh_id: MeshId,
     sector_mesh_ids: HashMap<core::SectorId, MeshId>,
 }
 
@@ -153,7 +156,8 @@ impl MeshIdManager {
         }
         let selection_marker_mesh_id = meshes.add(get_selection_mesh(context));
         let smoke_mesh_id = meshes.add(gen::get_one_tile_mesh(context, smoke_tex));
-        let fow_tile_mesh_id = meshes.add(gen::get_one_tile_mesh(context, floor_tex));
+        let fow_tile_mesh_id = meshes.add(gen::get_one_tile_mesh(context, floor_tex.clone()));
+        let obstacle_tile_mesh_id = meshes.add(gen::get_one_tile_mesh(context, floor_tex));
         let reinforcement_sector_tile_mesh_id = meshes.add(
             gen::get_one_tile_mesh(context, reinforcement_sector_tex));
         let big_building_mesh_id = meshes.add(
@@ -187,6 +191,7 @@ impl MeshIdManager {
             smoke_mesh_id: smoke_mesh_id,
             fow_tile_mesh_id: fow_tile_mesh_id,
             reinforcement_sector_tile_mesh_id: reinforcement_sector_tile_mesh_id,
+            obstacle_tile_mesh_id: obstacle_tile_mesh_id,
             sector_mesh_ids: sector_mesh_ids,
         }
     }
@@ -203,6 +208,7 @@ fn get_unit_type_visual_info(
         ("smg", "submachine", 2.0),
         ("scout", "scout", 2.5),
         ("mortar", "mortar", 1.5),
+        ("engineer", "soldier", 2.0),
         ("field_gun", "field_gun", 1.5),
         ("light_spg", "light_spg", 3.0),
         ("light_tank", "light_tank", 3.0),
@@ -231,6 +237,7 @@ pub struct Gui {
     button_zoom_in_id: ButtonId,
     button_zoom_out_id: ButtonId,
     label_unit_info_id: Option<ButtonId>,
+    info_panel_ids: Vec<ButtonId>,
     label_score_id: ButtonId,
     label_reinforcement_points_id: ButtonId,
 }
@@ -292,6 +299,7 @@ impl Gui {
             button_zoom_in_id: button_zoom_in_id,
             button_zoom_out_id: button_zoom_out_id,
             label_unit_info_id: None,
+            info_panel_ids: Vec::new(),
             label_score_id: label_score_id,
             label_reinforcement_points_id: label_reinforcement_points_id,
         }
@@ -403,6 +411,21 @@ fn make_scene(state: &PartialState, mesh_ids: &MeshIdManager) -> Scene {
                     children: Vec::new(),
                 });
             }
+            core::ObjectClass::Minefield |
+            core::ObjectClass::BarbedWire |
+            core::ObjectClass::TankTraps |
+            core::ObjectClass::Bridge |
+            core::ObjectClass::Entrenchment => {
+                let mut pos = geom::map_pos_to_world_pos(object.pos.map_pos);
+                pos.v.z += 0.03; // TODO: layers
+                scene.add_object(object_id, SceneNode {
+                    pos: pos,
+                    rot: Rad(0.0),
+                    mesh_id: Some(mesh_ids.obstacle_tile_mesh_id),
+                    color: gen::get_obstacle_color(object.class),
+                    children: Vec::new(),
+                });
+            }
             core::ObjectClass::Smoke => unimplemented!(),
         }
     }
@@ -420,6 +443,7 @@ pub struct TacticalScreen {
     meshes: MeshManager,
     unit_type_visual_info: UnitTypeVisualInfoManager,
     selected_unit_id: Option<UnitId>,
+    group_unit_ids: Vec<UnitId>,
     selection_manager: SelectionManager,
     context_menu_popup_rx: Option<Receiver<context_menu_popup::Command>>,
     reinforcements_popup_rx: Option<Receiver<(UnitTypeId, ExactPos)>>,
@@ -427,7 +451,7 @@ pub struct TacticalScreen {
 
 impl TacticalScreen {
     pub fn new(context: &mut Context, core_options: &core::Options) -> TacticalScreen {
-        let core = core::Core::new(core_options);
+        let core = core::Core::new(core_options).expect("Can`t start the bots");
         let mut player_info = PlayerInfoManager::new(context, core_options);
         let mut meshes = MeshManager::new();
         let mesh_ids = MeshIdManager::new(
@@ -453,6 +477,7 @@ impl TacticalScreen {
             meshes: meshes,
             unit_type_visual_info: unit_type_visual_info,
             selected_unit_id: None,
+            group_unit_ids: Vec::new(),
             selection_manager: selection_manager,
             map_text_manager: map_text_manager,
             context_menu_popup_rx: None,
@@ -488,6 +513,7 @@ impl TacticalScreen {
             let screen = Box::new(EndTurnScreen::new(context, next_id));
             context.add_command(ScreenCommand::PushScreen(screen));
         }
+        self.group_unit_ids.clear();
         self.deselect_unit(context);
         self.core.do_command(Command::EndTurn);
         self.regenerate_fow();
@@ -572,6 +598,7 @@ impl TacticalScreen {
     fn hide_selected_unit_meshes(&mut self, context: &mut Context) {
         let scene = &mut self.player_info.get_mut(self.core.player_id()).scene;
         self.selection_manager.deselect(scene);
+        self.selection_manager.unmark_group(scene);
         self.meshes.set(self.mesh_ids.walkable_mesh_id, gen::empty_mesh(context));
         self.meshes.set(self.mesh_ids.targets_mesh_id, gen::empty_mesh(context));
     }
@@ -580,8 +607,19 @@ impl TacticalScreen {
         if let Some(label_id) = self.gui.label_unit_info_id.take() {
             self.gui.button_manager.remove_button(label_id);
         }
+        self.hide_info_panel();
         self.selected_unit_id = None;
         self.hide_selected_unit_meshes(context);
+        self.update_group_markers();
+    }
+
+    /// Marks every unit of the group that is still alive
+    fn update_group_markers(&mut self) {
+        let player_info = self.player_info.get_mut(self.core.player_id());
+        let state = &player_info.game_state;
+        self.group_unit_ids.retain(|id| state.units().get(id).is_some());
+        self.selection_manager.mark_group(
+            state, &mut player_info.scene, &self.group_unit_ids);
     }
 
     fn current_state(&self) -> &PartialState {
@@ -687,6 +725,39 @@ impl TacticalScreen {
         });
     }
 
+    fn toggle_group_member(&mut self) {
+        let unit_id = match self.selected_unit_id {
+            Some(id) => id,
+            None => return,
+        };
+        if self.group_unit_ids.contains(&unit_id) {
+            self.group_unit_ids.retain(|&id| id != unit_id);
+        } else {
+            self.group_unit_ids.push(unit_id);
+        }
+        self.update_group_markers();
+    }
+
+    fn move_group(&mut self, context: &mut Context) {
+        let pos = match self.pick_tile(context) {
+            Some(pos) => pos,
+            None => return,
+        };
+        {
+            let state = &self.player_info.get(self.core.player_id()).game_state;
+            self.group_unit_ids.retain(|id| state.units().get(id).is_some());
+        }
+        if self.group_unit_ids.is_empty() {
+            return;
+        }
+        self.deselect_unit(context);
+        self.core.do_command(Command::GroupMove {
+            unit_ids: self.group_unit_ids.clone(),
+            pos: pos,
+            mode: core::MoveMode::Fast,
+        });
+    }
+
     fn handle_camera_move(&mut self, context: &Context, pos: ScreenPos) {
         let diff = pos.v - context.mouse().pos.v;
         let camera_move_speed = geom::HEX_EX_RADIUS * 12.0;
@@ -741,16 +812,48 @@ impl TacticalScreen {
         }
     }
 
-    fn print_info(&mut self, context: &Context) {
-        // TODO: move this to `fn Core::get_unit_info(...) -> &str`?
-        let pick_result = self.pick_tile(context);
-        if let Some(pos) = pick_result {
-            core::print_terrain_info(self.current_state(), pos);
-            println!("");
-            for unit in self.current_state().units_at(pos) {
-                core::print_unit_info(self.core.db(), unit);
-                println!("");
+    fn hide_info_panel(&mut self) {
+        for label_id in self.gui.info_panel_ids.drain(..) {
+            self.gui.button_manager.remove_button(label_id);
+        }
+    }
+
+    fn show_info_panel(&mut self, context: &mut Context) {
+        self.hide_info_panel();
+        let pos = match self.selected_unit_id {
+            Some(unit_id) => self.current_state().unit(unit_id).pos.map_pos,
+            None => match self.pick_tile(context) {
+                Some(pos) => pos,
+                None => return,
+            },
+        };
+        let text = {
+            let state = self.current_state();
+            let mut text = info::describe_terrain(state, pos).to_string();
+            for unit in state.units_at(pos) {
+                let description = info::describe_unit(self.core.db(), state, unit);
+                text += &format!("\n{}", description);
             }
+            text
+        };
+        // the panel is placed right next to the inspected tile
+        let tile_pos = pick::world_pos_to_screen_pos(
+            context,
+            &self.current_player_info().camera,
+            geom::map_pos_to_world_pos(pos),
+        );
+        let mut label_pos = ScreenPos{v: Vector2 {
+            x: tile_pos.v.x + 20,
+            y: context.win_size.h - tile_pos.v.y,
+        }};
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let mut label = Button::new_small(context, line, label_pos);
+            label_pos.v.y -= label.size().h;
+            let mut pos = label.pos();
+            pos.v.y = label_pos.v.y;
+            label.set_pos(pos);
+            let label_id = self.gui.button_manager.add_button(label);
+            self.gui.info_panel_ids.push(label_id);
         }
     }
 
@@ -774,7 +877,20 @@ impl TacticalScreen {
                 self.current_player_info_mut().camera.move_in_direction(Rad(PI * 1.0), s);
             },
             VirtualKeyCode::I => {
-                self.print_info(context);
+                self.show_info_panel(context);
+            },
+            VirtualKeyCode::U => {
+                if self.event_visualizer.is_none() && self.core.can_undo() {
+                    self.core.do_command(Command::Undo);
+                }
+            },
+            VirtualKeyCode::G => {
+                self.toggle_group_member();
+            },
+            VirtualKeyCode::M => {
+                if self.event_visualizer.is_none() {
+                    self.move_group(context);
+                }
             },
             VirtualKeyCode::Subtract | VirtualKeyCode::Key1 => {
                 self.current_player_info_mut().camera.change_zoom(1.3);
@@ -909,6 +1025,17 @@ impl TacticalScreen {
                     to,
                 )
             },
+            CoreEvent::UndoMove{unit_id, to, ..} => {
+                let type_id = state.unit(unit_id).type_id;
+                let visual_info = self.unit_type_visual_info.get(type_id);
+                event_visualizer::EventMoveVisualizer::new(
+                    state,
+                    scene,
+                    unit_id,
+                    visual_info,
+                    to,
+                )
+            },
             CoreEvent::EndTurn{..} => {
                 event_visualizer::EventEndTurnVisualizer::new()
             },
@@ -933,6 +1060,15 @@ impl TacticalScreen {
                     &mut self.map_text_manager,
                 )
             },
+            CoreEvent::AreaFire{attacker_id, pos} => {
+                event_visualizer::EventAreaFireVisualizer::new(
+                    scene,
+                    attacker_id,
+                    pos,
+                    self.mesh_ids.shell_mesh_id,
+                    &mut self.map_text_manager,
+                )
+            },
             CoreEvent::ShowUnit{ref unit_info, ..} => {
                 let mesh_id = self.unit_type_visual_info
                     .get(unit_info.type_id).mesh_id;
@@ -992,6 +1128,34 @@ impl TacticalScreen {
                     &mut self.map_text_manager,
                 )
             },
+            CoreEvent::CallSupport{ref call} => {
+                event_visualizer::EventCallSupportVisualizer::new(
+                    call,
+                    &mut self.map_text_manager,
+                )
+            },
+            CoreEvent::SupportStrike{ref call} => {
+                event_visualizer::EventSupportStrikeVisualizer::new(
+                    call,
+                    &mut self.map_text_manager,
+                )
+            },
+            CoreEvent::DigIn{unit_id} => {
+                event_visualizer::EventDigInVisualizer::new(
+                    state,
+                    unit_id,
+                    "Digging in",
+                    &mut self.map_text_manager,
+                )
+            },
+            CoreEvent::Entrench{unit_id} => {
+                event_visualizer::EventDigInVisualizer::new(
+                    state,
+                    unit_id,
+                    "Dug in",
+                    &mut self.map_text_manager,
+                )
+            },
             CoreEvent::SectorOwnerChanged{sector_id, new_owner_id} => {
                 event_visualizer::EventSectorOwnerChangedVisualizer::new(
                     scene,
@@ -1010,6 +1174,7 @@ impl TacticalScreen {
             }
             CoreEvent::Smoke{pos, unit_id, id} => {
                 event_visualizer::EventSmokeVisualizer::new(
+                    state,
                     scene,
                     pos,
                     unit_id,
@@ -1025,6 +1190,35 @@ impl TacticalScreen {
                     &mut self.map_text_manager,
                 )
             }
+            CoreEvent::RevealObject{id, ref object} => {
+                event_visualizer::EventRevealObjectVisualizer::new(
+                    state,
+                    scene,
+                    id,
+                    object,
+                    self.mesh_ids.obstacle_tile_mesh_id,
+                    &mut self.map_text_manager,
+                )
+            }
+            CoreEvent::Construct{id, pos, class, progress, ..} => {
+                event_visualizer::EventConstructVisualizer::new(
+                    state,
+                    scene,
+                    id,
+                    pos,
+                    class,
+                    progress,
+                    self.mesh_ids.obstacle_tile_mesh_id,
+                    &mut self.map_text_manager,
+                )
+            }
+            CoreEvent::ClearMinefield{id, ..} => {
+                event_visualizer::EventClearMinefieldVisualizer::new(
+                    state,
+                    id,
+                    &mut self.map_text_manager,
+                )
+            }
         }
     }
 
@@ -1160,8 +1354,12 @@ impl TacticalScreen {
         self.event = None;
         if let Some(event) = self.core.get_event() {
             self.start_event_visualization(context, event);
-        } else if let Some(unit_id) = self.selected_unit_id {
-            self.select_unit(context, unit_id);
+        } else {
+            if let Some(unit_id) = self.selected_unit_id {
+                self.select_unit(context, unit_id);
+            }
+            // the group members may have moved or died
+            self.update_group_markers();
         }
     }
 
@@ -1201,6 +1399,13 @@ impl TacticalScreen {
                     defender_id: id,
                 });
             },
+            context_menu_popup::Command::AreaFire{pos} => {
+                let selected_unit_id = self.selected_unit_id.unwrap();
+                self.core.do_command(Command::AreaFire {
+                    unit_id: selected_unit_id,
+                    pos: pos,
+                });
+            },
             context_menu_popup::Command::LoadUnit{passenger_id} => {
                 let selected_unit_id = self.selected_unit_id.unwrap();
                 self.core.do_command(Command::LoadUnit {
@@ -1233,6 +1438,22 @@ impl TacticalScreen {
                     mode: core::ReactionFireMode::HoldFire,
                 });
             },
+            context_menu_popup::Command::Overwatch{pos} => {
+                let selected_unit_id = self.selected_unit_id.unwrap();
+                self.core.do_command(Command::SetReactionFireMode {
+                    unit_id: selected_unit_id,
+                    mode: core::ReactionFireMode::Overwatch{pos: pos},
+                });
+            },
+            context_menu_popup::Command::Ambush{id, distance} => {
+                self.core.do_command(Command::SetReactionFireMode {
+                    unit_id: id,
+                    mode: core::ReactionFireMode::Ambush{distance: distance},
+                });
+            },
+            context_menu_popup::Command::DigIn{id} => {
+                self.core.do_command(Command::DigIn{unit_id: id});
+            },
             context_menu_popup::Command::Smoke{pos} => {
                 let selected_unit_id = self.selected_unit_id.unwrap();
                 self.core.do_command(Command::Smoke {
@@ -1240,6 +1461,40 @@ impl TacticalScreen {
                     pos: pos,
                 });
             },
+            context_menu_popup::Command::BuildBridge{pos} => {
+                let selected_unit_id = self.selected_unit_id.unwrap();
+                self.core.do_command(Command::BuildBridge {
+                    unit_id: selected_unit_id,
+                    pos: pos,
+                });
+            },
+            context_menu_popup::Command::BuildEntrenchment{pos} => {
+                let selected_unit_id = self.selected_unit_id.unwrap();
+                self.core.do_command(Command::BuildEntrenchment {
+                    unit_id: selected_unit_id,
+                    pos: pos,
+                });
+            },
+            context_menu_popup::Command::PlaceMinefield{pos} => {
+                let selected_unit_id = self.selected_unit_id.unwrap();
+                self.core.do_command(Command::PlaceMinefield {
+                    unit_id: selected_unit_id,
+                    pos: pos,
+                });
+            },
+            context_menu_popup::Command::ClearMinefield{pos} => {
+                let selected_unit_id = self.selected_unit_id.unwrap();
+                self.core.do_command(Command::ClearMinefield {
+                    unit_id: selected_unit_id,
+                    pos: pos,
+                });
+            },
+            context_menu_popup::Command::CallSupport{pos, support_type} => {
+                self.core.do_command(Command::CallSupport {
+                    pos: pos,
+                    support_type: support_type,
+                });
+            },
             context_menu_popup::Command::CallReiforcements{pos} => {
                 self.show_reinforcements_menu(context, pos);
             },